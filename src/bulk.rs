@@ -0,0 +1,618 @@
+use std::sync::{Condvar, Mutex, mpsc};
+use std::time::Duration;
+
+use colored::Colorize;
+use console::Term;
+
+use crate::config::Config;
+use crate::health;
+use crate::persist::{self, AutoStart};
+use crate::process;
+use crate::ssh_config::ConfigRoot;
+use crate::tunnel::{Endpoint, TunnelHost};
+
+/// What `up`, `down` or `restart` with `--all`/`--group` does to each tunnel.
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    Up { persist: bool },
+    Down,
+    Restart,
+}
+
+/// How one tunnel fared.
+#[derive(Debug)]
+pub enum Outcome {
+    Started {
+        pid: u32,
+        /// None for remote-only tunnels, which can't be probed locally.
+        health: Option<Result<(), String>>,
+        /// Set when `--persist` couldn't enable auto-start.
+        warning: Option<String>,
+    },
+    Restarted {
+        pid: u32,
+        health: Option<Result<(), String>>,
+    },
+    Stopped,
+    Failed(String),
+}
+
+impl Outcome {
+    /// The result line for tunnel `name`, as the single-tunnel commands print it.
+    pub fn line(&self, name: &str) -> String {
+        match self {
+            Outcome::Started {
+                pid,
+                health,
+                warning,
+            } => {
+                let mut line = started_line(name, "started", *pid, health.as_ref());
+                if let Some(w) = warning {
+                    line.push_str(&format!(" — {} auto-start not enabled: {}", "⚠".yellow(), w));
+                }
+                line
+            }
+            Outcome::Restarted { pid, health } => started_line(name, "restarted", *pid, health.as_ref()),
+            Outcome::Stopped => format!("{} {} {}", "○".dimmed(), name.bold(), "stopped".dimmed()),
+            Outcome::Failed(e) => format!("{} {} — {}", "✗".red(), name.red().bold(), e),
+        }
+    }
+
+    /// Whether tunnels waiting on this one can go ahead: it started and
+    /// answered its health check, or (for `down`) it stopped.
+    fn is_ready(&self) -> bool {
+        match self {
+            Outcome::Started { health, .. } | Outcome::Restarted { health, .. } => {
+                !matches!(health, Some(Err(_)))
+            }
+            Outcome::Stopped => true,
+            Outcome::Failed(_) => false,
+        }
+    }
+
+    fn verb(&self) -> &'static str {
+        match self {
+            Outcome::Started { .. } => "started",
+            Outcome::Restarted { .. } => "restarted",
+            Outcome::Stopped => "stopped",
+            Outcome::Failed(_) => "failed",
+        }
+    }
+}
+
+fn started_line(name: &str, verb: &str, pid: u32, health: Option<&Result<(), String>>) -> String {
+    let line = format!("{} {} {} (pid {})", "●".green(), name.green().bold(), verb.green(), pid);
+    match health {
+        None => line,
+        Some(Ok(())) => format!("{} — {} healthy", line, "✓".green()),
+        Some(Err(failure)) => format!("{} — {} not reachable yet ({})", line, "✗".yellow(), failure),
+    }
+}
+
+/// Wait up to `health_timeout` for a just-started tunnel's local listeners.
+pub fn wait_health(tunnel: &TunnelHost, cfg: &Config) -> Option<Result<(), String>> {
+    let listeners: Vec<&Endpoint> = tunnel.local_listeners().collect();
+    if listeners.is_empty() {
+        return None;
+    }
+    Some(health::wait_healthy(&listeners, Duration::from_secs(cfg.health_timeout)))
+}
+
+/// Apply `action` to one tunnel, calling `phase` as it moves along.
+fn perform(
+    action: Action,
+    tunnel: &TunnelHost,
+    root: &ConfigRoot,
+    cfg: &Config,
+    phase: impl Fn(&'static str),
+) -> Outcome {
+    let result = (|| -> anyhow::Result<Outcome> {
+        let was_active = matches!(action, Action::Down | Action::Restart) && process::is_active(&tunnel.name)?;
+        if was_active {
+            phase("stopping…");
+            process::stop_tunnel(tunnel, cfg)?;
+        }
+        if let Action::Down = action {
+            return Ok(Outcome::Stopped);
+        }
+
+        // Enable before starting, so a service manager starts the tunnel
+        // itself instead of racing mole's copy
+        let warning = match action {
+            Action::Up { persist: true } => persist::chosen(cfg)
+                .and_then(|backend| enable(backend, tunnel, root, cfg))
+                .err()
+                .map(|e| e.to_string()),
+            _ => None,
+        };
+        phase("starting…");
+        let pid = process::start_tunnel(tunnel, root, cfg)?;
+        phase("checking health…");
+        let health = wait_health(tunnel, cfg);
+        Ok(match action {
+            Action::Up { .. } => Outcome::Started { pid, health, warning },
+            _ if was_active => Outcome::Restarted { pid, health },
+            // A dependency `restart` had to bring up
+            _ => Outcome::Started {
+                pid,
+                health,
+                warning: None,
+            },
+        })
+    })();
+    result.unwrap_or_else(|e| Outcome::Failed(e.to_string()))
+}
+
+/// Held while a worker enables auto-start. Backends rewrite one shared file
+/// (the crontab) or reload the service manager, so they take turns.
+static ENABLING: Mutex<()> = Mutex::new(());
+
+fn enable(
+    backend: &'static dyn AutoStart,
+    tunnel: &TunnelHost,
+    root: &ConfigRoot,
+    cfg: &Config,
+) -> anyhow::Result<()> {
+    let _turn = ENABLING.lock().unwrap();
+    persist::enable_with(backend, tunnel, root, cfg).map(|_| ())
+}
+
+enum Progress {
+    Phase(usize, &'static str),
+    Done(usize, Outcome),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Slot {
+    Waiting,
+    Running,
+    /// Finished; true when tunnels waiting on it may proceed.
+    Done(bool),
+}
+
+enum Next {
+    Run(usize),
+    /// Can't run: the tunnel it waits on (by index) didn't come through.
+    Blocked(usize, usize),
+    /// Nothing runnable until a running tunnel finishes.
+    Wait,
+    Finished,
+}
+
+/// Which tunnels may run, given that tunnel `i` waits for every tunnel in `waits[i]`.
+struct Queue {
+    slots: Vec<Slot>,
+    waits: Vec<Vec<usize>>,
+}
+
+impl Queue {
+    fn next(&mut self) -> Next {
+        let mut waiting = false;
+        for i in 0..self.slots.len() {
+            if self.slots[i] != Slot::Waiting {
+                continue;
+            }
+            waiting = true;
+            if let Some(&dep) = self.waits[i].iter().find(|&&d| self.slots[d] == Slot::Done(false)) {
+                self.slots[i] = Slot::Done(false);
+                return Next::Blocked(i, dep);
+            }
+            if self.waits[i].iter().all(|&d| self.slots[d] == Slot::Done(true)) {
+                self.slots[i] = Slot::Running;
+                return Next::Run(i);
+            }
+        }
+        if !waiting {
+            Next::Finished
+        } else if self.slots.contains(&Slot::Running) {
+            Next::Wait
+        } else {
+            // Only a cycle leaves tunnels waiting with nothing running; callers
+            // reject those up front, but don't hang if one slips through
+            let i = self.slots.iter().position(|s| *s == Slot::Waiting).unwrap_or(0);
+            self.slots[i] = Slot::Done(false);
+            Next::Blocked(i, i)
+        }
+    }
+}
+
+/// For each tunnel, the tunnels in the list it has to wait for: its
+/// dependencies when starting, its dependents when stopping.
+fn waits(action: Action, tunnels: &[&TunnelHost]) -> Vec<Vec<usize>> {
+    let depends_on = |i: usize, j: usize| tunnels[i].depends.iter().any(|d| tunnels[j].has_name(d));
+    (0..tunnels.len())
+        .map(|i| {
+            (0..tunnels.len())
+                .filter(|&j| j != i)
+                .filter(|&j| match action {
+                    Action::Down => depends_on(j, i),
+                    _ => depends_on(i, j),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Apply `action` to every tunnel, `max_parallel` at a time. Tunnels start
+/// only once the dependencies among them are up and healthy, and stop only
+/// once their dependents have stopped. On a terminal each
+/// tunnel has a line that shows its progress and then its result; elsewhere
+/// result lines print as tunnels finish. Ends with a summary and returns the
+/// number of tunnels that failed.
+pub fn run(action: Action, tunnels: &[&TunnelHost], root: &ConfigRoot, cfg: &Config) -> usize {
+    let term = Term::stdout();
+    // Lines can only be redrawn while they are all still on screen
+    let live = term.is_term() && tunnels.len() < term.size().0 as usize;
+    if live {
+        for t in tunnels {
+            let _ = term.write_line(&format!("{} {} {}", "·".dimmed(), t.name, "waiting".dimmed()));
+        }
+    }
+
+    let workers = cfg.max_parallel.clamp(1, tunnels.len().max(1));
+    let mut outcomes = Vec::new();
+    let act = |i: usize, phase: &dyn Fn(&'static str)| perform(action, tunnels[i], root, cfg, phase);
+    schedule(action, tunnels, workers, act, |progress| {
+        let (i, line) = match progress {
+            Progress::Phase(i, phase) => {
+                let name = &tunnels[i].name;
+                (i, format!("{} {} {}", "◐".cyan(), name.bold(), phase.dimmed()))
+            }
+            Progress::Done(i, outcome) => {
+                let line = outcome.line(&tunnels[i].name);
+                if !live {
+                    println!("{}", line);
+                }
+                outcomes.push(outcome);
+                (i, line)
+            }
+        };
+        if live {
+            let up = tunnels.len() - i;
+            let _ = term.move_cursor_up(up);
+            let _ = term.clear_line();
+            let _ = term.write_line(&line);
+            if up > 1 {
+                let _ = term.move_cursor_down(up - 1);
+            }
+        }
+    });
+
+    print_summary(&outcomes)
+}
+
+/// Call `act` for every tunnel on `workers` threads, each tunnel once the
+/// ones it waits for are done, and pass progress to `report` as it comes.
+fn schedule(
+    action: Action,
+    tunnels: &[&TunnelHost],
+    workers: usize,
+    act: impl Fn(usize, &dyn Fn(&'static str)) -> Outcome + Sync,
+    mut report: impl FnMut(Progress),
+) {
+    let queue = Mutex::new(Queue {
+        slots: vec![Slot::Waiting; tunnels.len()],
+        waits: waits(action, tunnels),
+    });
+    let changed = Condvar::new();
+    let (tx, rx) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let (tx, queue, changed, act) = (tx.clone(), &queue, &changed, &act);
+            scope.spawn(move || {
+                let mut q = queue.lock().unwrap();
+                loop {
+                    match q.next() {
+                        Next::Finished => break,
+                        Next::Wait => q = changed.wait(q).unwrap(),
+                        Next::Blocked(i, dep) => {
+                            let reason = match action {
+                                _ if dep == i => "not run: dependency cycle".to_string(),
+                                Action::Down => format!("not stopped: dependent '{}' is still up", tunnels[dep].name),
+                                _ => format!("not started: dependency '{}' did not come up", tunnels[dep].name),
+                            };
+                            let _ = tx.send(Progress::Done(i, Outcome::Failed(reason)));
+                            changed.notify_all();
+                        }
+                        Next::Run(i) => {
+                            drop(q);
+                            let phase = |phase| {
+                                let _ = tx.send(Progress::Phase(i, phase));
+                            };
+                            let outcome = act(i, &phase);
+                            q = queue.lock().unwrap();
+                            q.slots[i] = Slot::Done(outcome.is_ready());
+                            let _ = tx.send(Progress::Done(i, outcome));
+                            changed.notify_all();
+                        }
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for progress in rx {
+            report(progress);
+        }
+    });
+}
+
+/// "✓ 4 started" or "✗ 3 started, 1 failed"; returns the number failed.
+fn print_summary(outcomes: &[Outcome]) -> usize {
+    let (summary, failed) = summarize(outcomes);
+    let icon = if failed == 0 { "✓".green() } else { "✗".red() };
+    println!();
+    println!("  {} {}", icon, summary);
+    failed
+}
+
+/// Count outcomes by kind, failures last: ("3 started, 1 failed", 1).
+fn summarize(outcomes: &[Outcome]) -> (String, usize) {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for outcome in outcomes {
+        match counts.iter_mut().find(|(verb, _)| *verb == outcome.verb()) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((outcome.verb(), 1)),
+        }
+    }
+    counts.sort_by_key(|(verb, _)| *verb == "failed");
+    let failed = counts.iter().find(|(verb, _)| *verb == "failed").map_or(0, |(_, n)| *n);
+    let parts: Vec<String> = counts.iter().map(|(verb, n)| format!("{} {}", n, verb)).collect();
+    (parts.join(", "), failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn started() -> Outcome {
+        Outcome::Started {
+            pid: 1,
+            health: None,
+            warning: None,
+        }
+    }
+
+    /// db ← api ← web: api depends on db, web on api.
+    fn chain() -> Vec<TunnelHost> {
+        vec![
+            TunnelHost::named("db"),
+            TunnelHost {
+                depends: vec!["db".to_string()],
+                ..TunnelHost::named("api")
+            },
+            TunnelHost {
+                depends: vec!["api".to_string()],
+                ..TunnelHost::named("web")
+            },
+        ]
+    }
+
+    /// Run `schedule`, returning the tunnels' names in the order they
+    /// finished and their outcomes.
+    fn finish_order(
+        action: Action,
+        tunnels: &[&TunnelHost],
+        workers: usize,
+        act: impl Fn(usize, &dyn Fn(&'static str)) -> Outcome + Sync,
+    ) -> Vec<(String, Outcome)> {
+        let mut done = Vec::new();
+        schedule(action, tunnels, workers, act, |progress| {
+            if let Progress::Done(i, outcome) = progress {
+                done.push((tunnels[i].name.clone(), outcome));
+            }
+        });
+        done
+    }
+
+    #[test]
+    fn waits_follow_dependencies() {
+        let tunnels = chain();
+        let tunnels: Vec<&TunnelHost> = tunnels.iter().collect();
+        assert_eq!(waits(Action::Up { persist: false }, &tunnels), [vec![], vec![0], vec![1]]);
+        assert_eq!(waits(Action::Restart, &tunnels), [vec![], vec![0], vec![1]]);
+        assert_eq!(waits(Action::Down, &tunnels), [vec![1], vec![2], vec![]]);
+    }
+
+    #[test]
+    fn queue_runs_dependencies_first() {
+        let mut queue = Queue {
+            slots: vec![Slot::Waiting; 3],
+            waits: vec![vec![], vec![0], vec![1]],
+        };
+        assert!(matches!(queue.next(), Next::Run(0)));
+        assert!(matches!(queue.next(), Next::Wait));
+        queue.slots[0] = Slot::Done(true);
+        assert!(matches!(queue.next(), Next::Run(1)));
+        queue.slots[1] = Slot::Done(true);
+        assert!(matches!(queue.next(), Next::Run(2)));
+        queue.slots[2] = Slot::Done(true);
+        assert!(matches!(queue.next(), Next::Finished));
+    }
+
+    #[test]
+    fn queue_blocks_on_failed_dependency() {
+        let mut queue = Queue {
+            slots: vec![Slot::Done(false), Slot::Waiting, Slot::Waiting, Slot::Waiting],
+            waits: vec![vec![], vec![0], vec![1], vec![]],
+        };
+        assert!(matches!(queue.next(), Next::Blocked(1, 0)));
+        assert!(matches!(queue.next(), Next::Blocked(2, 1)));
+        // Unrelated tunnels still run
+        assert!(matches!(queue.next(), Next::Run(3)));
+        queue.slots[3] = Slot::Done(true);
+        assert!(matches!(queue.next(), Next::Finished));
+    }
+
+    #[test]
+    fn queue_breaks_cycles() {
+        let mut queue = Queue {
+            slots: vec![Slot::Waiting; 2],
+            waits: vec![vec![1], vec![0]],
+        };
+        assert!(matches!(queue.next(), Next::Blocked(0, 0)));
+        assert!(matches!(queue.next(), Next::Blocked(1, 0)));
+        assert!(matches!(queue.next(), Next::Finished));
+    }
+
+    #[test]
+    fn starts_in_dependency_order() {
+        let tunnels = chain();
+        // Listed dependents first, so only the dependencies force the order
+        let tunnels: Vec<&TunnelHost> = tunnels.iter().rev().collect();
+        let done = finish_order(Action::Up { persist: false }, &tunnels, 3, |_, _| started());
+        let names: Vec<&str> = done.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["db", "api", "web"]);
+    }
+
+    #[test]
+    fn stops_dependents_first() {
+        let tunnels = chain();
+        let tunnels: Vec<&TunnelHost> = tunnels.iter().collect();
+        let done = finish_order(Action::Down, &tunnels, 3, |_, _| Outcome::Stopped);
+        let names: Vec<&str> = done.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["web", "api", "db"]);
+    }
+
+    #[test]
+    fn failed_dependency_blocks_dependents() {
+        let tunnels = chain();
+        let tunnels: Vec<&TunnelHost> = tunnels.iter().collect();
+        let ran = AtomicUsize::new(0);
+        let done = finish_order(Action::Up { persist: false }, &tunnels, 3, |_, _| {
+            ran.fetch_add(1, Ordering::SeqCst);
+            Outcome::Failed("connection refused".to_string())
+        });
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+        let reasons: Vec<String> = done
+            .into_iter()
+            .map(|(name, outcome)| match outcome {
+                Outcome::Failed(reason) => format!("{}: {}", name, reason),
+                other => panic!("{} {}", name, other.verb()),
+            })
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                "db: connection refused",
+                "api: not started: dependency 'db' did not come up",
+                "web: not started: dependency 'api' did not come up",
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_at_most_max_parallel() {
+        let tunnels: Vec<TunnelHost> = (0..8).map(|i| TunnelHost::named(&format!("t{}", i))).collect();
+        let tunnels: Vec<&TunnelHost> = tunnels.iter().collect();
+        let (running, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let done = finish_order(Action::Up { persist: false }, &tunnels, 3, |_, _| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            running.fetch_sub(1, Ordering::SeqCst);
+            started()
+        });
+        assert_eq!(done.len(), 8);
+        // More than one at a time, but never more than max_parallel
+        assert!((2..=3).contains(&most.load(Ordering::SeqCst)), "{:?}", most);
+    }
+
+    /// A backend keeping one shared list of entries, rewritten whole on every
+    /// enable the way the crontab is.
+    struct Shared {
+        entries: Mutex<Vec<String>>,
+        writes: AtomicUsize,
+    }
+
+    impl AutoStart for Shared {
+        fn name(&self) -> &'static str {
+            "shared"
+        }
+        fn is_available(&self) -> bool {
+            true
+        }
+        fn is_enabled(&self, name: &str) -> bool {
+            self.entries.lock().unwrap().iter().any(|e| e == name)
+        }
+        fn enable(&self, tunnel: &TunnelHost, _root: &ConfigRoot, _cfg: &Config) -> anyhow::Result<()> {
+            let mut entries = self.entries.lock().unwrap().clone();
+            // Room for another enable to read the same entries
+            std::thread::sleep(Duration::from_millis(10));
+            entries.push(tunnel.name.clone());
+            *self.entries.lock().unwrap() = entries;
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+        fn disable(&self, _name: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn render(&self, tunnel: &TunnelHost, _root: &ConfigRoot, _cfg: &Config) -> anyhow::Result<String> {
+            Ok(tunnel.name.clone())
+        }
+        fn installed(&self, _name: &str) -> Option<String> {
+            None
+        }
+    }
+
+    static SHARED: Shared = Shared {
+        entries: Mutex::new(Vec::new()),
+        writes: AtomicUsize::new(0),
+    };
+
+    #[test]
+    fn parallel_enables_take_turns() {
+        let root = ConfigRoot {
+            path: std::path::PathBuf::from("/dev/null"),
+            ssh_dir: std::env::temp_dir(),
+            custom: true,
+        };
+        let cfg = Config::default();
+        let tunnels: Vec<TunnelHost> = (0..6).map(|i| TunnelHost::named(&format!("t{}", i))).collect();
+        let tunnels: Vec<&TunnelHost> = tunnels.iter().collect();
+        finish_order(Action::Up { persist: true }, &tunnels, 6, |i, _| {
+            enable(&SHARED, tunnels[i], &root, &cfg).unwrap();
+            started()
+        });
+        assert_eq!(SHARED.writes.load(Ordering::SeqCst), 6);
+        let mut entries = SHARED.entries.lock().unwrap().clone();
+        entries.sort();
+        assert_eq!(entries, ["t0", "t1", "t2", "t3", "t4", "t5"]);
+    }
+
+    #[test]
+    fn summary_counts_failures_last() {
+        let outcomes = [
+            Outcome::Failed("boom".to_string()),
+            started(),
+            Outcome::Failed("boom".to_string()),
+            started(),
+            started(),
+        ];
+        assert_eq!(summarize(&outcomes), ("3 started, 2 failed".to_string(), 2));
+    }
+
+    #[test]
+    fn summary_without_failures() {
+        let outcomes = [Outcome::Stopped, Outcome::Stopped];
+        assert_eq!(summarize(&outcomes), ("2 stopped".to_string(), 0));
+    }
+
+    #[test]
+    fn result_lines() {
+        colored::control::set_override(false);
+        assert_eq!(Outcome::Stopped.line("db"), "○ db stopped");
+        assert_eq!(
+            Outcome::Failed("port 5432 already in use".to_string()).line("db"),
+            "✗ db — port 5432 already in use"
+        );
+        let restarted = Outcome::Restarted {
+            pid: 42,
+            health: Some(Err("127.0.0.1:5432: connection refused".to_string())),
+        };
+        assert_eq!(
+            restarted.line("db"),
+            "● db restarted (pid 42) — ✗ not reachable yet (127.0.0.1:5432: connection refused)"
+        );
+    }
+}
@@ -12,26 +12,36 @@ pub struct Cli {
     pub command: Command,
 }
 
+/// Discover tunnels from the configured SSH config, ignoring errors (completion
+/// must not fail) and without running `Match exec` commands on every keystroke.
+fn completion_tunnels() -> Vec<crate::tunnel::TunnelHost> {
+    let cfg = crate::config::Config::load();
+    crate::ssh_config::ConfigRoot::resolve(&cfg)
+        .and_then(|root| crate::ssh_config::discover_tunnels_with(&root, crate::ssh_config::Exec::Skip))
+        .unwrap_or_default()
+}
+
 fn complete_tunnel_names(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
     let prefix = current.to_str().unwrap_or("");
-    let tunnels = crate::ssh_config::discover_tunnels().unwrap_or_default();
+    let tunnels = completion_tunnels();
     tunnels
         .iter()
-        .filter(|t| t.name.starts_with(prefix))
-        .map(|t| CompletionCandidate::new(&t.name))
+        .flat_map(|t| t.aliases.iter())
+        .filter(|a| a.starts_with(prefix))
+        .map(CompletionCandidate::new)
         .collect()
 }
 
 fn complete_group_names(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
     let prefix = current.to_str().unwrap_or("");
-    let tunnels = crate::ssh_config::discover_tunnels().unwrap_or_default();
+    let tunnels = completion_tunnels();
     let mut seen = std::collections::HashSet::new();
     tunnels
         .iter()
         .filter_map(|t| t.group.as_deref())
         .filter(|g| g.starts_with(prefix))
         .filter(|g| seen.insert(g.to_string()))
-        .map(|g| CompletionCandidate::new(g))
+        .map(CompletionCandidate::new)
         .collect()
 }
 
@@ -48,7 +58,7 @@ pub enum Command {
         /// Start all inactive tunnels in a group
         #[arg(long, short, conflicts_with = "name", conflicts_with = "all", add = ArgValueCompleter::new(complete_group_names))]
         group: Option<String>,
-        /// Auto-start this tunnel on login via launchd
+        /// Auto-start this tunnel on login with the configured persistence backend
         #[arg(long)]
         persist: bool,
     },
@@ -91,9 +101,17 @@ pub enum Command {
     },
     /// Health-check all active tunnels
     Check,
+    /// Check tunnel definitions for problems without starting anything
+    Lint {
+        /// Print findings as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Diagnose the environment: dependencies, permissions, stale processes
+    Doctor,
     /// Add a new tunnel interactively
     Add,
-    /// Open ~/.ssh/config in your editor
+    /// Open the SSH config (~/.ssh/config or `ssh_config`) in your editor
     Edit,
     /// Show tunnel logs
     Logs {
@@ -107,7 +125,7 @@ pub enum Command {
         #[arg(short, long)]
         follow: bool,
     },
-    /// Enable auto-start on login via launchd
+    /// Enable auto-start on login with the configured persistence backend
     Enable {
         /// Tunnel name (interactive picker if omitted)
         #[arg(add = ArgValueCompleter::new(complete_tunnel_names))]
@@ -115,6 +133,12 @@ pub enum Command {
         /// Enable all tunnels in a group
         #[arg(long, short, conflicts_with = "name", add = ArgValueCompleter::new(complete_group_names))]
         group: Option<String>,
+        /// Rewrite and reload auto-start entries that no longer match what mole would generate
+        #[arg(long)]
+        sync: bool,
+        /// With --sync, every enabled tunnel
+        #[arg(long, short, requires = "sync", conflicts_with_all = ["name", "group"])]
+        all: bool,
     },
     /// Disable auto-start on login
     Disable {
@@ -133,7 +157,12 @@ pub enum Command {
         /// New tunnel name
         new_name: String,
     },
-    /// Initialize or edit ~/.mole/config.toml
+    /// Run the mole daemon, which owns tunnel processes and serves the CLI
+    Daemon {
+        #[command(subcommand)]
+        action: Option<DaemonAction>,
+    },
+    /// Initialize or edit the mole config file
     Config,
     /// Generate shell completions
     Completions {
@@ -143,4 +172,28 @@ pub enum Command {
     /// List tunnel names (for shell completion scripts)
     #[command(hide = true)]
     ListTunnelNames,
+    /// Run ssh for a tunnel under mole's supervisor (used by the "ssh" backend)
+    #[command(hide = true)]
+    Supervise {
+        /// Tunnel name, for logging and process lookup
+        name: String,
+        /// Give up if the first ssh exits within this many seconds (0: never)
+        #[arg(long, default_value_t = 30)]
+        gate_time: u64,
+        /// Arguments passed to ssh
+        #[arg(last = true)]
+        ssh_args: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DaemonAction {
+    /// Run the daemon in the foreground (the default)
+    Run,
+    /// Show whether the daemon is running and what it manages
+    Status,
+    /// Stop the daemon; tunnels keep running and the CLI manages them directly
+    Stop,
+    /// Stream tunnel events (started, stopped, exited, adopted) as they happen
+    Events,
 }
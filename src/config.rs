@@ -1,15 +1,11 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::PathBuf;
 
-fn config_path() -> Result<PathBuf> {
-    let dir = dirs::home_dir()
-        .context("cannot determine home directory")?
-        .join(".mole");
-    fs::create_dir_all(&dir)?;
-    Ok(dir.join("config.toml"))
-}
+use crate::paths;
+use crate::tunnel::TunnelHost;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(default)]
@@ -18,12 +14,95 @@ pub struct Config {
     pub shell: Option<String>,
     /// Editor for `mole edit` (overrides $VISUAL/$EDITOR)
     pub editor: Option<String>,
-    /// SSH Config file path (defaults to ~/.ssh/config)
+    /// SSH Config file path (defaults to ~/.ssh/config; `~` and $VARS are expanded)
     pub ssh_config: Option<String>,
     /// Health check timeout in seconds
     pub health_timeout: u64,
+    /// Seconds a stopping tunnel gets to exit and release its ports before SIGKILL
+    pub stop_timeout: u64,
     /// Max log file size in bytes before rotation
     pub max_log_size: u64,
+    /// Seconds the built-in supervisor's first ssh must stay up; if it exits
+    /// sooner the supervisor gives up (like AUTOSSH_GATETIME). 0 always retries.
+    pub gate_time: u64,
+    /// Tunnels started or stopped at once by `--all` and `--group`
+    pub max_parallel: usize,
+    /// Process that keeps tunnels up: "autossh", or "ssh" for mole's own supervisor.
+    /// Tunnels can override it with `# mole:backend=<name>`.
+    pub backend: Backend,
+    /// How `mole enable` starts tunnels at login: "auto", "launchd", "systemd",
+    /// "xdg-autostart" or "crontab"
+    pub persistence: Persistence,
+    /// Extra arguments for every tunnel's ssh, e.g. ["-o", "ServerAliveInterval=15"]
+    pub ssh_options: Vec<String>,
+    /// Environment for autossh, e.g. AUTOSSH_GATETIME or AUTOSSH_POLL
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub autossh_env: BTreeMap<String, String>,
+    /// Per-tunnel settings, `[tunnels.<name>]`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tunnels: BTreeMap<String, TunnelSettings>,
+}
+
+/// Settings for one tunnel, added to (or overriding) the global ones.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TunnelSettings {
+    pub ssh_options: Vec<String>,
+    pub autossh_env: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Autossh,
+    Ssh,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "autossh" => Ok(Backend::Autossh),
+            "ssh" => Ok(Backend::Ssh),
+            other => anyhow::bail!("unknown backend '{}' (expected 'autossh' or 'ssh')", other),
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Backend::Autossh => "autossh",
+            Backend::Ssh => "ssh",
+        })
+    }
+}
+
+/// Which service manager `mole enable` uses. `Auto` picks the first one
+/// available: launchd, systemd, XDG autostart, then crontab.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Persistence {
+    #[default]
+    Auto,
+    Launchd,
+    Systemd,
+    XdgAutostart,
+    Crontab,
+}
+
+impl std::fmt::Display for Persistence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Persistence::Auto => "auto",
+            Persistence::Launchd => "launchd",
+            Persistence::Systemd => "systemd",
+            Persistence::XdgAutostart => "xdg-autostart",
+            Persistence::Crontab => "crontab",
+        })
+    }
 }
 
 impl Default for Config {
@@ -33,15 +112,23 @@ impl Default for Config {
             editor: None,
             ssh_config: None,
             health_timeout: 5,
+            stop_timeout: 5,
+            gate_time: 30,
             max_log_size: 1_048_576,
+            max_parallel: 4,
+            backend: Backend::default(),
+            persistence: Persistence::default(),
+            ssh_options: Vec::new(),
+            autossh_env: BTreeMap::new(),
+            tunnels: BTreeMap::new(),
         }
     }
 }
 
 impl Config {
-    /// Load config from ~/.mole/config.toml, falling back to defaults.
+    /// Load the config file (see `paths`), falling back to defaults.
     pub fn load() -> Self {
-        let path = match config_path() {
+        let path = match paths::config_file() {
             Ok(p) => p,
             Err(_) => return Self::default(),
         };
@@ -64,9 +151,38 @@ impl Config {
             .unwrap_or_else(|_| "vi".to_string())
     }
 
+    /// The `[tunnels.<name>]` table for a tunnel, looked up by its name, then its aliases.
+    fn tunnel_settings(&self, tunnel: &TunnelHost) -> Option<&TunnelSettings> {
+        tunnel.aliases.iter().find_map(|a| self.tunnels.get(a))
+    }
+
+    /// Extra ssh arguments for a tunnel: its `# mole:ssh-option=` annotations,
+    /// then its `[tunnels.<name>]` options, then the global ones. ssh keeps the
+    /// first value of each `-o` option, so the most specific setting wins.
+    pub fn ssh_options(&self, tunnel: &TunnelHost) -> Vec<String> {
+        let mut options = tunnel.ssh_options.clone();
+        if let Some(settings) = self.tunnel_settings(tunnel) {
+            options.extend(settings.ssh_options.iter().cloned());
+        }
+        options.extend(self.ssh_options.iter().cloned());
+        options
+    }
+
+    /// autossh's environment for a tunnel: `AUTOSSH_PORT=0` (no monitor port,
+    /// rely on ServerAlive) overlaid with the global `autossh_env`, then the
+    /// tunnel's own.
+    pub fn autossh_env(&self, tunnel: &TunnelHost) -> BTreeMap<String, String> {
+        let mut env = BTreeMap::from([("AUTOSSH_PORT".to_string(), "0".to_string())]);
+        env.extend(self.autossh_env.clone());
+        if let Some(settings) = self.tunnel_settings(tunnel) {
+            env.extend(settings.autossh_env.clone());
+        }
+        env
+    }
+
     /// Write a default config file if none exists. Returns the path.
     pub fn init() -> Result<PathBuf> {
-        let path = config_path()?;
+        let path = paths::config_file()?;
         if path.exists() {
             return Ok(path);
         }
@@ -77,5 +193,144 @@ impl Config {
             .with_context(|| format!("failed to write {}", path.display()))?;
         Ok(path)
     }
+}
+
+/// Expand a leading `~` and `$VAR` / `${VAR}` references in a path from config.
+pub fn expand_path(value: &str) -> Result<PathBuf> {
+    let mut out = String::new();
+    let mut rest = value;
+
+    if rest == "~" || rest.starts_with("~/") {
+        let home = dirs::home_dir().context("cannot determine home directory")?;
+        out.push_str(&home.to_string_lossy());
+        rest = &rest[1..];
+    }
+
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let (name, consumed) = if let Some(braced) = after.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .with_context(|| format!("unterminated ${{ in '{}'", value))?;
+            (&braced[..end], end + 2)
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+        if name.is_empty() {
+            out.push('$');
+        } else {
+            let val = std::env::var(name)
+                .with_context(|| format!("environment variable ${} is not set", name))?;
+            out.push_str(&val);
+        }
+        rest = &after[consumed..];
+    }
+    out.push_str(rest);
+
+    Ok(PathBuf::from(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_setting() {
+        let cfg: Config = toml::from_str("backend = \"ssh\"").unwrap();
+        assert_eq!(cfg.backend, Backend::Ssh);
+        assert_eq!(Config::default().backend, Backend::Autossh);
+        assert_eq!("AutoSSH".parse::<Backend>().unwrap(), Backend::Autossh);
+        assert!("mosh".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    fn persistence_setting() {
+        let cfg: Config = toml::from_str("persistence = \"xdg-autostart\"").unwrap();
+        assert_eq!(cfg.persistence, Persistence::XdgAutostart);
+        assert_eq!(Config::default().persistence, Persistence::Auto);
+        assert_eq!(Persistence::XdgAutostart.to_string(), "xdg-autostart");
+    }
+
+    fn tunnel(name: &str, ssh_options: &[&str]) -> TunnelHost {
+        TunnelHost {
+            aliases: vec![name.to_string(), format!("{}.alias", name)],
+            ssh_options: ssh_options.iter().map(|o| o.to_string()).collect(),
+            ..TunnelHost::named(name)
+        }
+    }
 
+    #[test]
+    fn ssh_options_most_specific_first() {
+        let cfg: Config = toml::from_str(
+            r#"
+ssh_options = ["-o", "ServerAliveInterval=30"]
+
+[tunnels."db.alias"]
+ssh_options = ["-o", "ServerAliveInterval=15"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.ssh_options(&tunnel("db", &["-v"])),
+            ["-v", "-o", "ServerAliveInterval=15", "-o", "ServerAliveInterval=30"]
+        );
+        assert_eq!(cfg.ssh_options(&tunnel("web", &[])), ["-o", "ServerAliveInterval=30"]);
+    }
+
+    #[test]
+    fn autossh_env_overrides() {
+        let cfg: Config = toml::from_str(
+            r#"
+autossh_env = { AUTOSSH_GATETIME = "0", AUTOSSH_POLL = "60" }
+
+[tunnels.db]
+autossh_env = { AUTOSSH_POLL = "10", AUTOSSH_PORT = "20000" }
+"#,
+        )
+        .unwrap();
+        let env = cfg.autossh_env(&tunnel("db", &[]));
+        assert_eq!(env["AUTOSSH_PORT"], "20000");
+        assert_eq!(env["AUTOSSH_POLL"], "10");
+        assert_eq!(env["AUTOSSH_GATETIME"], "0");
+        assert_eq!(
+            Config::default().autossh_env(&tunnel("db", &[])),
+            BTreeMap::from([("AUTOSSH_PORT".to_string(), "0".to_string())])
+        );
+    }
+
+    #[test]
+    fn default_config_serializes() {
+        let text = toml::to_string_pretty(&Config::default()).unwrap();
+        assert!(text.contains("ssh_options = []"));
+        assert!(!text.contains("tunnels"));
+    }
+
+    #[test]
+    fn expand_path_plain() {
+        assert_eq!(expand_path("/etc/ssh/config").unwrap(), PathBuf::from("/etc/ssh/config"));
+    }
+
+    #[test]
+    fn expand_path_tilde() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_path("~/work/ssh_config").unwrap(), home.join("work/ssh_config"));
+        assert_eq!(expand_path("~").unwrap(), home);
+    }
+
+    #[test]
+    fn expand_path_env_vars() {
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(expand_path("$HOME/a").unwrap(), PathBuf::from(format!("{}/a", home)));
+        assert_eq!(expand_path("${HOME}/b").unwrap(), PathBuf::from(format!("{}/b", home)));
+    }
+
+    #[test]
+    fn expand_path_unset_var() {
+        assert!(expand_path("$MOLE_TEST_SURELY_UNSET_VAR/x").is_err());
+        assert!(expand_path("${HOME").is_err());
+    }
 }
@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::persist::{self, AutoStart};
+use crate::process;
+use crate::ssh_config::ConfigRoot;
+use crate::tunnel::TunnelHost;
+
+/// `@reboot` lines in the user's crontab, for machines with cron but no
+/// service manager.
+pub struct Crontab;
+
+impl AutoStart for Crontab {
+    fn name(&self) -> &'static str {
+        "crontab"
+    }
+
+    fn is_available(&self) -> bool {
+        process::which("crontab").is_ok()
+    }
+
+    fn is_enabled(&self, name: &str) -> bool {
+        self.installed(name).is_some()
+    }
+
+    fn enable(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<()> {
+        let line = self.render(tunnel, root, cfg)?;
+        update(|crontab| {
+            let mut crontab = without(crontab, &tunnel.name);
+            crontab.push_str(&line);
+            crontab.push('\n');
+            Ok(crontab)
+        })
+    }
+
+    fn disable(&self, name: &str) -> Result<()> {
+        update(|crontab| {
+            if find_entry(crontab, name).is_none() {
+                anyhow::bail!("tunnel '{}' is not enabled for auto-start", name);
+            }
+            Ok(without(crontab, name))
+        })
+    }
+
+    fn render(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<String> {
+        let log_path = process::log_file(&tunnel.name)?;
+        let spec = process::launch_spec(tunnel, root, cfg)?;
+        let program = process::which(&spec.program)?;
+        Ok(entry(&tunnel.name, &persist::shell_command(&program, &spec, &log_path)))
+    }
+
+    fn installed(&self, name: &str) -> Option<String> {
+        find_entry(&read().ok()?, name).map(str::to_string)
+    }
+}
+
+/// Held from reading the crontab to writing it back, so two edits from this
+/// process can't both start from the same crontab and lose one's line.
+static EDITING: Mutex<()> = Mutex::new(());
+
+/// Rewrite the user's crontab as `edit` returns it.
+fn update(edit: impl FnOnce(&str) -> Result<String>) -> Result<()> {
+    let _editing = EDITING.lock().unwrap();
+    write(&edit(&read()?)?)
+}
+
+/// The user's crontab; empty if they don't have one. A [`persist::Survey`]
+/// reads it once for all tunnels.
+fn read() -> Result<String> {
+    persist::memo("crontab -l", read_now)
+}
+
+fn read_now() -> Result<String> {
+    let output = Command::new("crontab")
+        .arg("-l")
+        .stdin(Stdio::null())
+        .output()
+        .context("failed to run crontab -l")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let crontab = if output.status.success() {
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    } else if stderr.contains("no crontab") {
+        String::new()
+    } else {
+        // Don't go on as if it were empty: writing it back would wipe it
+        anyhow::bail!("crontab -l failed: {}", stderr.trim());
+    };
+    Ok(crontab)
+}
+
+/// Replace the user's crontab.
+fn write(crontab: &str) -> Result<()> {
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run crontab")?;
+    child
+        .stdin
+        .take()
+        .context("crontab has no stdin")?
+        .write_all(crontab.as_bytes())
+        .context("failed to write to crontab")?;
+    let output = child.wait_with_output().context("failed to run crontab")?;
+    if !output.status.success() {
+        anyhow::bail!("crontab failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Ends the line mole adds for a tunnel; `sh` reads it as a comment.
+fn marker(name: &str) -> String {
+    format!(" # mole:{}", name)
+}
+
+/// The `@reboot` line for a tunnel. cron turns unescaped `%` into newlines.
+fn entry(name: &str, command: &str) -> String {
+    format!("@reboot {}{}", command.replace('%', "\\%"), marker(name))
+}
+
+/// The tunnel's line in a crontab.
+fn find_entry<'a>(crontab: &'a str, name: &str) -> Option<&'a str> {
+    crontab.lines().find(|line| line.ends_with(&marker(name)))
+}
+
+/// The crontab without the tunnel's line, everything else kept as it was.
+fn without(crontab: &str, name: &str) -> String {
+    crontab
+        .lines()
+        .filter(|line| !line.ends_with(&marker(name)))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_escapes_percent_signs() {
+        assert_eq!(
+            entry("db", "exec /usr/bin/autossh -o 'ProxyCommand=nc %h %p' db"),
+            "@reboot exec /usr/bin/autossh -o 'ProxyCommand=nc \\%h \\%p' db # mole:db"
+        );
+    }
+
+    #[test]
+    fn entries_are_found_and_removed_by_tunnel() {
+        let crontab = format!(
+            "MAILTO=me\n0 * * * * backup.sh\n{}\n{}\n",
+            entry("db", "exec autossh -N db"),
+            entry("b", "exec autossh -N b")
+        );
+        assert_eq!(find_entry(&crontab, "db"), Some(entry("db", "exec autossh -N db").as_str()));
+        assert!(find_entry(&crontab, "b").is_some());
+        assert!(find_entry(&crontab, "d").is_none());
+
+        let rest = without(&crontab, "db");
+        assert!(find_entry(&rest, "db").is_none());
+        assert_eq!(rest, format!("MAILTO=me\n0 * * * * backup.sh\n{}\n", entry("b", "exec autossh -N b")));
+    }
+}
@@ -0,0 +1,785 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::paths;
+use crate::process;
+use crate::ssh_config::{self, ConfigRoot, Exec};
+use crate::tunnel::{Endpoint, TunnelHost};
+
+/// How often exited children are reaped.
+const REAP_INTERVAL: Duration = Duration::from_millis(500);
+/// How often tunnels started outside the daemon (launchd, a shell) are looked for.
+const ADOPT_INTERVAL: Duration = Duration::from_secs(10);
+/// How often `logs --follow` checks the log file for new output.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the CLI waits for the daemon to answer before acting without it.
+/// Stopping a tunnel may take its `stop_timeout` on top of this.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Set in the daemon process so process:: calls act directly instead of
+/// asking the daemon, i.e. itself.
+static SERVING: AtomicBool = AtomicBool::new(false);
+static STOP: AtomicBool = AtomicBool::new(false);
+/// Set once the daemon failed to answer, so later calls don't wait for it again.
+static UNRESPONSIVE: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_stop(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// One request per line, as JSON.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum Request {
+    Up { name: String },
+    Down { name: String },
+    List,
+    Logs { name: String, lines: usize, follow: bool },
+    Subscribe,
+    Shutdown,
+}
+
+/// One or more responses per request, one per line, as JSON.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Started { pid: u32 },
+    Tunnels { tunnels: Vec<TunnelState> },
+    /// A log line. `logs` sends the requested lines and then `Ok`; with follow,
+    /// lines appended later keep coming after it.
+    Line { text: String },
+    Event { event: Event },
+    Error { message: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelState {
+    pub name: String,
+    pub pid: u32,
+    /// Unix time the process started.
+    pub started: u64,
+    /// Whether the daemon spawned it, rather than adopting it.
+    pub owned: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Started { name: String, pid: u32 },
+    Stopped { name: String },
+    Exited { name: String, status: String },
+    Adopted { name: String, pid: u32 },
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::Started { name, pid } => write!(f, "{} started (pid {})", name, pid),
+            Event::Stopped { name } => write!(f, "{} stopped", name),
+            Event::Exited { name, status } => write!(f, "{} exited ({})", name, status),
+            Event::Adopted { name, pid } => write!(f, "{} adopted (pid {})", name, pid),
+        }
+    }
+}
+
+/// The error a request fails with when the daemon doesn't answer in time.
+#[derive(Debug)]
+pub struct Unresponsive;
+
+impl std::fmt::Display for Unresponsive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "mole daemon is not responding")
+    }
+}
+
+fn connection_error(e: io::Error, what: &'static str) -> anyhow::Error {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => anyhow::Error::new(e).context(Unresponsive),
+        _ => anyhow::Error::new(e).context(what),
+    }
+}
+
+/// Run `f` with a connection to the daemon. None when the daemon isn't running
+/// or stops answering; callers then act directly.
+pub fn with_client<T>(f: impl FnOnce(&mut Client) -> Result<T>) -> Option<Result<T>> {
+    let mut client = Client::connect()?;
+    match f(&mut client) {
+        Err(e) if e.is::<Unresponsive>() => {
+            UNRESPONSIVE.store(true, Ordering::SeqCst);
+            eprintln!("mole: {}; managing tunnels directly", Unresponsive);
+            None
+        }
+        result => Some(result),
+    }
+}
+
+/// A connection to a running daemon.
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    timeout: Duration,
+}
+
+impl Client {
+    /// Connect to the daemon, or None when it isn't running (callers then act directly).
+    pub fn connect() -> Option<Client> {
+        if SERVING.load(Ordering::SeqCst) || UNRESPONSIVE.load(Ordering::SeqCst) {
+            return None;
+        }
+        Client::connect_to(&paths::socket().ok()?, REPLY_TIMEOUT)
+    }
+
+    /// Connect to the socket at `path`, failing requests that get no answer within `timeout`.
+    fn connect_to(path: &Path, timeout: Duration) -> Option<Client> {
+        let stream = UnixStream::connect(path).ok()?;
+        stream.set_read_timeout(Some(timeout)).ok()?;
+        stream.set_write_timeout(Some(timeout)).ok()?;
+        let writer = stream.try_clone().ok()?;
+        Some(Client {
+            reader: BufReader::new(stream),
+            writer,
+            timeout,
+        })
+    }
+
+    /// Wait up to `timeout` for each response from now on; None waits forever.
+    fn wait_for(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.reader.get_ref().set_read_timeout(timeout)?)
+    }
+
+    fn send(&mut self, request: &Request) -> Result<()> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .map_err(|e| connection_error(e, "lost connection to mole daemon"))
+    }
+
+    fn receive(&mut self) -> Result<Option<Response>> {
+        let mut line = String::new();
+        let read = self
+            .reader
+            .read_line(&mut line)
+            .map_err(|e| connection_error(e, "lost connection to mole daemon"))?;
+        if read == 0 {
+            return Ok(None);
+        }
+        let response = serde_json::from_str(&line).context("invalid response from mole daemon")?;
+        if let Response::Error { message } = response {
+            anyhow::bail!("{}", message);
+        }
+        Ok(Some(response))
+    }
+
+    fn request(&mut self, request: &Request) -> Result<Response> {
+        self.send(request)?;
+        self.receive()?
+            .context("mole daemon closed the connection")
+    }
+
+    pub fn up(&mut self, name: &str) -> Result<u32> {
+        match self.request(&Request::Up { name: name.to_string() })? {
+            Response::Started { pid } => Ok(pid),
+            other => anyhow::bail!("unexpected response from mole daemon: {:?}", other),
+        }
+    }
+
+    /// Stop a tunnel, allowing `grace` for it to exit on top of the usual wait.
+    pub fn down(&mut self, name: &str, grace: Duration) -> Result<()> {
+        self.wait_for(Some(self.timeout + grace))?;
+        self.request(&Request::Down { name: name.to_string() })?;
+        self.wait_for(Some(self.timeout))
+    }
+
+    pub fn list(&mut self) -> Result<Vec<TunnelState>> {
+        match self.request(&Request::List)? {
+            Response::Tunnels { tunnels } => Ok(tunnels),
+            other => anyhow::bail!("unexpected response from mole daemon: {:?}", other),
+        }
+    }
+
+    pub fn status(&mut self, name: &str) -> Result<Option<TunnelState>> {
+        Ok(self.list()?.into_iter().find(|t| t.name == name))
+    }
+
+    /// Print a tunnel's log; with follow, keeps printing until the connection drops.
+    pub fn logs(&mut self, name: &str, lines: usize, follow: bool) -> Result<()> {
+        self.send(&Request::Logs {
+            name: name.to_string(),
+            lines,
+            follow,
+        })?;
+        while let Some(response) = self.receive()? {
+            match response {
+                Response::Line { text } => println!("{}", text),
+                // New lines come whenever the tunnel writes them
+                Response::Ok if follow => self.wait_for(None)?,
+                Response::Ok => break,
+                other => anyhow::bail!("unexpected response from mole daemon: {:?}", other),
+            }
+        }
+        Ok(())
+    }
+
+    /// Call `on_event` for every event until the daemon goes away.
+    pub fn subscribe(&mut self, mut on_event: impl FnMut(Event)) -> Result<()> {
+        self.send(&Request::Subscribe)?;
+        self.wait_for(None)?;
+        while let Some(response) = self.receive()? {
+            if let Response::Event { event } = response {
+                on_event(event);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.request(&Request::Shutdown)?;
+        Ok(())
+    }
+}
+
+/// A tunnel process the daemon knows about.
+struct Entry {
+    pid: u32,
+    started: u64,
+    /// Present when the daemon spawned it and must reap it.
+    child: Option<Child>,
+}
+
+#[derive(Default)]
+struct State {
+    tunnels: HashMap<String, Entry>,
+    /// Names being started or stopped right now, so requests (and adoption) can't race.
+    busy: HashSet<String>,
+    subscribers: Vec<UnixStream>,
+}
+
+impl State {
+    fn emit(&mut self, event: Event) {
+        eprintln!("mole daemon: {}", event);
+        let Ok(mut line) = serde_json::to_string(&Response::Event { event }) else {
+            return;
+        };
+        line.push('\n');
+        self.subscribers
+            .retain_mut(|s| s.write_all(line.as_bytes()).is_ok());
+    }
+
+    fn snapshot(&self) -> Vec<TunnelState> {
+        let mut tunnels: Vec<TunnelState> = self
+            .tunnels
+            .iter()
+            .map(|(name, e)| TunnelState {
+                name: name.clone(),
+                pid: e.pid,
+                started: e.started,
+                owned: e.child.is_some(),
+            })
+            .collect();
+        tunnels.sort_by(|a, b| a.name.cmp(&b.name));
+        tunnels
+    }
+}
+
+type Shared = Arc<Mutex<State>>;
+
+/// What the daemon does to tunnels: [`Live`] reads the SSH config and runs
+/// them; tests substitute a fake.
+trait Launcher: Sync {
+    /// mole's config, read afresh so edits apply without a restart.
+    fn config(&self) -> Config;
+    /// The SSH config root and the tunnels in it.
+    fn discover(&self, cfg: &Config, exec: Exec) -> Result<(ConfigRoot, Vec<TunnelHost>)>;
+    /// Start a tunnel; the daemon owns the child.
+    fn spawn(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<Child>;
+    /// Clean up after a tunnel that stopped (its PID file).
+    fn forget(&self, name: &str);
+}
+
+struct Live;
+
+impl Launcher for Live {
+    fn config(&self) -> Config {
+        Config::load()
+    }
+
+    fn discover(&self, cfg: &Config, exec: Exec) -> Result<(ConfigRoot, Vec<TunnelHost>)> {
+        let root = ConfigRoot::resolve(cfg)?;
+        let tunnels = ssh_config::discover_tunnels_with(&root, exec)?;
+        Ok((root, tunnels))
+    }
+
+    fn spawn(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<Child> {
+        process::spawn_tunnel(tunnel, root, cfg)
+    }
+
+    fn forget(&self, name: &str) {
+        if let Ok(path) = process::pid_file(name) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Run the daemon in the foreground until SIGTERM/SIGINT or a shutdown request.
+/// Tunnels keep running when it exits; the CLI then manages them directly again.
+pub fn serve() -> Result<()> {
+    let path = paths::socket()?;
+    if UnixStream::connect(&path).is_ok() {
+        anyhow::bail!("mole daemon is already running ({})", path.display());
+    }
+    // Left behind by a daemon that didn't shut down cleanly
+    let _ = fs::remove_file(&path);
+
+    let listener =
+        UnixListener::bind(&path).with_context(|| format!("failed to bind {}", path.display()))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    listener.set_nonblocking(true)?;
+
+    SERVING.store(true, Ordering::SeqCst);
+    unsafe {
+        for sig in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+            libc::signal(sig, handle_stop as *const () as libc::sighandler_t);
+        }
+    }
+
+    let state: Shared = Arc::default();
+    adopt(&state, &Live);
+    eprintln!("mole daemon: listening on {}", path.display());
+
+    let reaper = {
+        let state = Arc::clone(&state);
+        std::thread::spawn(move || watch(&state, &Live))
+    };
+
+    accept(&listener, &state, &Live)?;
+
+    let _ = fs::remove_file(&path);
+    let _ = reaper.join();
+    eprintln!("mole daemon: stopped (tunnels keep running)");
+    Ok(())
+}
+
+/// Answer connections on a non-blocking `listener`, each in its own thread,
+/// until a signal or a shutdown request.
+fn accept(listener: &UnixListener, state: &Shared, launcher: &'static dyn Launcher) -> Result<()> {
+    while !STOP.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let state = Arc::clone(state);
+                std::thread::spawn(move || {
+                    if let Err(e) = handle(stream, &state, launcher) {
+                        eprintln!("mole daemon: {:#}", e);
+                    }
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err(e).context("failed to accept connection"),
+        }
+    }
+    Ok(())
+}
+
+/// Reap exited children and periodically adopt tunnels started elsewhere.
+fn watch(state: &Shared, launcher: &dyn Launcher) {
+    let mut last_adopt = Instant::now();
+    while !STOP.load(Ordering::SeqCst) {
+        std::thread::sleep(REAP_INTERVAL);
+        {
+            let mut state = state.lock().unwrap();
+            let mut exited = Vec::new();
+            for (name, entry) in state.tunnels.iter_mut() {
+                let status = match entry.child {
+                    Some(ref mut child) => match child.try_wait() {
+                        Ok(Some(status)) => Some(status.to_string()),
+                        Ok(None) => None,
+                        Err(e) => Some(e.to_string()),
+                    },
+                    None if !process::runs_tunnel(entry.pid, name) => Some("gone".to_string()),
+                    None => None,
+                };
+                if let Some(status) = status {
+                    exited.push((name.clone(), status));
+                }
+            }
+            for (name, status) in exited {
+                state.tunnels.remove(&name);
+                launcher.forget(&name);
+                state.emit(Event::Exited { name, status });
+            }
+        }
+        if last_adopt.elapsed() >= ADOPT_INTERVAL {
+            adopt(state, launcher);
+            last_adopt = Instant::now();
+        }
+    }
+}
+
+/// Pick up running tunnels the daemon didn't start, from one process table scan.
+fn adopt(state: &Shared, launcher: &dyn Launcher) {
+    // This runs every few seconds: don't rerun the user's Match exec commands each time
+    let Ok((_, tunnels)) = launcher.discover(&launcher.config(), Exec::Skip) else {
+        return;
+    };
+    let unknown: Vec<&str> = {
+        let state = state.lock().unwrap();
+        tunnels
+            .iter()
+            .map(|t| t.name.as_str())
+            .filter(|name| !state.tunnels.contains_key(*name) && !state.busy.contains(*name))
+            .collect()
+    };
+    if unknown.is_empty() {
+        return;
+    }
+    for (name, running) in process::running_tunnels(unknown) {
+        let mut state = state.lock().unwrap();
+        // A CLI request may have claimed the tunnel while we were scanning
+        if state.tunnels.contains_key(&name) || state.busy.contains(&name) {
+            continue;
+        }
+        state.tunnels.insert(
+            name.clone(),
+            Entry {
+                pid: running.pid,
+                started: running.started.unwrap_or_else(now),
+                child: None,
+            },
+        );
+        state.emit(Event::Adopted {
+            name,
+            pid: running.pid,
+        });
+    }
+}
+
+fn handle(stream: UnixStream, state: &Shared, launcher: &dyn Launcher) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    let reader = BufReader::new(stream.try_clone()?);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request: Request = match serde_json::from_str(&line) {
+            Ok(r) => r,
+            Err(e) => {
+                reply(&mut writer, &Response::Error {
+                    message: format!("invalid request: {}", e),
+                })?;
+                continue;
+            }
+        };
+        match request {
+            Request::Subscribe => {
+                state.lock().unwrap().subscribers.push(stream.try_clone()?);
+                return Ok(());
+            }
+            Request::Logs {
+                name,
+                lines,
+                follow,
+            } => {
+                if let Err(e) = stream_log(&mut writer, &name, lines, follow) {
+                    reply(&mut writer, &Response::Error {
+                        message: format!("{:#}", e),
+                    })?;
+                }
+            }
+            Request::Shutdown => {
+                STOP.store(true, Ordering::SeqCst);
+                reply(&mut writer, &Response::Ok)?;
+            }
+            request => {
+                let response = respond(request, state, launcher).unwrap_or_else(|e| Response::Error {
+                    message: format!("{:#}", e),
+                });
+                reply(&mut writer, &response)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn reply(writer: &mut UnixStream, response: &Response) -> Result<()> {
+    let mut line = serde_json::to_string(response)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn respond(request: Request, state: &Shared, launcher: &dyn Launcher) -> Result<Response> {
+    match request {
+        Request::List => Ok(Response::Tunnels {
+            tunnels: state.lock().unwrap().snapshot(),
+        }),
+        Request::Up { name } => up(&name, state, launcher).map(|pid| Response::Started { pid }),
+        Request::Down { name } => down(&name, state, launcher).map(|()| Response::Ok),
+        Request::Logs { .. } | Request::Subscribe | Request::Shutdown => {
+            unreachable!("handled by the connection loop")
+        }
+    }
+}
+
+fn up(name: &str, state: &Shared, launcher: &dyn Launcher) -> Result<u32> {
+    // Re-read config on every start so edits take effect without restarting the daemon
+    let cfg = launcher.config();
+    let (root, tunnels) = launcher.discover(&cfg, Exec::Run)?;
+    let tunnel = tunnels
+        .iter()
+        .find(|t| t.has_name(name))
+        .with_context(|| format!("tunnel '{}' not found in SSH config", name))?;
+
+    {
+        let mut state = state.lock().unwrap();
+        if state.tunnels.contains_key(&tunnel.name) || !state.busy.insert(tunnel.name.clone())
+        {
+            anyhow::bail!("tunnel '{}' is already active", tunnel.name);
+        }
+    }
+    // Spawning waits a moment for fast failures; don't hold the lock meanwhile
+    let spawned = launcher.spawn(tunnel, &root, &cfg);
+
+    let mut state = state.lock().unwrap();
+    state.busy.remove(&tunnel.name);
+    let child = spawned?;
+    let pid = child.id();
+    state.tunnels.insert(
+        tunnel.name.clone(),
+        Entry {
+            pid,
+            started: now(),
+            child: Some(child),
+        },
+    );
+    state.emit(Event::Started {
+        name: tunnel.name.clone(),
+        pid,
+    });
+    Ok(pid)
+}
+
+fn down(name: &str, state: &Shared, launcher: &dyn Launcher) -> Result<()> {
+    let entry = {
+        let mut state = state.lock().unwrap();
+        let entry = state
+            .tunnels
+            .remove(name)
+            .with_context(|| format!("tunnel '{}' is not active", name))?;
+        if entry.child.is_none() && !process::runs_tunnel(entry.pid, name) {
+            anyhow::bail!("tunnel '{}' is not active (pid {} exited)", name, entry.pid);
+        }
+        state.busy.insert(name.to_string());
+        entry
+    };
+
+    // Stopping waits for the process and its ports; don't hold the lock meanwhile
+    let cfg = launcher.config();
+    let tunnel = launcher
+        .discover(&cfg, Exec::Run)
+        .ok()
+        .and_then(|(_, tunnels)| tunnels.into_iter().find(|t| t.name == name));
+    let listeners: Vec<&Endpoint> = tunnel.iter().flat_map(|t| t.local_listeners()).collect();
+    let pid = entry.pid;
+    let mut child = entry.child;
+    let result = process::terminate(
+        pid,
+        &listeners,
+        Duration::from_secs(cfg.stop_timeout),
+        || match child {
+            Some(ref mut child) => matches!(child.try_wait(), Ok(Some(_))),
+            None => !process::is_pid_alive(pid),
+        },
+    );
+    launcher.forget(name);
+
+    let mut state = state.lock().unwrap();
+    state.busy.remove(name);
+    state.emit(Event::Stopped {
+        name: name.to_string(),
+    });
+    result
+}
+
+/// Send the last `lines` lines of a tunnel's log and `Ok`, then (with follow)
+/// whatever gets appended, until the client disconnects.
+fn stream_log(writer: &mut UnixStream, name: &str, lines: usize, follow: bool) -> Result<()> {
+    let path = process::log_file(name)?;
+    let mut file =
+        fs::File::open(&path).with_context(|| format!("no log file for '{}'", name))?;
+
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let all: Vec<&str> = content.lines().collect();
+    for text in &all[all.len().saturating_sub(lines)..] {
+        reply(writer, &Response::Line {
+            text: text.to_string(),
+        })?;
+    }
+    reply(writer, &Response::Ok)?;
+    if !follow {
+        return Ok(());
+    }
+
+    let mut pos = file.stream_position()?;
+    let mut partial = String::new();
+    loop {
+        std::thread::sleep(FOLLOW_INTERVAL);
+        let len = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        if len < pos {
+            // Rotated or truncated: start from the top of the new file
+            file = fs::File::open(&path)?;
+            pos = 0;
+        }
+        file.seek(SeekFrom::Start(pos))?;
+        let mut chunk = String::new();
+        pos += file.read_to_string(&mut chunk)? as u64;
+        partial.push_str(&chunk);
+        while let Some(end) = partial.find('\n') {
+            let text: String = partial.drain(..=end).collect();
+            reply(writer, &Response::Line {
+                text: text.trim_end_matches('\n').to_string(),
+            })?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_wire_format() {
+        let json = serde_json::to_string(&Request::Up {
+            name: "db".to_string(),
+        })
+        .unwrap();
+        assert_eq!(json, r#"{"request":"up","name":"db"}"#);
+        let parsed: Request = serde_json::from_str(r#"{"request":"list"}"#).unwrap();
+        assert!(matches!(parsed, Request::List));
+    }
+
+    #[test]
+    fn event_wire_format() {
+        let json = serde_json::to_string(&Response::Event {
+            event: Event::Exited {
+                name: "db".to_string(),
+                status: "exit status: 255".to_string(),
+            },
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            r#"{"response":"event","event":{"event":"exited","name":"db","status":"exit status: 255"}}"#
+        );
+    }
+
+    #[test]
+    fn snapshot_is_sorted() {
+        let mut state = State::default();
+        for (name, pid) in [("web", 2), ("db", 1)] {
+            state.tunnels.insert(
+                name.to_string(),
+                Entry {
+                    pid,
+                    started: 0,
+                    child: None,
+                },
+            );
+        }
+        let names: Vec<String> = state.snapshot().into_iter().map(|t| t.name).collect();
+        assert_eq!(names, ["db", "web"]);
+    }
+
+    /// Runs `sleep` for tunnel "a" and leaves the user's files alone.
+    struct Fake;
+
+    impl Launcher for Fake {
+        fn config(&self) -> Config {
+            Config {
+                stop_timeout: 1,
+                ..Config::default()
+            }
+        }
+
+        fn discover(&self, _: &Config, _: Exec) -> Result<(ConfigRoot, Vec<TunnelHost>)> {
+            let root = ConfigRoot {
+                path: std::path::PathBuf::from("/dev/null"),
+                ssh_dir: std::env::temp_dir(),
+                custom: true,
+            };
+            Ok((root, vec![TunnelHost::named("a")]))
+        }
+
+        fn spawn(&self, _: &TunnelHost, _: &ConfigRoot, _: &Config) -> Result<Child> {
+            Ok(std::process::Command::new("sleep").arg("30").spawn()?)
+        }
+
+        fn forget(&self, _: &str) {}
+    }
+
+    fn socket_in(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("mole_test_daemon_{}", test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("mole.sock")
+    }
+
+    #[test]
+    fn up_list_down_over_socket() {
+        let path = socket_in("round_trip");
+        let listener = UnixListener::bind(&path).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let server = std::thread::spawn(move || accept(&listener, &Arc::default(), &Fake));
+
+        let mut client = Client::connect_to(&path, REPLY_TIMEOUT).unwrap();
+        let pid = client.up("a").unwrap();
+        let tunnels = client.list().unwrap();
+        assert_eq!(tunnels.len(), 1);
+        assert_eq!((tunnels[0].name.as_str(), tunnels[0].pid, tunnels[0].owned), ("a", pid, true));
+        assert!(client.up("a").is_err());
+        assert!(client.up("b").is_err());
+
+        client.down("a", Duration::from_secs(1)).unwrap();
+        assert!(client.list().unwrap().is_empty());
+        assert!(client.down("a", Duration::from_secs(1)).is_err());
+
+        client.shutdown().unwrap();
+        server.join().unwrap().unwrap();
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn silent_daemon_times_out() {
+        let path = socket_in("silent");
+        // Bound but never accepted: connecting works, nothing ever answers
+        let _listener = UnixListener::bind(&path).unwrap();
+        let mut client = Client::connect_to(&path, Duration::from_millis(200)).unwrap();
+        let started = Instant::now();
+        let err = client.list().unwrap_err();
+        assert!(err.is::<Unresponsive>(), "{:#}", err);
+        assert!(started.elapsed() < REPLY_TIMEOUT);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
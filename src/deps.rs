@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+
+use crate::tunnel::TunnelHost;
+
+/// The tunnels `tunnel` depends on (`# mole:depends=`), looked up by name or
+/// alias. Fails on the first name that isn't a tunnel.
+fn dependencies<'a>(all: &'a [TunnelHost], tunnel: &TunnelHost) -> Result<Vec<&'a TunnelHost>> {
+    tunnel
+        .depends
+        .iter()
+        .map(|name| {
+            all.iter()
+                .find(|t| t.has_name(name))
+                .ok_or_else(|| anyhow::anyhow!(unknown(&tunnel.name, name)))
+        })
+        .collect()
+}
+
+fn unknown(tunnel: &str, name: &str) -> String {
+    format!("tunnel '{}' depends on '{}', which is not a tunnel in the SSH config", tunnel, name)
+}
+
+/// `targets` and everything they depend on, directly or not, with every
+/// tunnel after its dependencies: the order to start them in.
+pub fn with_dependencies<'a>(all: &'a [TunnelHost], targets: &[&'a TunnelHost]) -> Result<Vec<&'a TunnelHost>> {
+    let mut order = Vec::new();
+    let mut done = HashSet::new();
+    for target in targets {
+        visit(all, target, &mut Vec::new(), &mut done, &mut order)?;
+    }
+    Ok(order)
+}
+
+/// Depth-first walk appending `tunnel` after its dependencies; `path` is the
+/// chain being walked, so meeting a tunnel on it again is a cycle.
+fn visit<'a>(
+    all: &'a [TunnelHost],
+    tunnel: &'a TunnelHost,
+    path: &mut Vec<&'a str>,
+    done: &mut HashSet<&'a str>,
+    order: &mut Vec<&'a TunnelHost>,
+) -> Result<()> {
+    if done.contains(tunnel.name.as_str()) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|n| *n == tunnel.name) {
+        let cycle = canonical_cycle(&path[start..]);
+        anyhow::bail!("dependency cycle: {}", cycle.join(" → "));
+    }
+    path.push(&tunnel.name);
+    for dep in dependencies(all, tunnel)? {
+        visit(all, dep, path, done, order)?;
+    }
+    path.pop();
+    done.insert(&tunnel.name);
+    order.push(tunnel);
+    Ok(())
+}
+
+/// A cycle starting from its alphabetically first member and closed with it
+/// again, so the same cycle reads the same wherever the walk entered it.
+fn canonical_cycle(members: &[&str]) -> Vec<String> {
+    let first = (0..members.len()).min_by_key(|&i| members[i]).unwrap_or(0);
+    let mut cycle: Vec<String> = members[first..]
+        .iter()
+        .chain(&members[..first])
+        .map(|n| n.to_string())
+        .collect();
+    cycle.push(cycle[0].clone());
+    cycle
+}
+
+/// `targets` and every tunnel that depends on them, directly or not, with
+/// every tunnel before its dependencies: the order to stop them in.
+pub fn with_dependents<'a>(all: &'a [TunnelHost], targets: &[&'a TunnelHost]) -> Result<Vec<&'a TunnelHost>> {
+    let mut wanted: HashSet<&str> = targets.iter().map(|t| t.name.as_str()).collect();
+    loop {
+        let mut grew = false;
+        for tunnel in all {
+            if !wanted.contains(tunnel.name.as_str())
+                && dependencies(all, tunnel)?.iter().any(|d| wanted.contains(d.name.as_str()))
+            {
+                wanted.insert(&tunnel.name);
+                grew = true;
+            }
+        }
+        if !grew {
+            break;
+        }
+    }
+
+    let members: Vec<&TunnelHost> = all.iter().filter(|t| wanted.contains(t.name.as_str())).collect();
+    let mut order = with_dependencies(all, &members)?;
+    order.retain(|t| wanted.contains(t.name.as_str()));
+    order.reverse();
+    Ok(order)
+}
+
+/// Every dependency problem in the config, one message per unknown name or
+/// distinct cycle, as (tunnel, message).
+pub fn problems(all: &[TunnelHost]) -> Vec<(String, String)> {
+    let mut found: Vec<(String, String)> = Vec::new();
+    // Report unknown names, then look for cycles among the names that do resolve
+    let mut known = all.to_vec();
+    for tunnel in &mut known {
+        let tunnel_name = tunnel.name.clone();
+        tunnel.depends.retain(|name| {
+            let exists = all.iter().any(|t| t.has_name(name));
+            if !exists {
+                found.push((tunnel_name.clone(), unknown(&tunnel_name, name)));
+            }
+            exists
+        });
+    }
+    for tunnel in &known {
+        if let Err(e) = with_dependencies(&known, &[tunnel]) {
+            let message = e.to_string();
+            if !found.iter().any(|(_, m)| *m == message) {
+                found.push((tunnel.name.clone(), message));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tunnel(name: &str, depends: &[&str]) -> TunnelHost {
+        TunnelHost {
+            depends: depends.iter().map(|d| d.to_string()).collect(),
+            ..TunnelHost::named(name)
+        }
+    }
+
+    fn names(tunnels: &[&TunnelHost]) -> Vec<String> {
+        tunnels.iter().map(|t| t.name.clone()).collect()
+    }
+
+    fn by_name<'a>(all: &'a [TunnelHost], name: &str) -> &'a TunnelHost {
+        all.iter().find(|t| t.name == name).unwrap()
+    }
+
+    #[test]
+    fn dependencies_start_first() {
+        let all = [
+            tunnel("api", &["socks", "db"]),
+            tunnel("db", &["socks"]),
+            tunnel("socks", &[]),
+            tunnel("other", &[]),
+        ];
+        let order = with_dependencies(&all, &[by_name(&all, "api")]).unwrap();
+        assert_eq!(names(&order), ["socks", "db", "api"]);
+    }
+
+    #[test]
+    fn shared_dependencies_appear_once() {
+        let all = [tunnel("a", &["socks"]), tunnel("b", &["socks"]), tunnel("socks", &[])];
+        let targets: Vec<&TunnelHost> = all.iter().collect();
+        let order = with_dependencies(&all, &targets).unwrap();
+        assert_eq!(names(&order), ["socks", "a", "b"]);
+    }
+
+    #[test]
+    fn dependents_stop_first() {
+        let all = [
+            tunnel("api", &["db"]),
+            tunnel("db", &["socks"]),
+            tunnel("socks", &[]),
+            tunnel("web", &["socks"]),
+            tunnel("other", &[]),
+        ];
+        let order = with_dependents(&all, &[by_name(&all, "socks")]).unwrap();
+        let order = names(&order);
+        assert_eq!(order.len(), 4);
+        assert_eq!(order.last().unwrap(), "socks");
+        let pos = |n: &str| order.iter().position(|o| o == n).unwrap();
+        assert!(pos("api") < pos("db"));
+    }
+
+    #[test]
+    fn dependencies_by_alias() {
+        let mut bastion = tunnel("bastion", &[]);
+        bastion.aliases.push("jump".to_string());
+        let all = [tunnel("db", &["jump"]), bastion];
+        let order = with_dependencies(&all, &[&all[0]]).unwrap();
+        assert_eq!(names(&order), ["bastion", "db"]);
+    }
+
+    #[test]
+    fn missing_dependency_is_named() {
+        let all = [tunnel("db", &["socks"])];
+        let err = with_dependencies(&all, &[&all[0]]).unwrap_err().to_string();
+        assert_eq!(err, "tunnel 'db' depends on 'socks', which is not a tunnel in the SSH config");
+    }
+
+    #[test]
+    fn cycles_are_named() {
+        let all = [tunnel("b", &["c"]), tunnel("c", &["a"]), tunnel("a", &["b"]), tunnel("d", &["a"])];
+        let err = with_dependencies(&all, &[&all[3]]).unwrap_err().to_string();
+        assert_eq!(err, "dependency cycle: a → b → c → a");
+
+        // Reported once however many tunnels lead into it
+        let problems = problems(&all);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].1, "dependency cycle: a → b → c → a");
+    }
+
+    #[test]
+    fn self_dependency_is_a_cycle() {
+        let all = [tunnel("a", &["a"])];
+        assert_eq!(problems(&all)[0].1, "dependency cycle: a → a");
+    }
+}
@@ -1,68 +1,34 @@
+use std::sync::Arc;
+
 use colored::Colorize;
-use console::Alignment;
+use console::{Alignment, Term};
 
-use crate::health;
-use crate::launchd;
-use crate::process;
+use crate::config::Config;
+use crate::persist;
+use crate::process::{self, Running};
+use crate::proctable;
+use crate::status::{self, Probe};
+use crate::ssh_config::ConfigRoot;
 use crate::tunnel::TunnelHost;
 
-/// Print a formatted list of all tunnels with their status.
-pub fn print_tunnel_list(tunnels: &[TunnelHost]) {
+/// Print a formatted list of all tunnels with their status. Process state comes
+/// from one scan; ports are probed concurrently, and on a terminal each row's
+/// health fills in as its probes finish (elsewhere rows print in order once known).
+pub fn print_tunnel_list(tunnels: &[TunnelHost], root: &ConfigRoot, cfg: &Config) {
     if tunnels.is_empty() {
-        println!("{}", "No tunnels found in ~/.ssh/config".yellow());
+        println!("{}", format!("No tunnels found in {}", root.path.display()).yellow());
         println!("Add a Host block with LocalForward, RemoteForward, or DynamicForward to get started.");
         return;
     }
 
-    // Pre-compute all row data
-    let mut rows: Vec<Row> = Vec::new();
-    for tunnel in tunnels {
-        let active = process::is_active(&tunnel.name).unwrap_or(false);
-        let enabled = launchd::is_enabled(&tunnel.name);
-        let mut fwd_parts: Vec<String> = tunnel.forwards.iter().map(|f| f.to_string()).collect();
-        fwd_parts.extend(tunnel.remote_forwards.iter().map(|f| f.to_string()));
-        fwd_parts.extend(tunnel.dynamic_forwards.iter().map(|f| f.to_string()));
-        let fwd_str = fwd_parts.join(", ");
-        let has_local_forwards = !tunnel.forwards.is_empty() || !tunnel.dynamic_forwards.is_empty();
-
-        if active {
-            let pid = process::read_pid(&tunnel.name).ok().flatten();
-            let uptime = process::get_start_time(&tunnel.name)
-                .ok()
-                .flatten()
-                .map(process::format_uptime)
-                .unwrap_or_default();
-            let healthy = if has_local_forwards {
-                let local_ok = tunnel.forwards.iter().all(|f| health::check_port(f.local_port));
-                let dynamic_ok = tunnel.dynamic_forwards.iter().all(|f| health::check_port(f.listen_port));
-                Some(local_ok && dynamic_ok)
-            } else {
-                None // remote-only tunnels can't be probed locally
-            };
-
-            rows.push(Row {
-                name: tunnel.name.clone(),
-                group: tunnel.group.clone(),
-                active: true,
-                status: format!("up {}", uptime),
-                healthy,
-                pid,
-                fwd_str,
-                enabled,
-            });
-        } else {
-            rows.push(Row {
-                name: tunnel.name.clone(),
-                group: tunnel.group.clone(),
-                active: false,
-                status: "inactive".to_string(),
-                healthy: None,
-                pid: None,
-                fwd_str,
-                enabled,
-            });
-        }
-    }
+    let status = status::collect(tunnels, Arc::new(status::Live), status::DEADLINE);
+    // Read while the ports are probed
+    let survey = persist::Survey::take(tunnels, root, cfg);
+    let mut rows: Vec<Row> = tunnels
+        .iter()
+        .zip(&status.running)
+        .map(|(tunnel, running)| Row::new(tunnel, running.as_ref(), &survey))
+        .collect();
 
     // Column widths from plain text (name + optional group badge)
     let w_name = rows
@@ -75,73 +41,201 @@ pub fn print_tunnel_list(tunnels: &[TunnelHost]) {
         .unwrap_or(0);
     let w_status = rows.iter().map(|r| r.status.len()).max().unwrap_or(0);
 
-    for row in &rows {
-        let bullet = if row.active {
+    let term = Term::stdout();
+    // Rows can only be redrawn while they are all still on screen
+    let live = term.is_term() && rows.len() < term.size().0 as usize;
+    if live {
+        for row in &rows {
+            let _ = term.write_line(&row.render(w_name, w_status));
+        }
+    }
+
+    let mut done = vec![false; rows.len()];
+    let mut printed = 0;
+    for report in status.reports {
+        let up = rows.len() - report.tunnel;
+        let row = &mut rows[report.tunnel];
+        row.health = Health::of(row.active, &report.probes);
+        done[report.tunnel] = true;
+
+        if !live {
+            while printed < rows.len() && done[printed] {
+                println!("{}", rows[printed].render(w_name, w_status));
+                printed += 1;
+            }
+        } else if !report.probes.is_empty() {
+            // Rows without probes were final when first drawn
+            let _ = term.move_cursor_up(up);
+            let _ = term.clear_line();
+            let _ = term.write_line(&row.render(w_name, w_status));
+            if up > 1 {
+                let _ = term.move_cursor_down(up - 1);
+            }
+        }
+    }
+
+    if rows.iter().any(|r| r.drifted) {
+        println!();
+        println!(
+            "{}",
+            "Auto-start entries marked drifted no longer match this config; run `mole enable --sync --all`."
+                .yellow()
+        );
+    }
+}
+
+/// CPU time the way ps shows it: "0:42", "12:05", "3:02:17".
+fn format_cpu(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
+
+/// Pad an ANSI-colored string to a visible width using console's awareness of escape codes.
+fn pad(s: &str, width: usize) -> String {
+    console::pad_str(s, width, Alignment::Left, None).to_string()
+}
+
+/// Health column of a row.
+enum Health {
+    /// Not running, or nothing that can be probed locally (remote-only).
+    Unknown,
+    /// Probes still in flight.
+    Pending,
+    Healthy,
+    Unhealthy,
+    /// Some probe didn't answer before the deadline.
+    TimedOut,
+}
+
+impl Health {
+    fn of(active: bool, probes: &[Probe]) -> Self {
+        if !active || probes.is_empty() {
+            Health::Unknown
+        } else if probes.iter().any(|p| matches!(p, Probe::Failed(_))) {
+            Health::Unhealthy
+        } else if probes.contains(&Probe::TimedOut) {
+            Health::TimedOut
+        } else {
+            Health::Healthy
+        }
+    }
+}
+
+struct Row {
+    name: String,
+    group: Option<String>,
+    active: bool,
+    status: String,
+    health: Health,
+    pid: Option<u32>,
+    /// Who keeps the process up: mole, or the service manager running it
+    owner: Option<&'static str>,
+    /// Memory and CPU of the tunnel's process tree
+    usage: Option<String>,
+    fwd_str: String,
+    enabled: bool,
+    /// The auto-start entry differs from what mole would write now
+    drifted: bool,
+}
+
+impl Row {
+    fn new(tunnel: &TunnelHost, running: Option<&Running>, survey: &persist::Survey) -> Self {
+        let mut fwd_parts: Vec<String> = tunnel.forwards.iter().map(|f| f.to_string()).collect();
+        fwd_parts.extend(tunnel.remote_forwards.iter().map(|f| f.to_string()));
+        fwd_parts.extend(tunnel.dynamic_forwards.iter().map(|f| f.to_string()));
+        let has_local_forwards = tunnel.local_listeners().next().is_some();
+
+        let mut row = Row {
+            name: tunnel.name.clone(),
+            group: tunnel.group.clone(),
+            active: false,
+            status: "inactive".to_string(),
+            health: Health::Unknown,
+            pid: None,
+            owner: None,
+            usage: None,
+            fwd_str: fwd_parts.join(", "),
+            enabled: survey.is_enabled(&tunnel.name),
+            drifted: survey.is_drifted(&tunnel.name),
+        };
+        if let Some(run) = running {
+            let uptime = run.started.map(process::format_uptime).unwrap_or_default();
+            row.active = true;
+            row.status = format!("up {}", uptime);
+            if has_local_forwards {
+                row.health = Health::Pending;
+            }
+            row.pid = Some(run.pid);
+            row.owner = Some(survey.owner(&tunnel.name, run.pid).name());
+            row.usage = Some(format!(
+                "{} · cpu {}",
+                proctable::format_rss(run.rss_kb),
+                format_cpu(run.cpu_secs)
+            ));
+        }
+        row
+    }
+
+    fn render(&self, w_name: usize, w_status: usize) -> String {
+        let bullet = if self.active {
             "●".green().to_string()
         } else {
             "○".dimmed().to_string()
         };
 
-        let name_with_badge = if let Some(ref g) = row.group {
-            let badge = format!(" [{}]", g).dimmed().to_string();
-            let name_colored = if row.active {
-                row.name.green().bold().to_string()
-            } else {
-                row.name.to_string()
-            };
-            format!("{}{}", name_colored, badge)
+        let name_colored = if self.active {
+            self.name.green().bold().to_string()
         } else {
-            if row.active {
-                row.name.green().bold().to_string()
-            } else {
-                row.name.to_string()
-            }
+            self.name.to_string()
+        };
+        let name_with_badge = match self.group {
+            Some(ref g) => format!("{}{}", name_colored, format!(" [{}]", g).dimmed()),
+            None => name_colored,
         };
         let name_pad = pad(&name_with_badge, w_name);
 
-        let status_colored = if row.active {
-            row.status.green().to_string()
+        let status_colored = if self.active {
+            self.status.green().to_string()
         } else {
-            row.status.dimmed().to_string()
+            self.status.dimmed().to_string()
         };
         let status_pad = pad(&status_colored, w_status);
 
         // Measure actual display width of health icons so the placeholder
         // matches even when ✓/✗ render as double-width in some fonts.
         let w_health = console::measure_text_width("✓").max(1);
-        let health = match row.healthy {
-            Some(true) => pad(&"✓".green().to_string(), w_health),
-            Some(false) => pad(&"✗".red().to_string(), w_health),
-            None => " ".repeat(w_health),
+        let health = match self.health {
+            Health::Healthy => pad(&"✓".green().to_string(), w_health),
+            Health::Unhealthy => pad(&"✗".red().to_string(), w_health),
+            Health::TimedOut => pad(&"?".yellow().to_string(), w_health),
+            Health::Pending => pad(&"…".dimmed().to_string(), w_health),
+            Health::Unknown => " ".repeat(w_health),
         };
 
-        let fwd = row.fwd_str.dimmed().to_string();
+        let fwd = self.fwd_str.dimmed().to_string();
 
         let mut suffix = String::new();
-        if let Some(p) = row.pid {
-            suffix.push_str(&format!("  {}", format!("pid {}", p).dimmed()));
+        if let Some(p) = self.pid {
+            let pid = match self.owner {
+                Some(owner) => format!("pid {} ({})", p, owner),
+                None => format!("pid {}", p),
+            };
+            suffix.push_str(&format!("  {}", pid.dimmed()));
         }
-        if row.enabled {
-            let icon = if row.active { "⏎".green().to_string() } else { "⏎".dimmed().to_string() };
+        if let Some(ref usage) = self.usage {
+            suffix.push_str(&format!("  {}", usage.dimmed()));
+        }
+        if self.drifted {
+            suffix.push_str(&format!("  {}", "⏎ drifted".yellow()));
+        } else if self.enabled {
+            let icon = if self.active { "⏎".green().to_string() } else { "⏎".dimmed().to_string() };
             suffix.push_str(&format!("  {}", icon));
         }
 
-        println!("  {} {}  {}  {}  {}{}", bullet, name_pad, status_pad, health, fwd, suffix);
+        format!("  {} {}  {}  {}  {}{}", bullet, name_pad, status_pad, health, fwd, suffix)
     }
 }
-
-/// Pad an ANSI-colored string to a visible width using console's awareness of escape codes.
-fn pad(s: &str, width: usize) -> String {
-    console::pad_str(s, width, Alignment::Left, None).to_string()
-}
-
-struct Row {
-    name: String,
-    group: Option<String>,
-    active: bool,
-    status: String,
-    healthy: Option<bool>,
-    pid: Option<u32>,
-    fwd_str: String,
-    enabled: bool,
-}
@@ -0,0 +1,446 @@
+use colored::Colorize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config::{Backend, Config, Persistence};
+use crate::paths;
+use crate::persist;
+use crate::process;
+use crate::proctable::ProcessTable;
+use crate::ssh_config::ConfigRoot;
+
+/// Oldest OpenSSH with ProxyJump and Include (7.3).
+const MIN_OPENSSH: (u32, u32) = (7, 3);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// The outcome of one diagnostic, with a concrete fix when it isn't Ok.
+#[derive(Debug)]
+pub struct Check {
+    pub status: Status,
+    pub name: String,
+    pub detail: String,
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &str, detail: impl Into<String>) -> Self {
+        Self {
+            status: Status::Ok,
+            name: name.to_string(),
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warn,
+            name: name.to_string(),
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: Status::Fail,
+            name: name.to_string(),
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+pub struct Section {
+    pub title: &'static str,
+    pub checks: Vec<Check>,
+}
+
+/// An external program mole shells out to.
+struct Tool {
+    name: &'static str,
+    /// What mole uses it for, shown when it's missing.
+    used_for: &'static str,
+    /// Flags that print a version, tried in order.
+    version_flags: &'static [&'static str],
+    install: &'static str,
+    required: bool,
+}
+
+const TOOLS: &[Tool] = &[
+    Tool {
+        name: "autossh",
+        used_for: "starting tunnels",
+        version_flags: &["-V"],
+        install: "install autossh (brew install autossh / apt install autossh / dnf install autossh)",
+        required: true,
+    },
+    Tool {
+        name: "ps",
+        used_for: "reading the process table where /proc isn't available",
+        version_flags: &["--version"],
+        install: "install procps (apt install procps / dnf install procps-ng)",
+        required: cfg!(not(target_os = "linux")),
+    },
+    Tool {
+        name: "tail",
+        used_for: "mole logs",
+        version_flags: &["--version"],
+        install: "install coreutils (apt install coreutils)",
+        required: false,
+    },
+];
+
+/// Run every diagnostic.
+pub fn run(root: &ConfigRoot, cfg: &Config) -> Vec<Section> {
+    let mut deps: Vec<Check> = TOOLS
+        .iter()
+        .map(|tool| {
+            // With the built-in supervisor autossh is only needed by tunnels that opt into it
+            let required = tool.required && !(tool.name == "autossh" && cfg.backend == Backend::Ssh);
+            check_tool(tool, required)
+        })
+        .collect();
+    deps.push(check_ssh());
+
+    let mut perms = vec![check_ssh_config(&root.path), check_dir("ssh dir", &root.ssh_dir)];
+    if let Ok(dirs) = paths::dirs() {
+        perms.extend(dirs.iter().map(|(name, dir)| check_dir(name, dir)));
+    }
+
+    let table = ProcessTable::scan();
+    let mut procs = check_pid_files(&table);
+    procs.extend(check_orphans(&table));
+
+    vec![
+        Section {
+            title: "Dependencies",
+            checks: deps,
+        },
+        Section {
+            title: "Permissions",
+            checks: perms,
+        },
+        Section {
+            title: "Processes",
+            checks: procs,
+        },
+        Section {
+            title: "Persistence",
+            checks: vec![check_persistence(cfg)],
+        },
+    ]
+}
+
+/// Print the results. Returns true if any check failed.
+pub fn print(sections: &[Section]) -> bool {
+    let mut failed = false;
+    for section in sections {
+        println!("{}", section.title.bold());
+        for check in &section.checks {
+            let icon = match check.status {
+                Status::Ok => "✓".green().to_string(),
+                Status::Warn => "⚠".yellow().to_string(),
+                Status::Fail => {
+                    failed = true;
+                    "✗".red().to_string()
+                }
+            };
+            println!("  {} {:<14} {}", icon, check.name, check.detail);
+            if let Some(ref hint) = check.hint {
+                println!("    {} {}", "→".dimmed(), hint.dimmed());
+            }
+        }
+        println!();
+    }
+    failed
+}
+
+/// Find an executable on $PATH without relying on `which`.
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|p| {
+            p.metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+}
+
+/// First non-empty line a program prints for `flag` (stdout or stderr).
+fn version_line(path: &Path, flag: &str) -> Option<String> {
+    let output = Command::new(path)
+        .arg(flag)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty())?;
+    // Tools without a version flag print usage instead
+    if line.to_lowercase().contains("usage") || !line.chars().any(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(line.to_string())
+}
+
+fn check_tool(tool: &Tool, required: bool) -> Check {
+    let Some(path) = find_in_path(tool.name) else {
+        let detail = format!("not found on PATH (needed for {})", tool.used_for);
+        return if required {
+            Check::fail(tool.name, detail, tool.install)
+        } else {
+            Check::warn(tool.name, detail, tool.install)
+        };
+    };
+    let version = tool
+        .version_flags
+        .iter()
+        .find_map(|flag| version_line(&path, flag))
+        .unwrap_or_else(|| "version unknown".to_string());
+    Check::ok(tool.name, format!("{} ({})", version, path.display()))
+}
+
+/// Parse "OpenSSH_9.6p1 Ubuntu-3ubuntu13, OpenSSL 3.0.13" into (9, 6).
+fn parse_openssh_version(line: &str) -> Option<(u32, u32)> {
+    let rest = line.split("OpenSSH_").nth(1)?;
+    let mut parts = rest.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+fn check_ssh() -> Check {
+    let Some(path) = find_in_path("ssh") else {
+        return Check::fail(
+            "ssh",
+            "not found on PATH (every tunnel runs it)",
+            "install the OpenSSH client (apt install openssh-client / dnf install openssh-clients)",
+        );
+    };
+    let Some(line) = version_line(&path, "-V") else {
+        return Check::warn(
+            "ssh",
+            format!("cannot read version ({})", path.display()),
+            "run `ssh -V` to check the client works",
+        );
+    };
+    match parse_openssh_version(&line) {
+        Some(v) if v < MIN_OPENSSH => Check::warn(
+            "ssh",
+            line,
+            format!(
+                "OpenSSH {}.{}+ is needed for ProxyJump and Include; upgrade the ssh client",
+                MIN_OPENSSH.0, MIN_OPENSSH.1
+            ),
+        ),
+        Some(_) => Check::ok("ssh", line),
+        None => Check::warn(
+            "ssh",
+            line,
+            "not OpenSSH; mole's config handling follows OpenSSH semantics",
+        ),
+    }
+}
+
+/// ssh refuses a config that is writable by others or owned by someone else.
+fn check_ssh_config(path: &Path) -> Check {
+    let name = "ssh config";
+    let meta = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(_) => {
+            return Check::fail(
+                name,
+                format!("{} not found", path.display()),
+                format!(
+                    "create it (touch {p} && chmod 600 {p}) or set ssh_config with `mole config`",
+                    p = path.display()
+                ),
+            );
+        }
+    };
+    let uid = unsafe { libc::getuid() };
+    if meta.uid() != uid && meta.uid() != 0 {
+        return Check::fail(
+            name,
+            format!("{} is owned by uid {}", path.display(), meta.uid()),
+            format!("chown {} {}", whoami::username(), path.display()),
+        );
+    }
+    match permission_problem(meta.mode(), 0o022) {
+        Some(mode) => Check::fail(
+            name,
+            format!("{} has mode {:o}; ssh rejects group/world-writable configs", path.display(), mode),
+            format!("chmod 600 {}", path.display()),
+        ),
+        None => Check::ok(name, format!("{} ({:o})", path.display(), meta.mode() & 0o777)),
+    }
+}
+
+/// ~/.ssh and mole's directories should only be writable by their owner.
+fn check_dir(name: &str, path: &Path) -> Check {
+    let meta = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(_) => return Check::ok(name, format!("{} (not created yet)", path.display())),
+    };
+    if !meta.is_dir() {
+        return Check::fail(
+            name,
+            format!("{} is not a directory", path.display()),
+            format!("move {} out of the way", path.display()),
+        );
+    }
+    match permission_problem(meta.mode(), 0o022) {
+        Some(mode) => Check::warn(
+            name,
+            format!("{} has mode {:o}; others can write to it", path.display(), mode),
+            format!("chmod 700 {}", path.display()),
+        ),
+        None => Check::ok(name, format!("{} ({:o})", path.display(), meta.mode() & 0o777)),
+    }
+}
+
+/// Returns the permission bits if any of `forbidden` are set.
+fn permission_problem(mode: u32, forbidden: u32) -> Option<u32> {
+    let mode = mode & 0o777;
+    (mode & forbidden != 0).then_some(mode)
+}
+
+fn check_pid_files(table: &ProcessTable) -> Vec<Check> {
+    let entries = match process::pid_files() {
+        Ok(e) => e,
+        Err(e) => {
+            return vec![Check::warn(
+                "pid files",
+                format!("cannot read PID directory: {}", e),
+                "check the pid dir's permissions above",
+            )];
+        }
+    };
+    let stale: Vec<&(String, Option<u32>)> = entries
+        .iter()
+        .filter(|(name, _)| !process::pid_file_is_live(name, table))
+        .collect();
+    if stale.is_empty() {
+        return vec![Check::ok("pid files", format!("{} tracked, none stale", entries.len()))];
+    }
+    stale
+        .iter()
+        .map(|(name, pid)| {
+            let path = process::pid_file(name)
+                .map(|p| p.display().to_string())
+                .unwrap_or_default();
+            let detail = match pid {
+                Some(pid) => format!(
+                    "'{}' points at pid {}, which is no longer running the tunnel",
+                    name, pid
+                ),
+                None => format!("'{}' has an unreadable PID file", name),
+            };
+            Check::warn("stale pid", detail, format!("rm {}", path))
+        })
+        .collect()
+}
+
+fn check_orphans(table: &ProcessTable) -> Vec<Check> {
+    let tracked: Vec<u32> = process::pid_files()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(_, pid)| pid)
+        .collect();
+    let mut by_tunnel: BTreeMap<String, Vec<(u32, String)>> = BTreeMap::new();
+    for p in table.iter() {
+        if let Some(name) = process::tunnel_of(&p.argv) {
+            by_tunnel
+                .entry(name.to_string())
+                .or_default()
+                .push((p.pid, p.argv.join(" ")));
+        }
+    }
+    for procs in by_tunnel.values_mut() {
+        procs.sort();
+    }
+
+    let mut checks = Vec::new();
+    for (name, procs) in &by_tunnel {
+        if procs.len() > 1 {
+            let pids: Vec<String> = procs.iter().map(|(pid, _)| pid.to_string()).collect();
+            checks.push(Check::fail(
+                "duplicate",
+                format!("{} processes run tunnel '{}' (pids {})", procs.len(), name, pids.join(", ")),
+                "mole won't adopt any of them; kill all but one",
+            ));
+            continue;
+        }
+        let (pid, command) = &procs[0];
+        if !tracked.contains(pid) {
+            checks.push(Check::warn(
+                "orphan",
+                format!("pid {}: {}", pid, command),
+                format!("`mole list` adopts it if '{}' is a tunnel; otherwise kill {}", name, pid),
+            ));
+        }
+    }
+
+    if checks.is_empty() {
+        checks.push(Check::ok("tunnels", "no untracked or duplicate tunnel processes"));
+    }
+    checks
+}
+
+fn check_persistence(cfg: &Config) -> Check {
+    let name = "auto-start";
+    match persist::chosen(cfg) {
+        Ok(backend) if cfg.persistence == Persistence::Auto => {
+            Check::ok(name, format!("{} (picked by persistence = \"auto\")", backend.name()))
+        }
+        Ok(backend) => Check::ok(name, backend.name()),
+        Err(e) => Check::warn(
+            name,
+            e.to_string(),
+            "set persistence with `mole config` to one available here, or start tunnels from your shell profile",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_openssh_versions() {
+        assert_eq!(
+            parse_openssh_version("OpenSSH_9.6p1 Ubuntu-3ubuntu13, OpenSSL 3.0.13 30 Jan 2024"),
+            Some((9, 6))
+        );
+        assert_eq!(parse_openssh_version("OpenSSH_7.2p2, LibreSSL 2.3.0"), Some((7, 2)));
+        assert_eq!(parse_openssh_version("Dropbear v2022.83"), None);
+        assert!(parse_openssh_version("OpenSSH_7.2p2").unwrap() < MIN_OPENSSH);
+    }
+
+    #[test]
+    fn permission_bits() {
+        assert_eq!(permission_problem(0o100600, 0o022), None);
+        assert_eq!(permission_problem(0o100644, 0o022), None);
+        assert_eq!(permission_problem(0o100664, 0o022), Some(0o664));
+        assert_eq!(permission_problem(0o040777, 0o022), Some(0o777));
+    }
+
+    #[test]
+    fn finds_executables_on_path() {
+        assert!(find_in_path("sh").is_some());
+        assert!(find_in_path("mole-surely-not-a-real-program").is_none());
+    }
+}
@@ -1,32 +1,166 @@
-use std::net::{TcpListener, TcpStream};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
 use std::time::{Duration, Instant};
 
-/// Check if a local port is accepting connections (tunnel is healthy).
-pub fn check_port(port: u16) -> bool {
-    let addr = format!("127.0.0.1:{}", port);
-    TcpStream::connect_timeout(
-        &addr.parse().unwrap(),
-        Duration::from_secs(2),
-    )
-    .is_ok()
+use crate::tunnel::Endpoint;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The addresses ssh listens on for a forward's bind address. No bind address
+/// (or `localhost`) means both loopbacks; `*` means every interface in both
+/// families; anything else is an address literal or resolved like ssh would.
+pub fn bind_addresses(host: Option<&str>, port: u16) -> Vec<SocketAddr> {
+    let loopback = vec![
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), port),
+    ];
+    match host {
+        None => loopback,
+        Some(h) if h.eq_ignore_ascii_case("localhost") => loopback,
+        Some("*") | Some("") => vec![
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port),
+        ],
+        Some(h) => match h.parse::<IpAddr>() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => (h, port)
+                .to_socket_addrs()
+                .map(|addrs| addrs.collect())
+                .unwrap_or_default(),
+        },
+    }
 }
 
-/// Check if a local port is free (not already bound by another process).
-pub fn is_port_free(port: u16) -> bool {
-    TcpListener::bind(format!("127.0.0.1:{}", port)).is_ok()
+/// Addresses to connect to when probing a listener: a wildcard bind is
+/// reachable through the loopback of its family.
+fn probe_addresses(host: Option<&str>, port: u16) -> Vec<SocketAddr> {
+    bind_addresses(host, port)
+        .into_iter()
+        .map(|addr| match addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => {
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+            }
+            IpAddr::V6(ip) if ip.is_unspecified() => {
+                SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), port)
+            }
+            _ => addr,
+        })
+        .collect()
+}
+
+/// Check if a local forward endpoint is accepting connections (tunnel is healthy).
+/// A TCP listener is healthy when any of its addresses accepts; on failure the
+/// error names every address that was tried.
+pub fn check_endpoint(endpoint: &Endpoint) -> Result<(), String> {
+    match endpoint {
+        Endpoint::Tcp { host, port } => {
+            let addrs = probe_addresses(host.as_deref(), *port);
+            if addrs.is_empty() {
+                return Err(format!("{}: cannot resolve bind address", endpoint));
+            }
+            let mut failures = Vec::new();
+            for addr in addrs {
+                match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                    Ok(_) => return Ok(()),
+                    Err(e) => failures.push(format!("{}: {}", addr, short_error(&e))),
+                }
+            }
+            Err(failures.join(", "))
+        }
+        Endpoint::Unix(path) => UnixStream::connect(path)
+            .map(|_| ())
+            .map_err(|e| format!("{}: {}", path, short_error(&e))),
+    }
 }
 
-/// Probe a list of local ports with retries over a timeout period.
-/// Returns true if all ports became reachable within the timeout.
-pub fn wait_healthy_ports(ports: &[u16], timeout: Duration) -> bool {
+/// Check that a local endpoint is free (not already bound by another process).
+/// Returns the bind address that is taken. Addresses this machine doesn't have
+/// (e.g. `::1` with IPv6 disabled) are skipped, as ssh skips them too.
+/// ssh refuses to bind a Unix socket path that already exists.
+pub fn endpoint_conflict(endpoint: &Endpoint) -> Option<String> {
+    match endpoint {
+        Endpoint::Tcp { host, port } => bind_addresses(host.as_deref(), *port)
+            .into_iter()
+            // Each probe listener is dropped before the next bind, so a dual-stack
+            // `[::]` never collides with the `0.0.0.0` probe before it
+            .find(|addr| {
+                matches!(TcpListener::bind(addr), Err(e) if e.kind() == ErrorKind::AddrInUse)
+            })
+            .map(|addr| addr.to_string()),
+        Endpoint::Unix(path) => Path::new(path).exists().then(|| path.clone()),
+    }
+}
+
+/// Probe a list of local endpoints with retries over a timeout period.
+/// Returns the last failure if some endpoint never became reachable.
+pub fn wait_healthy(endpoints: &[&Endpoint], timeout: Duration) -> Result<(), String> {
     let start = Instant::now();
     loop {
-        if ports.iter().all(|&p| check_port(p)) {
-            return true;
+        let failure = endpoints.iter().find_map(|e| check_endpoint(e).err());
+        match failure {
+            None => return Ok(()),
+            Some(f) if start.elapsed() >= timeout => return Err(f),
+            Some(_) => std::thread::sleep(Duration::from_millis(500)),
         }
-        if start.elapsed() >= timeout {
-            return false;
-        }
-        std::thread::sleep(Duration::from_millis(500));
+    }
+}
+
+fn short_error(e: &std::io::Error) -> String {
+    match e.kind() {
+        ErrorKind::ConnectionRefused => "connection refused".to_string(),
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => "timed out".to_string(),
+        ErrorKind::NotFound => "no such socket".to_string(),
+        _ => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(list: &[&str]) -> Vec<SocketAddr> {
+        list.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn bind_addresses_by_host() {
+        assert_eq!(bind_addresses(None, 5432), addrs(&["127.0.0.1:5432", "[::1]:5432"]));
+        assert_eq!(
+            bind_addresses(Some("localhost"), 5432),
+            addrs(&["127.0.0.1:5432", "[::1]:5432"])
+        );
+        assert_eq!(bind_addresses(Some("*"), 80), addrs(&["0.0.0.0:80", "[::]:80"]));
+        assert_eq!(bind_addresses(Some("127.0.0.5"), 80), addrs(&["127.0.0.5:80"]));
+        assert_eq!(bind_addresses(Some("::1"), 80), addrs(&["[::1]:80"]));
+    }
+
+    #[test]
+    fn wildcard_probes_loopback() {
+        assert_eq!(probe_addresses(Some("0.0.0.0"), 80), addrs(&["127.0.0.1:80"]));
+        assert_eq!(probe_addresses(Some("*"), 80), addrs(&["127.0.0.1:80", "[::1]:80"]));
+    }
+
+    #[test]
+    fn probes_listener_on_its_bind_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let endpoint = Endpoint::Tcp {
+            host: Some("127.0.0.1".to_string()),
+            port,
+        };
+        assert!(check_endpoint(&endpoint).is_ok());
+        assert_eq!(endpoint_conflict(&endpoint), Some(format!("127.0.0.1:{}", port)));
+
+        // Same port on another loopback address is neither healthy nor taken
+        let other = Endpoint::Tcp {
+            host: Some("127.0.0.5".to_string()),
+            port,
+        };
+        let err = check_endpoint(&other).unwrap_err();
+        assert!(err.contains(&format!("127.0.0.5:{}", port)));
+        assert_eq!(endpoint_conflict(&other), None);
     }
 }
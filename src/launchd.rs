@@ -1,39 +1,166 @@
 use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
 
+use crate::config::{Backend, Config};
+use crate::persist::AutoStart;
 use crate::process;
+use crate::ssh_config::ConfigRoot;
 use crate::tunnel::TunnelHost;
 
+/// Launch agents in `~/Library/LaunchAgents`, macOS only.
+pub struct Launchd;
+
+impl AutoStart for Launchd {
+    fn name(&self) -> &'static str {
+        "launchd"
+    }
+
+    fn is_available(&self) -> bool {
+        cfg!(target_os = "macos") && process::which("launchctl").is_ok()
+    }
+
+    fn is_enabled(&self, name: &str) -> bool {
+        is_enabled(name)
+    }
+
+    fn enable(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<()> {
+        enable(tunnel, root, cfg)
+    }
+
+    fn disable(&self, name: &str) -> Result<()> {
+        disable(name)
+    }
+
+    fn render(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<String> {
+        plist(tunnel, root, cfg)
+    }
+
+    fn installed(&self, name: &str) -> Option<String> {
+        fs::read_to_string(plist_path(name).ok()?).ok()
+    }
+
+    fn supervises(&self) -> bool {
+        true
+    }
+
+    fn main_pid(&self, name: &str) -> Option<u32> {
+        let output = Command::new("launchctl")
+            .args(["list", &format!("com.mole.{}", name)])
+            .output()
+            .ok()?;
+        parse_pid(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    fn main_pids(&self, names: &[&str]) -> HashMap<String, u32> {
+        let Ok(output) = Command::new("launchctl").arg("list").output() else {
+            return HashMap::new();
+        };
+        parse_list(&String::from_utf8_lossy(&output.stdout))
+            .into_iter()
+            .filter(|(name, _)| names.contains(&name.as_str()))
+            .collect()
+    }
+
+    /// Loading the plist starts the tunnel (`RunAtLoad`); loading it twice is harmless.
+    fn start(&self, name: &str) -> Result<()> {
+        launchctl("load", name)
+    }
+
+    /// `KeepAlive` restarts a stopped job at once, so unload it instead. The
+    /// plist stays, and loads again at the next login.
+    fn stop(&self, name: &str) -> Result<()> {
+        launchctl("unload", name)
+    }
+}
+
+fn launchctl(subcommand: &str, name: &str) -> Result<()> {
+    let path = plist_path(name)?;
+    Command::new("launchctl")
+        .args([subcommand, &path.to_string_lossy()])
+        .status()
+        .with_context(|| format!("failed to run launchctl {}", subcommand))?;
+    Ok(())
+}
+
+/// The `"PID" = 123;` line `launchctl list <label>` prints for a running job.
+fn parse_pid(output: &str) -> Option<u32> {
+    output.lines().find_map(|line| {
+        line.trim()
+            .strip_prefix("\"PID\" = ")?
+            .trim_end_matches(';')
+            .parse()
+            .ok()
+    })
+}
+
+/// PIDs of mole's running jobs in the `PID\tStatus\tLabel` table `launchctl
+/// list` prints (`-` for a job that isn't running), by tunnel name.
+fn parse_list(output: &str) -> HashMap<String, u32> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split('\t');
+            let pid = columns.next()?.parse().ok()?;
+            let name = columns.nth(1)?.strip_prefix("com.mole.")?;
+            Some((name.to_string(), pid))
+        })
+        .collect()
+}
+
 fn launch_agents_dir() -> Result<PathBuf> {
-    let dir = dirs::home_dir()
+    Ok(dirs::home_dir()
         .context("cannot determine home directory")?
         .join("Library")
-        .join("LaunchAgents");
-    fs::create_dir_all(&dir)?;
-    Ok(dir)
+        .join("LaunchAgents"))
 }
 
 /// Path to the launchd plist for a tunnel.
-pub fn plist_path(name: &str) -> Result<PathBuf> {
+fn plist_path(name: &str) -> Result<PathBuf> {
     Ok(launch_agents_dir()?.join(format!("com.mole.{}.plist", name)))
 }
 
 /// Check if a tunnel has a launchd plist installed.
-pub fn is_enabled(name: &str) -> bool {
+fn is_enabled(name: &str) -> bool {
     plist_path(name).map(|p| p.exists()).unwrap_or(false)
 }
 
 /// Generate and install a launchd plist for auto-starting a tunnel.
-pub fn enable(tunnel: &TunnelHost) -> Result<()> {
-    let log_path = process::log_file(&tunnel.name)?;
-    let label = format!("com.mole.{}", tunnel.name);
+fn enable(tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<()> {
+    fs::create_dir_all(launch_agents_dir()?)?;
     let path = plist_path(&tunnel.name)?;
+    fs::write(&path, plist(tunnel, root, cfg)?)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    launchctl("load", &tunnel.name)
+}
 
-    let autossh = which_autossh()?;
+/// The launchd plist running a tunnel.
+fn plist(tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<String> {
+    let log_path = process::log_file(&tunnel.name)?;
+    let label = format!("com.mole.{}", tunnel.name);
+    let spec = process::launch_spec(tunnel, root, cfg)?;
+    let program = process::which(&spec.program)?;
+    let args: String = spec
+        .args
+        .iter()
+        .map(|a| format!("\n        <string>{}</string>", xml_escape(a)))
+        .collect();
+    let env: String = spec
+        .env
+        .iter()
+        .map(|(k, v)| {
+            format!(
+                "\n        <key>{}</key>\n        <string>{}</string>",
+                xml_escape(k),
+                xml_escape(v)
+            )
+        })
+        .collect();
 
-    let plist = format!(
+    Ok(format!(
         r#"<?xml version="1.0" encoding="UTF-8"?>
 <!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
 <plist version="1.0">
@@ -42,19 +169,16 @@ pub fn enable(tunnel: &TunnelHost) -> Result<()> {
     <string>{label}</string>
     <key>ProgramArguments</key>
     <array>
-        <string>{autossh}</string>
-        <string>-N</string>
-        <string>{name}</string>
+        <string>{program}</string>{args}
     </array>
     <key>EnvironmentVariables</key>
-    <dict>
-        <key>AUTOSSH_PORT</key>
-        <string>0</string>
+    <dict>{env}
     </dict>
     <key>RunAtLoad</key>
     <true/>
-    <key>KeepAlive</key>
-    <true/>
+    <key>KeepAlive</key>{keep_alive}
+    <key>ExitTimeOut</key>
+    <integer>{stop_timeout}</integer>
     <key>StandardErrorPath</key>
     <string>{log}</string>
     <key>StandardOutPath</key>
@@ -62,33 +186,35 @@ pub fn enable(tunnel: &TunnelHost) -> Result<()> {
 </dict>
 </plist>"#,
         label = label,
-        autossh = autossh,
-        name = tunnel.name,
+        program = xml_escape(&program),
+        args = args,
+        env = env,
         log = log_path.display(),
-    );
-
-    fs::write(&path, plist)
-        .with_context(|| format!("failed to write {}", path.display()))?;
-
-    Command::new("launchctl")
-        .args(["load", &path.to_string_lossy()])
-        .status()
-        .context("failed to run launchctl load")?;
+        stop_timeout = cfg.stop_timeout,
+        keep_alive = keep_alive(spec.backend),
+    ))
+}
 
-    Ok(())
+/// When launchd relaunches the job. mole's supervisor exits non-zero only when
+/// it gives up (or can't run ssh at all), and a relaunch would undo that, so it
+/// is restarted only after exiting 0 (told to stop outside launchd) or crashing.
+fn keep_alive(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Autossh => "\n    <true/>",
+        Backend::Ssh => {
+            "\n    <dict>\n        <key>SuccessfulExit</key>\n        <true/>\n        <key>Crashed</key>\n        <true/>\n    </dict>"
+        }
+    }
 }
 
 /// Remove and unload a launchd plist for a tunnel.
-pub fn disable(name: &str) -> Result<()> {
+fn disable(name: &str) -> Result<()> {
     let path = plist_path(name)?;
     if !path.exists() {
         anyhow::bail!("tunnel '{}' is not enabled for auto-start", name);
     }
 
-    Command::new("launchctl")
-        .args(["unload", &path.to_string_lossy()])
-        .status()
-        .context("failed to run launchctl unload")?;
+    launchctl("unload", name)?;
 
     fs::remove_file(&path)
         .with_context(|| format!("failed to remove {}", path.display()))?;
@@ -96,14 +222,43 @@ pub fn disable(name: &str) -> Result<()> {
     Ok(())
 }
 
-/// Find the absolute path to autossh.
-fn which_autossh() -> Result<String> {
-    let output = Command::new("which")
-        .arg("autossh")
-        .output()
-        .context("failed to run 'which autossh'")?;
-    if !output.status.success() {
-        anyhow::bail!("autossh not found in PATH");
+/// Escape text for a plist `<string>`; ssh options can contain `<`, `>` or `&`.
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_launchctl_pid() {
+        let output = "{\n\t\"LimitLoadToSessionType\" = \"Aqua\";\n\t\"Label\" = \"com.mole.db\";\n\t\"PID\" = 4242;\n};\n";
+        assert_eq!(parse_pid(output), Some(4242));
+        assert_eq!(parse_pid("{\n\t\"Label\" = \"com.mole.db\";\n\t\"LastExitStatus\" = 0;\n};\n"), None);
+    }
+
+    #[test]
+    fn parses_launchctl_list() {
+        let output = "PID\tStatus\tLabel\n4242\t0\tcom.mole.db\n-\t0\tcom.mole.web\n311\t0\tcom.apple.Finder\n";
+        let pids = parse_list(output);
+        assert_eq!(pids.len(), 1);
+        assert_eq!(pids["db"], 4242);
+    }
+
+    #[test]
+    fn supervisor_give_up_is_not_relaunched() {
+        assert_eq!(keep_alive(Backend::Autossh), "\n    <true/>");
+        let ssh = keep_alive(Backend::Ssh);
+        assert!(ssh.contains("<key>SuccessfulExit</key>\n        <true/>"));
+        assert!(ssh.contains("<key>Crashed</key>\n        <true/>"));
+    }
+
+    #[test]
+    fn escapes_plist_strings() {
+        assert_eq!(
+            xml_escape("ProxyCommand=nc -X 5 -x localhost:1080 %h %p <&>"),
+            "ProxyCommand=nc -X 5 -x localhost:1080 %h %p &lt;&amp;&gt;"
+        );
     }
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
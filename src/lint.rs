@@ -0,0 +1,655 @@
+use colored::Colorize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::deps;
+use crate::health;
+use crate::ssh_config::{self, ConfigSet, LineKind};
+use crate::tunnel::{Endpoint, TunnelHost};
+
+/// Annotation keys mole understands (`# mole:<key>=<value>`).
+const KNOWN_ANNOTATIONS: &[&str] = &["group", "backend", "depends", "ssh-option"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// One problem found in the SSH config.
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Stable identifier of the check, e.g. "port-conflict".
+    pub check: &'static str,
+    /// `file:line` the finding points at, when it has one.
+    pub location: Option<String>,
+    /// Tunnel the finding is about, when it is about one.
+    pub host: Option<String>,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+impl Report {
+    fn new(findings: Vec<Finding>) -> Self {
+        let errors = findings.iter().filter(|f| f.severity == Severity::Error).count();
+        Self {
+            warnings: findings.len() - errors,
+            errors,
+            findings,
+        }
+    }
+}
+
+struct Linter<'a> {
+    set: &'a ConfigSet,
+    findings: Vec<Finding>,
+    /// Where each concrete host name is first declared.
+    host_lines: HashMap<String, String>,
+}
+
+/// Run every check against a loaded SSH config.
+pub fn lint(set: &ConfigSet) -> Report {
+    let mut linter = Linter {
+        set,
+        findings: Vec::new(),
+        host_lines: HashMap::new(),
+    };
+
+    for warning in &set.warnings {
+        linter.push(Severity::Warning, "config", None, None, warning.clone());
+    }
+    linter.check_lines();
+
+    let (hosts, warnings) = ssh_config::resolve_hosts(set, ssh_config::Exec::Run);
+    for warning in warnings {
+        linter.push(Severity::Warning, "config", None, None, warning);
+    }
+    let mut tunnels = Vec::new();
+    for host in hosts {
+        let exit_on_failure = host
+            .config
+            .get("ExitOnForwardFailure")
+            .is_some_and(|v| v.eq_ignore_ascii_case("yes"));
+        let divergent = match host.aliases.len() {
+            1 => Vec::new(),
+            _ => ssh_config::divergent_aliases(set, &host),
+        };
+        if let Some(tunnel) = ssh_config::tunnel_from_config(host.aliases, &host.config) {
+            for alias in divergent {
+                linter.tunnel_finding(
+                    Severity::Warning,
+                    "alias-options",
+                    &tunnel,
+                    format!(
+                        "'{}' gets options '{}' doesn't (a Host pattern or Match block matches only it); \
+                         mole always connects as '{}', so they don't apply to this tunnel",
+                        alias, tunnel.name, tunnel.name
+                    ),
+                );
+            }
+            if !exit_on_failure {
+                linter.tunnel_finding(
+                    Severity::Warning,
+                    "exit-on-forward-failure",
+                    &tunnel,
+                    "ExitOnForwardFailure is not 'yes'; ssh keeps running when a forward fails to bind"
+                        .to_string(),
+                );
+            }
+            tunnels.push(tunnel);
+        }
+    }
+
+    linter.check_port_conflicts(&tunnels);
+    linter.check_dependencies(&tunnels);
+    for tunnel in &tunnels {
+        linter.check_privileged_ports(tunnel);
+        linter.check_identity_files(tunnel);
+        linter.check_proxy_jump(tunnel);
+    }
+
+    Report::new(linter.findings)
+}
+
+impl Linter<'_> {
+    fn push(
+        &mut self,
+        severity: Severity,
+        check: &'static str,
+        location: Option<String>,
+        host: Option<String>,
+        message: String,
+    ) {
+        self.findings.push(Finding {
+            severity,
+            check,
+            location,
+            host,
+            message,
+        });
+    }
+
+    fn tunnel_finding(
+        &mut self,
+        severity: Severity,
+        check: &'static str,
+        tunnel: &TunnelHost,
+        message: String,
+    ) {
+        let location = self.host_lines.get(&tunnel.name).cloned();
+        self.push(severity, check, location, Some(tunnel.name.clone()), message);
+    }
+
+    fn location(&self, file: usize, number: usize) -> String {
+        format!("{}:{}", self.set.files[file].display(), number + 1)
+    }
+
+    /// Line-level checks: forward syntax, annotations and duplicate Host names.
+    fn check_lines(&mut self) {
+        let mut in_block = false;
+        let mut include_stack = Vec::new();
+
+        for line in &self.set.lines {
+            let location = self.location(line.file, line.number);
+            match &line.kind {
+                LineKind::IncludeStart => include_stack.push(in_block),
+                LineKind::IncludeEnd => in_block = include_stack.pop().unwrap_or(in_block),
+                LineKind::Comment(text) => self.check_annotation(text, in_block, location),
+                LineKind::Directive { key, value } => {
+                    let key = key.to_lowercase();
+                    match key.as_str() {
+                        "host" => {
+                            in_block = true;
+                            self.check_duplicate_host(value, location);
+                        }
+                        "match" => in_block = true,
+                        "localforward" | "remoteforward" | "dynamicforward" => {
+                            let parsed = match key.as_str() {
+                                "localforward" => ssh_config::parse_local_forward(value).is_some(),
+                                "remoteforward" => ssh_config::parse_remote_forward(value).is_some(),
+                                _ => ssh_config::parse_dynamic_forward(value).is_some(),
+                            };
+                            if !parsed {
+                                self.push(
+                                    Severity::Error,
+                                    "forward-syntax",
+                                    Some(location),
+                                    None,
+                                    format!("cannot parse '{} {}'; mole ignores this forward", key_name(&key), value),
+                                );
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_annotation(&mut self, text: &str, in_block: bool, location: String) {
+        let body = text.trim_start_matches('#').trim_start();
+        if !body.starts_with("mole:") {
+            return;
+        }
+        match ssh_config::parse_annotation(text) {
+            None => self.push(
+                Severity::Error,
+                "annotation",
+                Some(location),
+                None,
+                format!("malformed annotation '{}'; expected '# mole:<key>=<value>'", text),
+            ),
+            Some((key, _)) if !KNOWN_ANNOTATIONS.contains(&key) => self.push(
+                Severity::Error,
+                "annotation",
+                Some(location),
+                None,
+                format!("unknown annotation key '{}'", key),
+            ),
+            Some(("backend", value)) if value.parse::<config::Backend>().is_err() => self.push(
+                Severity::Error,
+                "annotation",
+                Some(location),
+                None,
+                format!("unknown backend '{}'; expected 'autossh' or 'ssh'", value),
+            ),
+            Some(_) if !in_block => self.push(
+                Severity::Warning,
+                "annotation",
+                Some(location),
+                None,
+                "annotation outside any Host block is ignored".to_string(),
+            ),
+            Some(_) => {}
+        }
+    }
+
+    fn check_duplicate_host(&mut self, value: &str, location: String) {
+        for name in ssh_config::split_args(value) {
+            if !ssh_config::is_concrete(&name) {
+                continue;
+            }
+            match self.host_lines.get(&name) {
+                Some(first) if *first != location => {
+                    let message = format!(
+                        "Host '{}' is also declared at {}; ssh keeps the first value of each option",
+                        name, first
+                    );
+                    self.push(Severity::Warning, "duplicate-host", Some(location.clone()), Some(name), message);
+                }
+                Some(_) => {}
+                None => {
+                    self.host_lines.insert(name, location.clone());
+                }
+            }
+        }
+    }
+
+    /// Two local listeners (in the same or different tunnels) that would bind the same socket.
+    fn check_port_conflicts(&mut self, tunnels: &[TunnelHost]) {
+        let listeners: Vec<(&TunnelHost, &Endpoint)> = tunnels
+            .iter()
+            .flat_map(|t| t.local_listeners().map(move |e| (t, e)))
+            .collect();
+
+        for (i, (first, a)) in listeners.iter().enumerate() {
+            for (second, b) in &listeners[i + 1..] {
+                if !endpoints_overlap(a, b) {
+                    continue;
+                }
+                let message = if first.name == second.name {
+                    format!("{} is forwarded twice in '{}'", b, second.name)
+                } else {
+                    format!("{} is also claimed by '{}' ({})", b, first.name, a)
+                };
+                self.tunnel_finding(Severity::Error, "port-conflict", second, message);
+            }
+        }
+    }
+
+    /// `# mole:depends=` names that aren't tunnels, and dependency cycles.
+    fn check_dependencies(&mut self, tunnels: &[TunnelHost]) {
+        for (name, message) in deps::problems(tunnels) {
+            if let Some(tunnel) = tunnels.iter().find(|t| t.name == name) {
+                self.tunnel_finding(Severity::Error, "depends", tunnel, message);
+            }
+        }
+    }
+
+    fn check_privileged_ports(&mut self, tunnel: &TunnelHost) {
+        let local: Vec<u16> = tunnel.local_listeners().filter_map(|e| e.port()).collect();
+        for port in local {
+            if port != 0 && port < 1024 {
+                self.tunnel_finding(
+                    Severity::Warning,
+                    "privileged-port",
+                    tunnel,
+                    format!("local port {} is privileged; binding it needs root", port),
+                );
+            }
+        }
+        let remote: Vec<u16> = tunnel.remote_forwards.iter().filter_map(|f| f.listen.port()).collect();
+        for port in remote {
+            if port != 0 && port < 1024 {
+                self.tunnel_finding(
+                    Severity::Warning,
+                    "privileged-port",
+                    tunnel,
+                    format!("remote port {} is privileged; only root can bind it on the server", port),
+                );
+            }
+        }
+    }
+
+    fn check_identity_files(&mut self, tunnel: &TunnelHost) {
+        for file in &tunnel.identity_files {
+            if file.eq_ignore_ascii_case("none") {
+                continue;
+            }
+            let Some(path) = identity_path(file, tunnel) else {
+                continue;
+            };
+            if !path.exists() {
+                self.tunnel_finding(
+                    Severity::Warning,
+                    "identity-file",
+                    tunnel,
+                    format!("IdentityFile {} does not exist", path.display()),
+                );
+            }
+        }
+    }
+
+    /// Every ProxyJump hop must be a Host the config defines, unless it is
+    /// plainly a DNS name or address ssh can reach without one.
+    fn check_proxy_jump(&mut self, tunnel: &TunnelHost) {
+        let Some(jump) = tunnel.proxy_jump.as_deref() else {
+            return;
+        };
+        for hop in jump.split(',') {
+            let host = jump_host(hop.trim());
+            if host.is_empty()
+                || host.contains(['.', ':'])
+                || host.eq_ignore_ascii_case("localhost")
+                || self.is_defined(host)
+            {
+                continue;
+            }
+            self.tunnel_finding(
+                Severity::Error,
+                "proxy-jump",
+                tunnel,
+                format!("ProxyJump target '{}' is not defined in the SSH config", host),
+            );
+        }
+    }
+
+    /// Whether any Host line other than a catch-all `Host *` matches `host`.
+    fn is_defined(&self, host: &str) -> bool {
+        self.set.lines.iter().any(|line| match &line.kind {
+            LineKind::Directive { key, value } if key.eq_ignore_ascii_case("host") => {
+                let patterns = ssh_config::split_args(value);
+                !patterns.iter().all(|p| p == "*") && ssh_config::host_matches(host, &patterns)
+            }
+            _ => false,
+        })
+    }
+}
+
+/// Canonical spelling of a forward keyword for messages.
+fn key_name(key: &str) -> &'static str {
+    match key {
+        "localforward" => "LocalForward",
+        "remoteforward" => "RemoteForward",
+        _ => "DynamicForward",
+    }
+}
+
+/// The host part of a ProxyJump hop: `[user@]host[:port]` or `ssh://[user@]host[:port]`.
+fn jump_host(hop: &str) -> &str {
+    let hop = hop.strip_prefix("ssh://").unwrap_or(hop);
+    let hop = hop.rsplit_once('@').map_or(hop, |(_, h)| h);
+    if let Some(rest) = hop.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    hop.split(':').next().unwrap_or(hop)
+}
+
+/// Expand an IdentityFile value the way ssh does (`~`, env vars and the common
+/// `%` tokens). Relative paths are relative to the home directory. Returns None
+/// when it uses a token mole can't expand.
+fn identity_path(value: &str, tunnel: &TunnelHost) -> Option<PathBuf> {
+    let home = dirs::home_dir()?;
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'd' => out.push_str(&home.to_string_lossy()),
+            'u' => out.push_str(&whoami::username()),
+            'h' => out.push_str(tunnel.hostname.as_deref().unwrap_or(&tunnel.name)),
+            'n' => out.push_str(&tunnel.name),
+            'r' => out.push_str(
+                &tunnel.user.clone().unwrap_or_else(whoami::username),
+            ),
+            '%' => out.push('%'),
+            _ => return None,
+        }
+    }
+    let path = config::expand_path(&out).ok()?;
+    Some(if path.is_absolute() { path } else { home.join(path) })
+}
+
+/// Whether two local listeners would fight over the same socket.
+fn endpoints_overlap(a: &Endpoint, b: &Endpoint) -> bool {
+    match (a, b) {
+        (Endpoint::Unix(a), Endpoint::Unix(b)) => a == b,
+        (Endpoint::Tcp { host: ha, port: pa }, Endpoint::Tcp { host: hb, port: pb }) => {
+            if pa != pb || *pa == 0 {
+                return false;
+            }
+            let a = health::bind_addresses(ha.as_deref(), *pa);
+            let b = health::bind_addresses(hb.as_deref(), *pb);
+            a.iter().any(|x| b.iter().any(|y| addrs_overlap(x, y)))
+        }
+        _ => false,
+    }
+}
+
+fn addrs_overlap(a: &SocketAddr, b: &SocketAddr) -> bool {
+    a == b || (a.is_ipv4() == b.is_ipv4() && (a.ip().is_unspecified() || b.ip().is_unspecified()))
+}
+
+/// Print a report for humans, one finding per line.
+pub fn print_report(report: &Report) {
+    for f in &report.findings {
+        let icon = match f.severity {
+            Severity::Error => "✗".red().to_string(),
+            Severity::Warning => "⚠".yellow().to_string(),
+        };
+        let location = f
+            .location
+            .as_deref()
+            .map(|l| format!("{}: ", l))
+            .unwrap_or_default();
+        println!(
+            "{} {}{} {}",
+            icon,
+            location.dimmed(),
+            f.message,
+            format!("[{}]", f.check).dimmed()
+        );
+    }
+
+    if report.findings.is_empty() {
+        println!("{} No problems found", "✓".green());
+    } else {
+        println!();
+        println!("{} error(s), {} warning(s)", report.errors, report.warnings);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_config::ConfigRoot;
+
+    fn lint_str(name: &str, content: &str) -> Report {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("config"), content).unwrap();
+        let root = ConfigRoot {
+            path: dir.join("config"),
+            ssh_dir: dir.clone(),
+            custom: true,
+        };
+        let set = ssh_config::load(&root).unwrap();
+        let report = lint(&set);
+        std::fs::remove_dir_all(&dir).unwrap();
+        report
+    }
+
+    fn checks(report: &Report) -> Vec<&'static str> {
+        report.findings.iter().map(|f| f.check).collect()
+    }
+
+    #[test]
+    fn clean_config_has_no_findings() {
+        let report = lint_str(
+            "mole_test_lint_clean",
+            "\
+Host bastion
+  HostName bastion.example.com
+
+Host db
+  # mole:group=prod
+  ProxyJump bastion
+  LocalForward 5432 localhost:5432
+  ExitOnForwardFailure yes
+",
+        );
+        assert!(report.findings.is_empty(), "{:?}", report.findings);
+        assert_eq!(report.errors, 0);
+    }
+
+    #[test]
+    fn flags_options_only_a_secondary_alias_gets() {
+        let report = lint_str(
+            "mole_test_lint_alias_options",
+            "\
+Host db db.prod
+  LocalForward 5432 localhost:5432
+  ExitOnForwardFailure yes
+
+Host web web.internal
+  LocalForward 8080 localhost:80
+  ExitOnForwardFailure yes
+
+Host *.prod
+  User deploy
+
+Host web*
+  User www
+",
+        );
+        assert_eq!(checks(&report), ["alias-options"]);
+        let finding = &report.findings[0];
+        assert_eq!(finding.host.as_deref(), Some("db"));
+        assert!(finding.message.starts_with("'db.prod' gets options 'db' doesn't"));
+    }
+
+    #[test]
+    fn flags_each_problem() {
+        let report = lint_str(
+            "mole_test_lint_problems",
+            "\
+# mole:group=early
+Host db
+  # mole:grup prod
+  # mole:colour=red
+  LocalForward 5432 localhost:5432
+  LocalForward 80 localhost
+  ProxyJump bastoin
+  IdentityFile ~/.ssh/mole_test_surely_missing_key
+  ExitOnForwardFailure yes
+
+Host cache
+  LocalForward 127.0.0.1:5432 localhost:6379
+  LocalForward 127.0.0.2:6000 localhost:6000
+  RemoteForward 443 localhost:443
+  ExitOnForwardFailure yes
+
+Host other
+  LocalForward 127.0.0.3:6000 localhost:6000
+
+Host db
+  User again
+",
+        );
+        let found = checks(&report);
+        for check in [
+            "annotation",
+            "forward-syntax",
+            "proxy-jump",
+            "identity-file",
+            "port-conflict",
+            "privileged-port",
+            "exit-on-forward-failure",
+            "duplicate-host",
+        ] {
+            assert!(found.contains(&check), "missing {}: {:?}", check, report.findings);
+        }
+        // Malformed, unknown key, and outside a block
+        assert_eq!(found.iter().filter(|c| **c == "annotation").count(), 3);
+        // Only 5432 conflicts; 127.0.0.2 and 127.0.0.3 are different sockets
+        assert_eq!(found.iter().filter(|c| **c == "port-conflict").count(), 1);
+        assert!(report.errors > 0);
+
+        let syntax = report.findings.iter().find(|f| f.check == "forward-syntax").unwrap();
+        assert!(syntax.location.as_deref().unwrap().ends_with("config:6"));
+    }
+
+    #[test]
+    fn flags_dependency_problems() {
+        let report = lint_str(
+            "mole_test_lint_depends",
+            "\
+Host socks
+  # mole:depends=api
+  DynamicForward 1080
+  ExitOnForwardFailure yes
+
+Host api
+  # mole:depends=socks, nosuch
+  LocalForward 8080 localhost:80
+  ExitOnForwardFailure yes
+",
+        );
+        let messages: Vec<&str> = report
+            .findings
+            .iter()
+            .filter(|f| f.check == "depends")
+            .map(|f| f.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "tunnel 'api' depends on 'nosuch', which is not a tunnel in the SSH config",
+                "dependency cycle: api → socks → api",
+            ]
+        );
+    }
+
+    #[test]
+    fn jump_host_forms() {
+        assert_eq!(jump_host("bastion"), "bastion");
+        assert_eq!(jump_host("admin@bastion:2222"), "bastion");
+        assert_eq!(jump_host("ssh://admin@[::1]:22"), "::1");
+    }
+
+    #[test]
+    fn wildcard_bind_overlaps_loopback() {
+        let any = Endpoint::Tcp {
+            host: Some("*".to_string()),
+            port: 8080,
+        };
+        let default = Endpoint::Tcp { host: None, port: 8080 };
+        let other = Endpoint::Tcp {
+            host: Some("127.0.0.2".to_string()),
+            port: 8080,
+        };
+        assert!(endpoints_overlap(&any, &default));
+        assert!(endpoints_overlap(&any, &other));
+        assert!(!endpoints_overlap(&default, &other));
+    }
+
+    #[test]
+    fn report_serializes_to_json() {
+        let report = Report::new(vec![Finding {
+            severity: Severity::Error,
+            check: "port-conflict",
+            location: Some("config:3".to_string()),
+            host: Some("db".to_string()),
+            message: "taken".to_string(),
+        }]);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["errors"], 1);
+        assert_eq!(json["findings"][0]["severity"], "error");
+        assert_eq!(json["findings"][0]["check"], "port-conflict");
+    }
+}
@@ -1,59 +1,119 @@
+mod bulk;
 mod cli;
 mod config;
+mod crontab;
+mod daemon;
+mod deps;
 mod display;
+mod doctor;
 mod health;
 mod launchd;
+mod lint;
+mod paths;
+mod persist;
 mod picker;
 mod process;
+mod proctable;
 mod ssh_config;
+mod status;
+mod supervisor;
+mod systemd;
 mod tunnel;
 mod wizard;
+mod xdg_autostart;
 
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
 use clap::{CommandFactory, Parser};
 use colored::Colorize;
 
-use cli::{Cli, Command};
+use cli::{Cli, Command, DaemonAction};
 use config::Config;
+use ssh_config::ConfigRoot;
 
 fn main() -> Result<()> {
     clap_complete::CompleteEnv::with_factory(Cli::command).complete();
 
     let cli = Cli::parse();
+    paths::migrate();
     let cfg = Config::load();
 
     if cli.no_color {
         colored::control::set_override(false);
     }
 
+    // Resolved lazily so `mole config` still works when ssh_config is invalid
+    let root = || ConfigRoot::resolve(&cfg);
+
     match cli.command {
         Command::Up {
             name,
             all,
             group,
             persist,
-        } => cmd_up(name, all, group, persist, &cfg),
-        Command::Down { name, all, group } => cmd_down(name, all, group),
-        Command::Remove { name } => cmd_remove(name),
-        Command::Rename { old, new_name } => cmd_rename(old, new_name),
-        Command::Restart { name, all, group } => cmd_restart(name, all, group, &cfg),
-        Command::List { group } => cmd_list(group),
-        Command::Check => cmd_check(),
-        Command::Add => wizard::cmd_add(),
-        Command::Edit => cmd_edit(&cfg),
+        } => cmd_up(name, all, group, persist, &root()?, &cfg),
+        Command::Down { name, all, group } => cmd_down(name, all, group, &root()?, &cfg),
+        Command::Remove { name } => cmd_remove(name, &root()?, &cfg),
+        Command::Rename { old, new_name } => cmd_rename(old, new_name, &root()?, &cfg),
+        Command::Restart { name, all, group } => cmd_restart(name, all, group, &root()?, &cfg),
+        Command::List { group } => cmd_list(group, &root()?, &cfg),
+        Command::Check => cmd_check(&root()?),
+        Command::Lint { json } => cmd_lint(json, &root()?),
+        Command::Doctor => cmd_doctor(&root()?, &cfg),
+        Command::Add => wizard::cmd_add(&root()?),
+        Command::Edit => cmd_edit(&root()?, &cfg),
         Command::Logs {
             name,
             lines,
             follow,
-        } => cmd_logs(name, lines, follow),
-        Command::Enable { name, group } => cmd_enable(name, group),
-        Command::Disable { name, group } => cmd_disable(name, group),
+        } => cmd_logs(name, lines, follow, &root()?),
+        Command::Enable {
+            name,
+            group,
+            sync: true,
+            all,
+        } => cmd_enable_sync(name, group, all, &root()?, &cfg),
+        Command::Enable { name, group, .. } => cmd_enable(name, group, &root()?, &cfg),
+        Command::Disable { name, group } => cmd_disable(name, group, &root()?),
+        Command::Daemon { action } => cmd_daemon(action.unwrap_or(DaemonAction::Run)),
         Command::Config => cmd_config(&cfg),
         Command::Completions { shell } => cmd_completions(shell, &cfg),
-        Command::ListTunnelNames => cmd_list_tunnel_names(),
+        Command::ListTunnelNames => cmd_list_tunnel_names(&root()?),
+        Command::Supervise {
+            name,
+            gate_time,
+            ssh_args,
+        } => supervisor::run(&name, Duration::from_secs(gate_time), &ssh_args),
+    }
+}
+
+/// Discover tunnels, printing SSH config warnings (missing includes etc.) to stderr.
+fn load_tunnels(root: &ConfigRoot) -> Result<Vec<tunnel::TunnelHost>> {
+    let found = ssh_config::discover(root)?;
+    for warning in &found.warnings {
+        eprintln!("{} {}", "⚠".yellow(), warning);
+    }
+    Ok(found.tunnels)
+}
+
+/// Look up a tunnel by its primary name or any of its aliases.
+fn find_tunnel<'a>(tunnels: &'a [tunnel::TunnelHost], name: &str) -> Result<&'a tunnel::TunnelHost> {
+    tunnels
+        .iter()
+        .find(|t| t.has_name(name))
+        .ok_or_else(|| anyhow::anyhow!("tunnel '{}' not found in SSH config", name))
+}
+
+/// Picker label: every alias (so fuzzy search finds any of them) plus forwards.
+fn tunnel_label(t: &tunnel::TunnelHost) -> String {
+    let mut names = t.name.clone();
+    for alias in t.other_aliases() {
+        names.push_str(" / ");
+        names.push_str(alias);
     }
+    format!("{} ({})", names, format_all_forwards(t))
 }
 
 /// Build a display string for all forwards (local + remote + dynamic) of a tunnel.
@@ -64,40 +124,59 @@ fn format_all_forwards(t: &tunnel::TunnelHost) -> String {
     parts.join(", ")
 }
 
-fn print_start_status(name: &str, pid: u32, tunnel: &tunnel::TunnelHost, cfg: &Config) {
-    let local_ports: Vec<u16> = tunnel
-        .forwards
-        .iter()
-        .map(|f| f.local_port)
-        .chain(tunnel.dynamic_forwards.iter().map(|f| f.listen_port))
-        .collect();
-
-    if local_ports.is_empty() {
-        // Remote-only tunnel — can't probe health
-        println!(
-            "{} {} {} (pid {})",
-            "●".green(),
-            name.green().bold(),
-            "started".green(),
-            pid,
-        );
-        return;
+/// Label for a probed local endpoint in `mole check`: ":5432" for a plain port.
+fn probe_label(endpoint: &tunnel::Endpoint) -> String {
+    match endpoint {
+        tunnel::Endpoint::Tcp { host: None, port } => format!(":{}", port),
+        other => other.to_string(),
     }
-    let timeout = Duration::from_secs(cfg.health_timeout);
-    let healthy = health::wait_healthy_ports(&local_ports, timeout);
-    let health_msg = if healthy {
-        format!("{} healthy", "✓".green())
-    } else {
-        format!("{} port not reachable yet", "✗".yellow())
-    };
-    println!(
-        "{} {} {} (pid {}) — {}",
-        "●".green(),
-        name.green().bold(),
-        "started".green(),
+}
+
+fn print_start_status(tunnel: &tunnel::TunnelHost, pid: u32, cfg: &Config) {
+    let outcome = bulk::Outcome::Started {
         pid,
-        health_msg
-    );
+        health: bulk::wait_health(tunnel, cfg),
+        warning: None,
+    };
+    println!("{}", outcome.line(&tunnel.name));
+}
+
+/// Apply a bulk action; exits non-zero when any tunnel failed.
+fn run_bulk(
+    action: bulk::Action,
+    tunnels: &[&tunnel::TunnelHost],
+    root: &ConfigRoot,
+    cfg: &Config,
+) -> Result<()> {
+    if bulk::run(action, tunnels, root, cfg) > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// `targets` and the dependencies they need started, dependencies first.
+/// Dependencies that are already running are left alone.
+fn with_missing_dependencies<'a>(
+    tunnels: &'a [tunnel::TunnelHost],
+    targets: &[&'a tunnel::TunnelHost],
+) -> Result<Vec<&'a tunnel::TunnelHost>> {
+    let order = deps::with_dependencies(tunnels, targets)?;
+    let running = process::running_tunnels(order.iter().map(|t| t.name.as_str()));
+    Ok(order
+        .into_iter()
+        .filter(|t| targets.iter().any(|x| x.name == t.name) || !running.contains_key(&t.name))
+        .collect())
+}
+
+/// `targets` and the running tunnels that depend on them, dependents first,
+/// leaving out any that aren't running.
+fn with_running_dependents<'a>(
+    tunnels: &'a [tunnel::TunnelHost],
+    targets: &[&'a tunnel::TunnelHost],
+) -> Result<Vec<&'a tunnel::TunnelHost>> {
+    let order = deps::with_dependents(tunnels, targets)?;
+    let running = process::running_tunnels(order.iter().map(|t| t.name.as_str()));
+    Ok(order.into_iter().filter(|t| running.contains_key(&t.name)).collect())
 }
 
 fn tunnels_in_group<'a>(tunnels: &'a [tunnel::TunnelHost], group: &str) -> Vec<&'a tunnel::TunnelHost> {
@@ -107,13 +186,21 @@ fn tunnels_in_group<'a>(tunnels: &'a [tunnel::TunnelHost], group: &str) -> Vec<&
         .collect()
 }
 
-fn cmd_up(name: Option<String>, all: bool, group: Option<String>, persist: bool, cfg: &Config) -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels()?;
+fn cmd_up(
+    name: Option<String>,
+    all: bool,
+    group: Option<String>,
+    persist: bool,
+    root: &ConfigRoot,
+    cfg: &Config,
+) -> Result<()> {
+    let tunnels = load_tunnels(root)?;
 
     if all {
+        let running = process::running_tunnels(tunnels.iter().map(|t| t.name.as_str()));
         let inactive: Vec<&tunnel::TunnelHost> = tunnels
             .iter()
-            .filter(|t| !process::is_active(&t.name).unwrap_or(false))
+            .filter(|t| !running.contains_key(&t.name))
             .collect();
 
         if inactive.is_empty() {
@@ -121,31 +208,8 @@ fn cmd_up(name: Option<String>, all: bool, group: Option<String>, persist: bool,
             return Ok(());
         }
 
-        for t in &inactive {
-            match process::start_tunnel(t, cfg.max_log_size) {
-                Ok(pid) => {
-                    print_start_status(&t.name, pid, t, cfg);
-                    if persist {
-                        if let Err(e) = launchd::enable(t) {
-                            println!(
-                                "  {} failed to enable auto-start: {}",
-                                "⚠".yellow(),
-                                e
-                            );
-                        }
-                    }
-                }
-                Err(e) => {
-                    println!(
-                        "{} {} — {}",
-                        "✗".red(),
-                        t.name.red().bold(),
-                        e
-                    );
-                }
-            }
-        }
-        return Ok(());
+        let plan = with_missing_dependencies(&tunnels, &inactive)?;
+        return run_bulk(bulk::Action::Up { persist }, &plan, root, cfg);
     }
 
     if let Some(ref group) = group {
@@ -154,9 +218,11 @@ fn cmd_up(name: Option<String>, all: bool, group: Option<String>, persist: bool,
             anyhow::bail!("no tunnels found in group '{}'", group);
         }
 
-        let inactive: Vec<&&tunnel::TunnelHost> = in_group
+        let running = process::running_tunnels(in_group.iter().map(|t| t.name.as_str()));
+        let inactive: Vec<&tunnel::TunnelHost> = in_group
             .iter()
-            .filter(|t| !process::is_active(&t.name).unwrap_or(false))
+            .copied()
+            .filter(|t| !running.contains_key(&t.name))
             .collect();
 
         if inactive.is_empty() {
@@ -164,42 +230,20 @@ fn cmd_up(name: Option<String>, all: bool, group: Option<String>, persist: bool,
             return Ok(());
         }
 
-        for t in &inactive {
-            match process::start_tunnel(t, cfg.max_log_size) {
-                Ok(pid) => {
-                    print_start_status(&t.name, pid, t, cfg);
-                    if persist {
-                        if let Err(e) = launchd::enable(t) {
-                            println!(
-                                "  {} failed to enable auto-start: {}",
-                                "⚠".yellow(),
-                                e
-                            );
-                        }
-                    }
-                }
-                Err(e) => {
-                    println!(
-                        "{} {} — {}",
-                        "✗".red(),
-                        t.name.red().bold(),
-                        e
-                    );
-                }
-            }
-        }
-        return Ok(());
+        let plan = with_missing_dependencies(&tunnels, &inactive)?;
+        return run_bulk(bulk::Action::Up { persist }, &plan, root, cfg);
     }
 
     let tunnel = match name {
         Some(ref n) => tunnels
             .iter()
-            .find(|t| t.name == *n)
+            .find(|t| t.has_name(n))
             .ok_or_else(|| anyhow::anyhow!("tunnel '{}' not found in SSH config", n))?,
         None => {
+            let running = process::running_tunnels(tunnels.iter().map(|t| t.name.as_str()));
             let inactive: Vec<&tunnel::TunnelHost> = tunnels
                 .iter()
-                .filter(|t| !process::is_active(&t.name).unwrap_or(false))
+                .filter(|t| !running.contains_key(&t.name))
                 .collect();
 
             if inactive.is_empty() {
@@ -209,7 +253,7 @@ fn cmd_up(name: Option<String>, all: bool, group: Option<String>, persist: bool,
 
             let items: Vec<String> = inactive
                 .iter()
-                .map(|t| format!("{} ({})", t.name, format_all_forwards(t)))
+                .map(|t| tunnel_label(t))
                 .collect();
 
             let idx = picker::pick("Start tunnel", &items)?;
@@ -222,33 +266,49 @@ fn cmd_up(name: Option<String>, all: bool, group: Option<String>, persist: bool,
         return Ok(());
     }
 
-    let pid = process::start_tunnel(tunnel, cfg.max_log_size)?;
-    print_start_status(&tunnel.name, pid, tunnel, cfg);
+    let plan = with_missing_dependencies(&tunnels, &[tunnel])?;
+    if plan.len() > 1 {
+        return run_bulk(bulk::Action::Up { persist }, &plan, root, cfg);
+    }
 
-    if persist {
-        match launchd::enable(tunnel) {
-            Ok(()) => println!(
-                "  {} auto-start enabled",
-                "⏎".green()
-            ),
-            Err(e) => println!(
-                "  {} failed to enable auto-start: {}",
-                "⚠".yellow(),
-                e
-            ),
-        }
+    // Enable before starting, so a service manager starts the tunnel itself
+    // instead of racing mole's copy
+    let enabled = persist.then(|| persist::enable(tunnel, root, cfg));
+
+    let pid = process::start_tunnel(tunnel, root, cfg)?;
+    print_start_status(tunnel, pid, cfg);
+
+    match enabled {
+        Some(Ok(backend)) => println!(
+            "  {} auto-start enabled {}",
+            "⏎".green(),
+            format!("({})", backend).dimmed()
+        ),
+        Some(Err(e)) => println!(
+            "  {} failed to enable auto-start: {}",
+            "⚠".yellow(),
+            e
+        ),
+        None => {}
     }
 
     Ok(())
 }
 
-fn cmd_down(name: Option<String>, all: bool, group: Option<String>) -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels()?;
+fn cmd_down(
+    name: Option<String>,
+    all: bool,
+    group: Option<String>,
+    root: &ConfigRoot,
+    cfg: &Config,
+) -> Result<()> {
+    let tunnels = load_tunnels(root)?;
 
     if all {
+        let running = process::running_tunnels(tunnels.iter().map(|t| t.name.as_str()));
         let active: Vec<&tunnel::TunnelHost> = tunnels
             .iter()
-            .filter(|t| process::is_active(&t.name).unwrap_or(false))
+            .filter(|t| running.contains_key(&t.name))
             .collect();
 
         if active.is_empty() {
@@ -256,23 +316,8 @@ fn cmd_down(name: Option<String>, all: bool, group: Option<String>) -> Result<()
             return Ok(());
         }
 
-        for t in &active {
-            match process::stop_tunnel(&t.name) {
-                Ok(()) => println!(
-                    "{} {} {}",
-                    "○".dimmed(),
-                    t.name.bold(),
-                    "stopped".dimmed()
-                ),
-                Err(e) => println!(
-                    "{} {} — {}",
-                    "✗".red(),
-                    t.name.red().bold(),
-                    e
-                ),
-            }
-        }
-        return Ok(());
+        let plan = with_running_dependents(&tunnels, &active)?;
+        return run_bulk(bulk::Action::Down, &plan, root, cfg);
     }
 
     if let Some(ref group) = group {
@@ -281,9 +326,11 @@ fn cmd_down(name: Option<String>, all: bool, group: Option<String>) -> Result<()
             anyhow::bail!("no tunnels found in group '{}'", group);
         }
 
-        let active: Vec<&&tunnel::TunnelHost> = in_group
+        let running = process::running_tunnels(in_group.iter().map(|t| t.name.as_str()));
+        let active: Vec<&tunnel::TunnelHost> = in_group
             .iter()
-            .filter(|t| process::is_active(&t.name).unwrap_or(false))
+            .copied()
+            .filter(|t| running.contains_key(&t.name))
             .collect();
 
         if active.is_empty() {
@@ -291,36 +338,17 @@ fn cmd_down(name: Option<String>, all: bool, group: Option<String>) -> Result<()
             return Ok(());
         }
 
-        for t in &active {
-            match process::stop_tunnel(&t.name) {
-                Ok(()) => println!(
-                    "{} {} {}",
-                    "○".dimmed(),
-                    t.name.bold(),
-                    "stopped".dimmed()
-                ),
-                Err(e) => println!(
-                    "{} {} — {}",
-                    "✗".red(),
-                    t.name.red().bold(),
-                    e
-                ),
-            }
-        }
-        return Ok(());
+        let plan = with_running_dependents(&tunnels, &active)?;
+        return run_bulk(bulk::Action::Down, &plan, root, cfg);
     }
 
-    let tunnel_name = match name {
-        Some(n) => {
-            if !tunnels.iter().any(|t| t.name == n) {
-                anyhow::bail!("tunnel '{}' not found in SSH config", n);
-            }
-            n
-        }
+    let tunnel = match name {
+        Some(n) => find_tunnel(&tunnels, &n)?,
         None => {
+            let running = process::running_tunnels(tunnels.iter().map(|t| t.name.as_str()));
             let active: Vec<&tunnel::TunnelHost> = tunnels
                 .iter()
-                .filter(|t| process::is_active(&t.name).unwrap_or(false))
+                .filter(|t| running.contains_key(&t.name))
                 .collect();
 
             if active.is_empty() {
@@ -330,43 +358,43 @@ fn cmd_down(name: Option<String>, all: bool, group: Option<String>) -> Result<()
 
             let items: Vec<String> = active
                 .iter()
-                .map(|t| format!("{} ({})", t.name, format_all_forwards(t)))
+                .map(|t| tunnel_label(t))
                 .collect();
 
             let idx = picker::pick("Stop tunnel", &items)?;
-            active[idx].name.clone()
+            active[idx]
         }
     };
 
-    if !process::is_active(&tunnel_name)? {
-        println!("{} is not active", tunnel_name.yellow());
+    if !process::is_active(&tunnel.name)? {
+        println!("{} is not active", tunnel.name.yellow());
         return Ok(());
     }
 
-    process::stop_tunnel(&tunnel_name)?;
+    let plan = with_running_dependents(&tunnels, &[tunnel])?;
+    if plan.len() > 1 {
+        return run_bulk(bulk::Action::Down, &plan, root, cfg);
+    }
+
+    process::stop_tunnel(tunnel, cfg)?;
     println!(
         "{} {} {}",
         "○".dimmed(),
-        tunnel_name.bold(),
+        tunnel.name.bold(),
         "stopped".dimmed()
     );
 
     Ok(())
 }
 
-fn cmd_remove(name: Option<String>) -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels()?;
+fn cmd_remove(name: Option<String>, root: &ConfigRoot, cfg: &Config) -> Result<()> {
+    let tunnels = load_tunnels(root)?;
 
-    let tunnel = match name {
-        Some(ref n) => tunnels
-            .iter()
-            .find(|t| t.name == *n)
-            .ok_or_else(|| anyhow::anyhow!("tunnel '{}' not found in SSH config", n))?,
+    // A name on the command line removes just that alias; the picker removes the whole block
+    let (tunnel, alias) = match name {
+        Some(ref n) => (find_tunnel(&tunnels, n)?, Some(n.clone())),
         None => {
-            let items: Vec<String> = tunnels
-                .iter()
-                .map(|t| format!("{} ({})", t.name, format_all_forwards(t)))
-                .collect();
+            let items: Vec<String> = tunnels.iter().map(tunnel_label).collect();
 
             if items.is_empty() {
                 println!("{}", "No tunnels found.".yellow());
@@ -374,21 +402,33 @@ fn cmd_remove(name: Option<String>) -> Result<()> {
             }
 
             let idx = picker::pick("Remove tunnel", &items)?;
-            &tunnels[idx]
+            (&tunnels[idx], None)
         }
     };
+    let alias = alias.filter(|_| tunnel.aliases.len() > 1);
+    let label = alias.as_deref().unwrap_or(&tunnel.name);
 
     // Show what will be removed
-    if let Ok(Some((_path, block))) = ssh_config::read_host_block(&tunnel.name) {
-        println!("{}", "Will remove from SSH config:".dimmed());
+    if let Ok(Some((_path, block))) = ssh_config::read_host_block(root, label) {
+        match alias {
+            Some(ref a) => println!("{}", format!("Will remove alias '{}' from:", a).dimmed()),
+            None => println!("{}", "Will remove from SSH config:".dimmed()),
+        }
         for line in block.lines() {
+            // For an alias only the Host line changes; skip the comments above it
+            if alias.is_some() && line.trim_start().starts_with('#') {
+                continue;
+            }
             println!("  {}", line.dimmed());
+            if alias.is_some() {
+                break;
+            }
         }
         println!();
     }
 
     let confirmed = dialoguer::Confirm::new()
-        .with_prompt(format!("Remove {}?", tunnel.name))
+        .with_prompt(format!("Remove {}?", label))
         .default(false)
         .interact()
         .context("failed to read confirmation")?;
@@ -398,9 +438,12 @@ fn cmd_remove(name: Option<String>) -> Result<()> {
         return Ok(());
     }
 
+    // Secondary aliases have no process or mole files of their own
+    let owns_tunnel = alias.as_deref().is_none_or(|a| a == tunnel.name);
+
     // Stop if active
-    if process::is_active(&tunnel.name)? {
-        process::stop_tunnel(&tunnel.name)?;
+    if owns_tunnel && process::is_active(&tunnel.name)? {
+        process::stop_tunnel(tunnel, cfg)?;
         println!(
             "{} {} {}",
             "○".dimmed(),
@@ -409,47 +452,45 @@ fn cmd_remove(name: Option<String>) -> Result<()> {
         );
     }
 
-    // Disable launchd if enabled
-    if launchd::is_enabled(&tunnel.name) {
-        launchd::disable(&tunnel.name)?;
+    // Disable auto-start if enabled
+    if owns_tunnel && persist::is_enabled(&tunnel.name) {
+        let backends = persist::disable(&tunnel.name)?;
         println!(
-            "{} auto-start {}",
+            "{} auto-start {} {}",
             "○".dimmed(),
-            "disabled".dimmed()
+            "disabled".dimmed(),
+            format!("({})", backends.join(", ")).dimmed()
         );
     }
 
     // Remove from SSH config
-    let file_path = ssh_config::remove_host_block(&tunnel.name)?;
+    let file_path = match alias {
+        Some(ref a) => ssh_config::remove_host_alias(root, a)?,
+        None => ssh_config::remove_host_block(root, &tunnel.name)?,
+    };
 
     // Clean up mole files
-    process::cleanup_files(&tunnel.name)?;
+    if owns_tunnel {
+        process::cleanup_files(&tunnel.name)?;
+    }
 
     println!(
         "{} {} removed from {}",
         "✓".green(),
-        tunnel.name.green().bold(),
+        label.green().bold(),
         file_path.display()
     );
 
     Ok(())
 }
 
-fn cmd_rename(old: Option<String>, new_name: String) -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels()?;
+fn cmd_rename(old: Option<String>, new_name: String, root: &ConfigRoot, cfg: &Config) -> Result<()> {
+    let tunnels = load_tunnels(root)?;
 
-    let old_name = match old {
-        Some(n) => {
-            if !tunnels.iter().any(|t| t.name == n) {
-                anyhow::bail!("tunnel '{}' not found in SSH config", n);
-            }
-            n
-        }
+    let (tunnel, old_name) = match old {
+        Some(n) => (find_tunnel(&tunnels, &n)?, n),
         None => {
-            let items: Vec<String> = tunnels
-                .iter()
-                .map(|t| format!("{} ({})", t.name, format_all_forwards(t)))
-                .collect();
+            let items: Vec<String> = tunnels.iter().map(tunnel_label).collect();
 
             if items.is_empty() {
                 println!("{}", "No tunnels found.".yellow());
@@ -457,18 +498,31 @@ fn cmd_rename(old: Option<String>, new_name: String) -> Result<()> {
             }
 
             let idx = picker::pick("Rename tunnel", &items)?;
-            tunnels[idx].name.clone()
+            (&tunnels[idx], tunnels[idx].name.clone())
         }
     };
 
-    if tunnels.iter().any(|t| t.name == new_name) {
+    ssh_config::check_host_name(&new_name)?;
+    if tunnels.iter().any(|t| t.has_name(&new_name)) {
         anyhow::bail!("tunnel '{}' already exists", new_name);
     }
 
+    // Renaming a secondary alias only touches the Host line
+    if old_name != tunnel.name {
+        ssh_config::rename_host_block(root, &old_name, &new_name)?;
+        println!(
+            "{} renamed {} -> {}",
+            "✓".green(),
+            old_name.green().bold(),
+            new_name.green().bold()
+        );
+        return Ok(());
+    }
+
     // Stop if active
     let was_active = process::is_active(&old_name)?;
     if was_active {
-        process::stop_tunnel(&old_name)?;
+        process::stop_tunnel(tunnel, cfg)?;
         println!(
             "{} {} {}",
             "○".dimmed(),
@@ -477,26 +531,26 @@ fn cmd_rename(old: Option<String>, new_name: String) -> Result<()> {
         );
     }
 
-    // Disable launchd if enabled
-    let was_enabled = launchd::is_enabled(&old_name);
-    if was_enabled {
-        launchd::disable(&old_name)?;
+    // Disable auto-start if enabled
+    let enabled_by = persist::enabled_by(&old_name);
+    for backend in &enabled_by {
+        backend.disable(&old_name)?;
     }
 
     // Rename SSH config host block
-    ssh_config::rename_host_block(&old_name, &new_name)?;
+    ssh_config::rename_host_block(root, &old_name, &new_name)?;
 
     // Rename mole-managed files (PID, logs)
     process::rename_files(&old_name, &new_name)?;
 
-    // Re-enable launchd if it was enabled
-    if was_enabled {
-        let tunnels = ssh_config::discover_tunnels()?;
-        let new_tunnel = tunnels
-            .iter()
-            .find(|t| t.name == new_name)
-            .ok_or_else(|| anyhow::anyhow!("renamed tunnel '{}' not found after rename", new_name))?;
-        launchd::enable(new_tunnel)?;
+    // Re-enable auto-start with the same backends if it was enabled
+    if !enabled_by.is_empty() {
+        let tunnels = ssh_config::discover_tunnels(root)?;
+        let new_tunnel = find_tunnel(&tunnels, &new_name)
+            .context("renamed tunnel not found after rename")?;
+        for backend in &enabled_by {
+            backend.enable(new_tunnel, root, cfg)?;
+        }
     }
 
     println!(
@@ -509,9 +563,9 @@ fn cmd_rename(old: Option<String>, new_name: String) -> Result<()> {
     Ok(())
 }
 
-fn restart_tunnel(tunnel: &tunnel::TunnelHost, cfg: &Config) -> Result<()> {
+fn restart_tunnel(tunnel: &tunnel::TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<()> {
     if process::is_active(&tunnel.name)? {
-        process::stop_tunnel(&tunnel.name)?;
+        process::stop_tunnel(tunnel, cfg)?;
         println!(
             "{} {} {}",
             "○".dimmed(),
@@ -520,18 +574,25 @@ fn restart_tunnel(tunnel: &tunnel::TunnelHost, cfg: &Config) -> Result<()> {
         );
     }
 
-    let pid = process::start_tunnel(tunnel, cfg.max_log_size)?;
-    print_start_status(&tunnel.name, pid, tunnel, cfg);
+    let pid = process::start_tunnel(tunnel, root, cfg)?;
+    print_start_status(tunnel, pid, cfg);
     Ok(())
 }
 
-fn cmd_restart(name: Option<String>, all: bool, group: Option<String>, cfg: &Config) -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels()?;
+fn cmd_restart(
+    name: Option<String>,
+    all: bool,
+    group: Option<String>,
+    root: &ConfigRoot,
+    cfg: &Config,
+) -> Result<()> {
+    let tunnels = load_tunnels(root)?;
 
     if all {
+        let running = process::running_tunnels(tunnels.iter().map(|t| t.name.as_str()));
         let active: Vec<&tunnel::TunnelHost> = tunnels
             .iter()
-            .filter(|t| process::is_active(&t.name).unwrap_or(false))
+            .filter(|t| running.contains_key(&t.name))
             .collect();
 
         if active.is_empty() {
@@ -539,17 +600,8 @@ fn cmd_restart(name: Option<String>, all: bool, group: Option<String>, cfg: &Con
             return Ok(());
         }
 
-        for t in &active {
-            if let Err(e) = restart_tunnel(t, cfg) {
-                println!(
-                    "{} {} — {}",
-                    "✗".red(),
-                    t.name.red().bold(),
-                    e
-                );
-            }
-        }
-        return Ok(());
+        let plan = with_missing_dependencies(&tunnels, &active)?;
+        return run_bulk(bulk::Action::Restart, &plan, root, cfg);
     }
 
     if let Some(ref group) = group {
@@ -558,28 +610,20 @@ fn cmd_restart(name: Option<String>, all: bool, group: Option<String>, cfg: &Con
             anyhow::bail!("no tunnels found in group '{}'", group);
         }
 
-        for t in &in_group {
-            if let Err(e) = restart_tunnel(t, cfg) {
-                println!(
-                    "{} {} — {}",
-                    "✗".red(),
-                    t.name.red().bold(),
-                    e
-                );
-            }
-        }
-        return Ok(());
+        let plan = with_missing_dependencies(&tunnels, &in_group)?;
+        return run_bulk(bulk::Action::Restart, &plan, root, cfg);
     }
 
     let tunnel = match name {
         Some(ref n) => tunnels
             .iter()
-            .find(|t| t.name == *n)
+            .find(|t| t.has_name(n))
             .ok_or_else(|| anyhow::anyhow!("tunnel '{}' not found in SSH config", n))?,
         None => {
+            let running = process::running_tunnels(tunnels.iter().map(|t| t.name.as_str()));
             let active: Vec<&tunnel::TunnelHost> = tunnels
                 .iter()
-                .filter(|t| process::is_active(&t.name).unwrap_or(false))
+                .filter(|t| running.contains_key(&t.name))
                 .collect();
 
             if active.is_empty() {
@@ -589,7 +633,7 @@ fn cmd_restart(name: Option<String>, all: bool, group: Option<String>, cfg: &Con
 
             let items: Vec<String> = active
                 .iter()
-                .map(|t| format!("{} ({})", t.name, format_all_forwards(t)))
+                .map(|t| tunnel_label(t))
                 .collect();
 
             let idx = picker::pick("Restart tunnel", &items)?;
@@ -597,13 +641,18 @@ fn cmd_restart(name: Option<String>, all: bool, group: Option<String>, cfg: &Con
         }
     };
 
-    restart_tunnel(tunnel, cfg)?;
+    let plan = with_missing_dependencies(&tunnels, &[tunnel])?;
+    if plan.len() > 1 {
+        return run_bulk(bulk::Action::Restart, &plan, root, cfg);
+    }
+
+    restart_tunnel(tunnel, root, cfg)?;
 
     Ok(())
 }
 
-fn cmd_list(group: Option<String>) -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels()?;
+fn cmd_list(group: Option<String>, root: &ConfigRoot, cfg: &Config) -> Result<()> {
+    let tunnels = load_tunnels(root)?;
     if let Some(ref group) = group {
         let filtered: Vec<tunnel::TunnelHost> = tunnels
             .into_iter()
@@ -612,22 +661,47 @@ fn cmd_list(group: Option<String>) -> Result<()> {
         if filtered.is_empty() {
             anyhow::bail!("no tunnels found in group '{}'", group);
         }
-        display::print_tunnel_list(&filtered);
+        display::print_tunnel_list(&filtered, root, cfg);
     } else {
-        display::print_tunnel_list(&tunnels);
+        display::print_tunnel_list(&tunnels, root, cfg);
     }
     Ok(())
 }
 
-fn cmd_check() -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels()?;
+/// Lint the SSH config; exits non-zero when any finding is an error.
+fn cmd_lint(json: bool, root: &ConfigRoot) -> Result<()> {
+    let set = ssh_config::load(root)?;
+    let report = lint::lint(&set);
 
-    let active: Vec<&tunnel::TunnelHost> = tunnels
-        .iter()
-        .filter(|t| process::is_active(&t.name).unwrap_or(false))
-        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        lint::print_report(&report);
+    }
+
+    if report.errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Diagnose the environment; exits non-zero when any check fails.
+fn cmd_doctor(root: &ConfigRoot, cfg: &Config) -> Result<()> {
+    let sections = doctor::run(root, cfg);
+    if doctor::print(&sections) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Health-check every active tunnel. Ports are probed concurrently; each
+/// tunnel prints, in order, as soon as it and the ones before it are done.
+fn cmd_check(root: &ConfigRoot) -> Result<()> {
+    let tunnels = load_tunnels(root)?;
 
-    if active.is_empty() {
+    let status = status::collect(&tunnels, Arc::new(status::Live), status::DEADLINE);
+    let active = status.running.iter().filter(|r| r.is_some()).count();
+    if active == 0 {
         println!("{}", "No active tunnels to check.".yellow());
         return Ok(());
     }
@@ -635,52 +709,18 @@ fn cmd_check() -> Result<()> {
     let mut total_ports = 0;
     let mut healthy_ports = 0;
 
-    for t in &active {
-        let mut all_ok = true;
-        print!("  {} {:<20}", "●".green(), t.name.green().bold());
-
-        for fwd in &t.forwards {
-            total_ports += 1;
-            let ok = health::check_port(fwd.local_port);
-            if ok {
-                healthy_ports += 1;
-            } else {
-                all_ok = false;
+    let mut finished: Vec<Option<Vec<status::Probe>>> = vec![None; tunnels.len()];
+    let mut printed = 0;
+    for report in status.reports {
+        finished[report.tunnel] = Some(report.probes);
+        while let Some(Some(probes)) = finished.get(printed) {
+            let t = &tunnels[printed];
+            if status.running[printed].is_some() {
+                total_ports += probes.len();
+                healthy_ports += probes.iter().filter(|p| **p == status::Probe::Healthy).count();
+                print_check(t, probes);
             }
-            let icon = if ok {
-                "✓".green().to_string()
-            } else {
-                "✗".red().to_string()
-            };
-            print!("  {} :{}", icon, fwd.local_port);
-        }
-
-        for fwd in &t.dynamic_forwards {
-            total_ports += 1;
-            let ok = health::check_port(fwd.listen_port);
-            if ok {
-                healthy_ports += 1;
-            } else {
-                all_ok = false;
-            }
-            let icon = if ok {
-                "✓".green().to_string()
-            } else {
-                "✗".red().to_string()
-            };
-            print!("  {} D:{}", icon, fwd.listen_port);
-        }
-
-        for fwd in &t.remote_forwards {
-            print!("  {} R:{}", "—".dimmed(), fwd.bind_port);
-        }
-        println!();
-
-        if !all_ok {
-            println!(
-                "  {}",
-                "  ↳ some ports not reachable".yellow()
-            );
+            printed += 1;
         }
     }
 
@@ -690,7 +730,7 @@ fn cmd_check() -> Result<()> {
             "  {} All {} port(s) healthy across {} tunnel(s)",
             "✓".green(),
             total_ports,
-            active.len()
+            active
         );
     } else {
         println!(
@@ -698,23 +738,54 @@ fn cmd_check() -> Result<()> {
             "✗".yellow(),
             healthy_ports,
             total_ports,
-            active.len()
+            active
         );
     }
 
     Ok(())
 }
 
-fn cmd_edit(cfg: &Config) -> Result<()> {
-    let editor = cfg.resolve_editor();
+/// One `mole check` line for a running tunnel, plus a line per failed probe.
+fn print_check(t: &tunnel::TunnelHost, probes: &[status::Probe]) {
+    let mut failures = Vec::new();
+    print!("  {} {:<20}", "●".green(), t.name.green().bold());
+
+    let local = t.forwards.iter().map(|f| probe_label(&f.listen));
+    let dynamic = t.dynamic_forwards.iter().map(|f| format!("D:{}", f.listen));
+    for (label, probe) in local.chain(dynamic).zip(probes) {
+        let icon = match probe {
+            status::Probe::Healthy => "✓".green().to_string(),
+            status::Probe::Failed(failure) => {
+                failures.push(failure.clone());
+                "✗".red().to_string()
+            }
+            status::Probe::TimedOut => {
+                failures.push(format!(
+                    "{}: no answer within {}s",
+                    label,
+                    status::DEADLINE.as_secs()
+                ));
+                "?".yellow().to_string()
+            }
+        };
+        print!("  {} {}", icon, label);
+    }
 
-    let config_path = dirs::home_dir()
-        .ok_or_else(|| anyhow::anyhow!("cannot determine home directory"))?
-        .join(".ssh")
-        .join("config");
+    for fwd in &t.remote_forwards {
+        print!("  {} R:{}", "—".dimmed(), fwd.listen);
+    }
+    println!();
+
+    for failure in &failures {
+        println!("  {}", format!("  ↳ {}", failure).yellow());
+    }
+}
+
+fn cmd_edit(root: &ConfigRoot, cfg: &Config) -> Result<()> {
+    let editor = cfg.resolve_editor();
 
     let status = std::process::Command::new(&editor)
-        .arg(&config_path)
+        .arg(&root.path)
         .status()
         .with_context(|| format!("failed to launch editor '{}'", editor))?;
 
@@ -725,16 +796,11 @@ fn cmd_edit(cfg: &Config) -> Result<()> {
     Ok(())
 }
 
-fn cmd_logs(name: Option<String>, lines: usize, follow: bool) -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels()?;
+fn cmd_logs(name: Option<String>, lines: usize, follow: bool, root: &ConfigRoot) -> Result<()> {
+    let tunnels = load_tunnels(root)?;
 
     let tunnel_name = match name {
-        Some(n) => {
-            if !tunnels.iter().any(|t| t.name == n) {
-                anyhow::bail!("tunnel '{}' not found in SSH config", n);
-            }
-            n
-        }
+        Some(n) => find_tunnel(&tunnels, &n)?.name.clone(),
         None => {
             let items: Vec<String> = tunnels.iter().map(|t| t.name.clone()).collect();
             if items.is_empty() {
@@ -746,6 +812,10 @@ fn cmd_logs(name: Option<String>, lines: usize, follow: bool) -> Result<()> {
         }
     };
 
+    if let Some(shown) = daemon::with_client(|client| client.logs(&tunnel_name, lines, follow)) {
+        return shown;
+    }
+
     let log_path = process::log_file(&tunnel_name)?;
 
     if !log_path.exists() {
@@ -780,8 +850,13 @@ fn cmd_logs(name: Option<String>, lines: usize, follow: bool) -> Result<()> {
     Ok(())
 }
 
-fn cmd_enable(name: Option<String>, group: Option<String>) -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels()?;
+fn cmd_enable(
+    name: Option<String>,
+    group: Option<String>,
+    root: &ConfigRoot,
+    cfg: &Config,
+) -> Result<()> {
+    let tunnels = load_tunnels(root)?;
 
     if let Some(ref group) = group {
         let in_group = tunnels_in_group(&tunnels, group);
@@ -791,7 +866,7 @@ fn cmd_enable(name: Option<String>, group: Option<String>) -> Result<()> {
 
         let disabled: Vec<&&tunnel::TunnelHost> = in_group
             .iter()
-            .filter(|t| !launchd::is_enabled(&t.name))
+            .filter(|t| !persist::is_enabled(&t.name))
             .collect();
 
         if disabled.is_empty() {
@@ -800,12 +875,13 @@ fn cmd_enable(name: Option<String>, group: Option<String>) -> Result<()> {
         }
 
         for t in &disabled {
-            match launchd::enable(t) {
-                Ok(()) => println!(
-                    "{} {} auto-start {}",
+            match persist::enable(t, root, cfg) {
+                Ok(backend) => println!(
+                    "{} {} auto-start {} {}",
                     "⏎".green(),
                     t.name.green().bold(),
-                    "enabled".green()
+                    "enabled".green(),
+                    format!("({})", backend).dimmed()
                 ),
                 Err(e) => println!(
                     "{} {} — {}",
@@ -821,12 +897,12 @@ fn cmd_enable(name: Option<String>, group: Option<String>) -> Result<()> {
     let tunnel = match name {
         Some(ref n) => tunnels
             .iter()
-            .find(|t| t.name == *n)
+            .find(|t| t.has_name(n))
             .ok_or_else(|| anyhow::anyhow!("tunnel '{}' not found in SSH config", n))?,
         None => {
             let disabled: Vec<&tunnel::TunnelHost> = tunnels
                 .iter()
-                .filter(|t| !launchd::is_enabled(&t.name))
+                .filter(|t| !persist::is_enabled(&t.name))
                 .collect();
 
             if disabled.is_empty() {
@@ -836,7 +912,7 @@ fn cmd_enable(name: Option<String>, group: Option<String>) -> Result<()> {
 
             let items: Vec<String> = disabled
                 .iter()
-                .map(|t| format!("{} ({})", t.name, format_all_forwards(t)))
+                .map(|t| tunnel_label(t))
                 .collect();
 
             let idx = picker::pick("Enable auto-start for", &items)?;
@@ -844,24 +920,97 @@ fn cmd_enable(name: Option<String>, group: Option<String>) -> Result<()> {
         }
     };
 
-    if launchd::is_enabled(&tunnel.name) {
+    if persist::is_enabled(&tunnel.name) {
         println!("{} is already enabled", tunnel.name.yellow());
         return Ok(());
     }
 
-    launchd::enable(tunnel)?;
+    let backend = persist::enable(tunnel, root, cfg)?;
     println!(
-        "{} {} auto-start {}",
+        "{} {} auto-start {} {}",
         "⏎".green(),
         tunnel.name.green().bold(),
-        "enabled".green()
+        "enabled".green(),
+        format!("({})", backend).dimmed()
     );
 
     Ok(())
 }
 
-fn cmd_disable(name: Option<String>, group: Option<String>) -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels()?;
+/// Rewrite and reload auto-start entries that have drifted from what mole
+/// would generate now.
+fn cmd_enable_sync(
+    name: Option<String>,
+    group: Option<String>,
+    all: bool,
+    root: &ConfigRoot,
+    cfg: &Config,
+) -> Result<()> {
+    let tunnels = load_tunnels(root)?;
+
+    let targets: Vec<&tunnel::TunnelHost> = match (&name, &group) {
+        (Some(n), _) => {
+            let tunnel = find_tunnel(&tunnels, n)?;
+            if !persist::is_enabled(&tunnel.name) {
+                anyhow::bail!("tunnel '{}' is not enabled for auto-start", tunnel.name);
+            }
+            vec![tunnel]
+        }
+        (None, Some(g)) => {
+            let in_group = tunnels_in_group(&tunnels, g);
+            if in_group.is_empty() {
+                anyhow::bail!("no tunnels found in group '{}'", g);
+            }
+            in_group
+        }
+        (None, None) => tunnels.iter().collect(),
+    };
+
+    let mut drifted: Vec<_> = targets
+        .into_iter()
+        .map(|t| (t, persist::drifted(t, root, cfg)))
+        .filter(|(_, backends)| !backends.is_empty())
+        .collect();
+
+    if drifted.is_empty() {
+        match name {
+            Some(n) => println!("{} auto-start is up to date", n.yellow()),
+            None => println!("{}", "All auto-start entries are up to date.".yellow()),
+        }
+        return Ok(());
+    }
+
+    if name.is_none() && group.is_none() && !all {
+        let items: Vec<String> = drifted.iter().map(|(t, _)| tunnel_label(t)).collect();
+        let idx = picker::pick("Sync auto-start for", &items)?;
+        drifted = vec![drifted.swap_remove(idx)];
+    }
+
+    for (t, backends) in &drifted {
+        for backend in backends {
+            match persist::sync(*backend, t, root, cfg) {
+                Ok(()) => println!(
+                    "{} {} auto-start {} {}",
+                    "⏎".green(),
+                    t.name.green().bold(),
+                    "synced".green(),
+                    format!("({})", backend.name()).dimmed()
+                ),
+                Err(e) => println!(
+                    "{} {} — {}",
+                    "✗".red(),
+                    t.name.red().bold(),
+                    e
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_disable(name: Option<String>, group: Option<String>, root: &ConfigRoot) -> Result<()> {
+    let tunnels = load_tunnels(root)?;
 
     if let Some(ref group) = group {
         let in_group = tunnels_in_group(&tunnels, group);
@@ -871,7 +1020,7 @@ fn cmd_disable(name: Option<String>, group: Option<String>) -> Result<()> {
 
         let enabled: Vec<&&tunnel::TunnelHost> = in_group
             .iter()
-            .filter(|t| launchd::is_enabled(&t.name))
+            .filter(|t| persist::is_enabled(&t.name))
             .collect();
 
         if enabled.is_empty() {
@@ -880,12 +1029,13 @@ fn cmd_disable(name: Option<String>, group: Option<String>) -> Result<()> {
         }
 
         for t in &enabled {
-            match launchd::disable(&t.name) {
-                Ok(()) => println!(
-                    "{} {} auto-start {}",
+            match persist::disable(&t.name) {
+                Ok(backends) => println!(
+                    "{} {} auto-start {} {}",
                     "○".dimmed(),
                     t.name.bold(),
-                    "disabled".dimmed()
+                    "disabled".dimmed(),
+                    format!("({})", backends.join(", ")).dimmed()
                 ),
                 Err(e) => println!(
                     "{} {} — {}",
@@ -899,16 +1049,11 @@ fn cmd_disable(name: Option<String>, group: Option<String>) -> Result<()> {
     }
 
     let tunnel_name = match name {
-        Some(n) => {
-            if !tunnels.iter().any(|t| t.name == n) {
-                anyhow::bail!("tunnel '{}' not found in SSH config", n);
-            }
-            n
-        }
+        Some(n) => find_tunnel(&tunnels, &n)?.name.clone(),
         None => {
             let enabled: Vec<&tunnel::TunnelHost> = tunnels
                 .iter()
-                .filter(|t| launchd::is_enabled(&t.name))
+                .filter(|t| persist::is_enabled(&t.name))
                 .collect();
 
             if enabled.is_empty() {
@@ -918,7 +1063,7 @@ fn cmd_disable(name: Option<String>, group: Option<String>) -> Result<()> {
 
             let items: Vec<String> = enabled
                 .iter()
-                .map(|t| format!("{} ({})", t.name, format_all_forwards(t)))
+                .map(|t| tunnel_label(t))
                 .collect();
 
             let idx = picker::pick("Disable auto-start for", &items)?;
@@ -926,22 +1071,60 @@ fn cmd_disable(name: Option<String>, group: Option<String>) -> Result<()> {
         }
     };
 
-    if !launchd::is_enabled(&tunnel_name) {
+    if !persist::is_enabled(&tunnel_name) {
         println!("{} is not enabled", tunnel_name.yellow());
         return Ok(());
     }
 
-    launchd::disable(&tunnel_name)?;
+    let backends = persist::disable(&tunnel_name)?;
     println!(
-        "{} {} auto-start {}",
+        "{} {} auto-start {} {}",
         "○".dimmed(),
         tunnel_name.bold(),
-        "disabled".dimmed()
+        "disabled".dimmed(),
+        format!("({})", backends.join(", ")).dimmed()
     );
 
     Ok(())
 }
 
+fn cmd_daemon(action: DaemonAction) -> Result<()> {
+    if let DaemonAction::Run = action {
+        return daemon::serve();
+    }
+
+    let Some(mut client) = daemon::Client::connect() else {
+        println!("{}", "mole daemon is not running.".yellow());
+        return Ok(());
+    };
+    match action {
+        DaemonAction::Run => unreachable!(),
+        DaemonAction::Status => {
+            let tunnels = client.list()?;
+            println!(
+                "{} mole daemon running ({})",
+                "●".green(),
+                paths::socket()?.display()
+            );
+            for t in &tunnels {
+                let how = if t.owned { "started" } else { "adopted" };
+                println!(
+                    "  {} {}  {}",
+                    t.name.bold(),
+                    format!("pid {}", t.pid).dimmed(),
+                    format!("{} {} ago", how, process::format_uptime(t.started)).dimmed()
+                );
+            }
+        }
+        DaemonAction::Stop => {
+            client.shutdown()?;
+            println!("{} mole daemon {}", "○".dimmed(), "stopped".dimmed());
+        }
+        DaemonAction::Events => client.subscribe(|event| println!("{}", event))?,
+    }
+    Ok(())
+}
+
 fn cmd_config(cfg: &Config) -> Result<()> {
     let path = Config::init()?;
     let editor = cfg.resolve_editor();
@@ -964,7 +1147,7 @@ fn cmd_completions(shell: Option<clap_complete::Shell>, cfg: &Config) -> Result<
         None => {
             let name = cfg.shell.as_deref()
                 .ok_or_else(|| anyhow::anyhow!(
-                    "no shell specified — use `mole completions <shell>` or set `shell` in ~/.mole/config.toml"
+                    "no shell specified — use `mole completions <shell>` or set `shell` in the config (`mole config`)"
                 ))?;
             name.parse::<clap_complete::Shell>()
                 .map_err(|_| anyhow::anyhow!("unknown shell '{}' in config", name))?
@@ -984,10 +1167,10 @@ fn cmd_completions(shell: Option<clap_complete::Shell>, cfg: &Config) -> Result<
     Ok(())
 }
 
-fn cmd_list_tunnel_names() -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels()?;
-    for t in &tunnels {
-        println!("{}", t.name);
+fn cmd_list_tunnel_names(root: &ConfigRoot) -> Result<()> {
+    let tunnels = ssh_config::discover_tunnels(root)?;
+    for alias in tunnels.iter().flat_map(|t| t.aliases.iter()) {
+        println!("{}", alias);
     }
     Ok(())
 }
@@ -0,0 +1,319 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+/// Where mole keeps its files.
+///
+/// With `MOLE_HOME` set, everything lives under it, laid out like the old
+/// `~/.mole`. On macOS that is still the default. Elsewhere mole follows the
+/// XDG base directories: config in `$XDG_CONFIG_HOME/mole`, PID files and the
+/// daemon socket in `$XDG_RUNTIME_DIR/mole` (cleared at boot), logs in
+/// `$XDG_STATE_HOME/mole`.
+#[derive(Debug, PartialEq)]
+pub struct Layout {
+    pub config: PathBuf,
+    pub pids: PathBuf,
+    pub socket: PathBuf,
+    pub logs: PathBuf,
+}
+
+impl Layout {
+    /// The `~/.mole`-style layout under one directory.
+    fn under(dir: &Path) -> Self {
+        Layout {
+            config: dir.join("config.toml"),
+            pids: dir.join("pids"),
+            socket: dir.join("mole.sock"),
+            logs: dir.join("logs"),
+        }
+    }
+}
+
+/// Resolve the layout from environment variables (`var`), the home directory
+/// and whether to use XDG directories at all.
+fn resolve(var: impl Fn(&str) -> Option<PathBuf>, home: &Path, xdg: bool) -> Layout {
+    if let Some(dir) = var("MOLE_HOME") {
+        return Layout::under(&dir);
+    }
+    if !xdg {
+        return Layout::under(&home.join(".mole"));
+    }
+    // The spec says relative values are invalid and to be ignored
+    let base = |name: &str, default: PathBuf| var(name).filter(|p| p.is_absolute()).unwrap_or(default);
+    let state = base("XDG_STATE_HOME", home.join(".local").join("state")).join("mole");
+    let runtime = var("XDG_RUNTIME_DIR")
+        .filter(|p| p.is_absolute())
+        .map(|dir| dir.join("mole"))
+        // No runtime dir (some cron and ssh sessions): keep them with the state
+        .unwrap_or_else(|| state.join("run"));
+    Layout {
+        config: base("XDG_CONFIG_HOME", home.join(".config")).join("mole").join("config.toml"),
+        socket: runtime.join("mole.sock"),
+        pids: runtime,
+        logs: state,
+    }
+}
+
+fn layout() -> Result<Layout> {
+    let home = dirs::home_dir().context("cannot determine home directory")?;
+    Ok(resolve(env_path, &home, !cfg!(target_os = "macos")))
+}
+
+fn env_path(name: &str) -> Option<PathBuf> {
+    let value = std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    if value.is_none() && name == "XDG_RUNTIME_DIR" {
+        // Where pam_systemd puts it, for sessions that didn't export it
+        let dir = PathBuf::from(format!("/run/user/{}", unsafe { libc::getuid() }));
+        return dir.is_dir().then_some(dir);
+    }
+    value
+}
+
+/// mole's config file. Its directory is created if missing.
+pub fn config_file() -> Result<PathBuf> {
+    let path = layout()?.config;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(path)
+}
+
+/// Directory for PID files, created if missing.
+pub fn pid_dir() -> Result<PathBuf> {
+    private_dir(layout()?.pids)
+}
+
+/// Path of the daemon's control socket. Its directory is created if missing.
+pub fn socket() -> Result<PathBuf> {
+    let path = layout()?.socket;
+    if let Some(dir) = path.parent() {
+        private_dir(dir.to_path_buf())?;
+    }
+    Ok(path)
+}
+
+/// Directory for tunnel logs, created if missing.
+pub fn log_dir() -> Result<PathBuf> {
+    private_dir(layout()?.logs)
+}
+
+/// Every directory mole writes to, for `mole doctor`.
+pub fn dirs() -> Result<Vec<(&'static str, PathBuf)>> {
+    let layout = layout()?;
+    let mut dirs = vec![
+        ("config dir", layout.config.parent().map(Path::to_path_buf).unwrap_or_default()),
+        ("pid dir", layout.pids),
+        ("log dir", layout.logs),
+    ];
+    if let Some(dir) = layout.socket.parent()
+        && !dirs.iter().any(|(_, d)| d == dir)
+    {
+        dirs.push(("socket dir", dir.to_path_buf()));
+    }
+    Ok(dirs)
+}
+
+/// Create a directory only the user can enter, as runtime dirs must be.
+fn private_dir(dir: PathBuf) -> Result<PathBuf> {
+    if !dir.exists() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    Ok(dir)
+}
+
+/// Move files from an old `~/.mole` into the XDG layout, once. Does nothing
+/// with `MOLE_HOME` set, on macOS, or when there is no `~/.mole`. Prints
+/// what moved and how to update auto-start entries that point at it.
+pub fn migrate() {
+    let Some(home) = dirs::home_dir() else {
+        return;
+    };
+    let old = home.join(".mole");
+    if std::env::var_os("MOLE_HOME").is_some() || cfg!(target_os = "macos") || !old.is_dir() {
+        return;
+    }
+    let Ok(new) = layout() else {
+        return;
+    };
+    match migrate_from(&old, &new) {
+        Ok(moved) if moved > 0 => {
+            eprintln!(
+                "mole: moved {} file(s) from {} to {}, {} and {}",
+                moved,
+                old.display(),
+                new.config.display(),
+                new.pids.display(),
+                new.logs.display()
+            );
+            eprintln!("mole: auto-start entries still use the old paths; run `mole enable --sync --all` to update them");
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("mole: couldn't move {} to the XDG directories: {:#}", old.display(), e),
+    }
+}
+
+/// Move `old`'s config, PID files and logs to `new`, leaving anything already
+/// there alone, then remove what's left of `old` if it's empty. Refuses while
+/// a daemon listens on `old`'s socket, as it would lose track of the PID
+/// files it manages. Returns how many files moved.
+fn migrate_from(old: &Path, new: &Layout) -> Result<usize> {
+    let mut moved = 0;
+    let old_layout = Layout::under(old);
+    if UnixStream::connect(&old_layout.socket).is_ok() {
+        anyhow::bail!(
+            "a mole daemon is still running from {}; stop it (SIGTERM) and run mole again",
+            old_layout.socket.display()
+        );
+    }
+    if old_layout.config.is_file() && !new.config.exists() {
+        if let Some(dir) = new.config.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        move_file(&old_layout.config, &new.config)?;
+        moved += 1;
+    }
+    for (from, to) in [(&old_layout.pids, &new.pids), (&old_layout.logs, &new.logs)] {
+        let Ok(entries) = fs::read_dir(from) else {
+            continue;
+        };
+        private_dir(to.clone())?;
+        for entry in entries.flatten() {
+            let target = to.join(entry.file_name());
+            if entry.path().is_file() && !target.exists() {
+                move_file(&entry.path(), &target)?;
+                moved += 1;
+            }
+        }
+        let _ = fs::remove_dir(from);
+    }
+    let _ = fs::remove_dir(old);
+    Ok(moved)
+}
+
+/// Rename, or copy and delete when the target is on another filesystem
+/// (the runtime dir is usually a tmpfs).
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)?;
+            Ok(())
+        }
+        Err(e) => Err(e).with_context(|| format!("failed to move {} to {}", from.display(), to.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<PathBuf> {
+        let map: HashMap<String, PathBuf> =
+            pairs.iter().map(|(k, v)| (k.to_string(), PathBuf::from(v))).collect();
+        move |name| map.get(name).cloned()
+    }
+
+    #[test]
+    fn xdg_layout() {
+        let home = Path::new("/home/me");
+        let layout = resolve(
+            vars(&[
+                ("XDG_CONFIG_HOME", "/home/me/.cfg"),
+                ("XDG_STATE_HOME", "/home/me/.st"),
+                ("XDG_RUNTIME_DIR", "/run/user/1000"),
+            ]),
+            home,
+            true,
+        );
+        assert_eq!(layout.config, Path::new("/home/me/.cfg/mole/config.toml"));
+        assert_eq!(layout.pids, Path::new("/run/user/1000/mole"));
+        assert_eq!(layout.socket, Path::new("/run/user/1000/mole/mole.sock"));
+        assert_eq!(layout.logs, Path::new("/home/me/.st/mole"));
+    }
+
+    #[test]
+    fn xdg_defaults() {
+        let layout = resolve(vars(&[("XDG_CONFIG_HOME", "relative/ignored")]), Path::new("/home/me"), true);
+        assert_eq!(layout.config, Path::new("/home/me/.config/mole/config.toml"));
+        assert_eq!(layout.logs, Path::new("/home/me/.local/state/mole"));
+        assert_eq!(layout.pids, Path::new("/home/me/.local/state/mole/run"));
+    }
+
+    #[test]
+    fn mole_home_overrides_everything() {
+        let layout = resolve(
+            vars(&[("MOLE_HOME", "/srv/mole"), ("XDG_RUNTIME_DIR", "/run/user/1000")]),
+            Path::new("/home/me"),
+            true,
+        );
+        assert_eq!(layout, Layout::under(Path::new("/srv/mole")));
+        assert_eq!(layout.pids, Path::new("/srv/mole/pids"));
+
+        let mac = resolve(vars(&[]), Path::new("/Users/me"), false);
+        assert_eq!(mac.config, Path::new("/Users/me/.mole/config.toml"));
+    }
+
+    #[test]
+    fn migrates_old_mole_dir() {
+        let root = std::env::temp_dir().join("mole_test_migrate");
+        let _ = fs::remove_dir_all(&root);
+        let old = root.join(".mole");
+        fs::create_dir_all(old.join("pids")).unwrap();
+        fs::create_dir_all(old.join("logs")).unwrap();
+        fs::write(old.join("config.toml"), "backend = \"ssh\"\n").unwrap();
+        fs::write(old.join("pids").join("db.pid"), "42\n").unwrap();
+        fs::write(old.join("logs").join("db.log"), "old log\n").unwrap();
+        fs::write(old.join("logs").join("web.log"), "old web log\n").unwrap();
+
+        let new = Layout {
+            config: root.join("config").join("mole").join("config.toml"),
+            pids: root.join("run").join("mole"),
+            socket: root.join("run").join("mole").join("mole.sock"),
+            logs: root.join("state").join("mole"),
+        };
+        // Already in the new place: kept, and the old copy left behind
+        fs::create_dir_all(&new.logs).unwrap();
+        fs::write(new.logs.join("web.log"), "new web log\n").unwrap();
+
+        assert_eq!(migrate_from(&old, &new).unwrap(), 3);
+        assert_eq!(fs::read_to_string(&new.config).unwrap(), "backend = \"ssh\"\n");
+        assert_eq!(fs::read_to_string(new.pids.join("db.pid")).unwrap(), "42\n");
+        assert_eq!(fs::read_to_string(new.logs.join("db.log")).unwrap(), "old log\n");
+        assert_eq!(fs::read_to_string(new.logs.join("web.log")).unwrap(), "new web log\n");
+        assert!(!old.join("pids").exists());
+        assert!(old.join("logs").join("web.log").exists());
+
+        // Nothing left to move the second time
+        assert_eq!(migrate_from(&old, &new).unwrap(), 0);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn leaves_old_dir_while_daemon_runs() {
+        let root = std::env::temp_dir().join("mole_test_migrate_daemon");
+        let _ = fs::remove_dir_all(&root);
+        let old = root.join(".mole");
+        fs::create_dir_all(old.join("pids")).unwrap();
+        fs::write(old.join("pids").join("db.pid"), "42\n").unwrap();
+        let new = Layout::under(&root.join("new"));
+
+        let daemon = std::os::unix::net::UnixListener::bind(old.join("mole.sock")).unwrap();
+        assert!(migrate_from(&old, &new).is_err());
+        assert!(old.join("pids").join("db.pid").exists());
+
+        // A socket nobody listens on is left over from a crash
+        drop(daemon);
+        assert_eq!(migrate_from(&old, &new).unwrap(), 1);
+        assert!(new.pids.join("db.pid").exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
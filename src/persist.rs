@@ -0,0 +1,525 @@
+use anyhow::Result;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, Persistence};
+use crate::crontab::Crontab;
+use crate::launchd::Launchd;
+use crate::process::{self, LaunchSpec};
+use crate::ssh_config::ConfigRoot;
+use crate::systemd::Systemd;
+use crate::tunnel::TunnelHost;
+use crate::xdg_autostart::XdgAutostart;
+
+/// A way to start a tunnel at login: launchd agents, systemd user units,
+/// XDG autostart entries or crontab `@reboot` lines.
+pub trait AutoStart: Sync {
+    /// Name shown to the user, as in the `persistence` config key.
+    fn name(&self) -> &'static str;
+    /// Whether this backend can work on this machine.
+    fn is_available(&self) -> bool;
+    /// Check if a tunnel has an entry with this backend.
+    fn is_enabled(&self, name: &str) -> bool;
+    /// Install an entry starting the tunnel at login.
+    fn enable(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<()>;
+    /// Remove the tunnel's entry.
+    fn disable(&self, name: &str) -> Result<()>;
+    /// The entry `enable` would install for the tunnel now.
+    fn render(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<String>;
+    /// The entry installed for the tunnel, as written.
+    fn installed(&self, name: &str) -> Option<String>;
+
+    /// Whether the backend restarts the tunnel when it exits. Such tunnels
+    /// have to be started and stopped through it, or the two race.
+    fn supervises(&self) -> bool {
+        false
+    }
+    /// PID of the tunnel process the backend is running now.
+    fn main_pid(&self, _name: &str) -> Option<u32> {
+        None
+    }
+    /// [`main_pid`](Self::main_pid) of each of `names` that has one. Backends
+    /// that can answer for all of them with one query override this.
+    fn main_pids(&self, names: &[&str]) -> HashMap<String, u32> {
+        names
+            .iter()
+            .filter_map(|name| Some((name.to_string(), self.main_pid(name)?)))
+            .collect()
+    }
+    /// Start the tunnel through the backend.
+    fn start(&self, name: &str) -> Result<()> {
+        anyhow::bail!("{} can't start '{}' on demand", self.name(), name)
+    }
+    /// Stop the tunnel through the backend, leaving it enabled.
+    fn stop(&self, name: &str) -> Result<()> {
+        anyhow::bail!("{} can't stop '{}' on demand", self.name(), name)
+    }
+}
+
+/// Who keeps a running tunnel up.
+#[derive(Clone, Copy)]
+pub enum Owner {
+    /// mole started it, or adopted it; stopping it is final.
+    Mole,
+    /// A service manager runs it and restarts it when it exits.
+    Service(&'static dyn AutoStart),
+}
+
+impl Owner {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Owner::Mole => "mole",
+            Owner::Service(backend) => backend.name(),
+        }
+    }
+}
+
+/// How long a service manager gets to bring up a tunnel's process.
+const SERVICE_START: Duration = Duration::from_secs(5);
+
+/// Every backend, in the order `persistence = "auto"` tries them.
+pub fn backends() -> [&'static dyn AutoStart; 4] {
+    [&Launchd, &Systemd, &XdgAutostart, &Crontab]
+}
+
+/// The backend `mole enable` uses with this config.
+pub fn chosen(cfg: &Config) -> Result<&'static dyn AutoStart> {
+    let backend: &'static dyn AutoStart = match cfg.persistence {
+        Persistence::Auto => {
+            return backends()
+                .into_iter()
+                .find(|b| b.is_available())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "no way to start tunnels at login here (tried launchd, systemd, XDG autostart and crontab)"
+                    )
+                });
+        }
+        Persistence::Launchd => &Launchd,
+        Persistence::Systemd => &Systemd,
+        Persistence::XdgAutostart => &XdgAutostart,
+        Persistence::Crontab => &Crontab,
+    };
+    if !backend.is_available() {
+        anyhow::bail!("persistence = \"{}\" is not available on this system", backend.name());
+    }
+    Ok(backend)
+}
+
+/// The backends a tunnel has an entry with, whichever created it.
+pub fn enabled_by(name: &str) -> Vec<&'static dyn AutoStart> {
+    backends().into_iter().filter(|b| b.is_enabled(name)).collect()
+}
+
+/// Check if a tunnel is set to start at login, by any backend.
+pub fn is_enabled(name: &str) -> bool {
+    backends().iter().any(|b| b.is_enabled(name))
+}
+
+/// The enabled backend that restarts a tunnel when it exits, if any.
+pub fn supervisor(name: &str) -> Option<&'static dyn AutoStart> {
+    backends().into_iter().find(|b| b.supervises() && b.is_enabled(name))
+}
+
+/// Who owns a tunnel's running process `pid`.
+pub fn owner(name: &str, pid: u32) -> Owner {
+    match supervisor(name) {
+        Some(backend) if backend.main_pid(name) == Some(pid) => Owner::Service(backend),
+        _ => Owner::Mole,
+    }
+}
+
+/// Auto-start state of many tunnels, for `list`: every entry is read and
+/// every service manager asked once, not once per tunnel and question.
+#[derive(Default)]
+pub struct Survey {
+    enabled: HashSet<String>,
+    drifted: HashSet<String>,
+    /// The backend running each tunnel, and the PID it runs.
+    services: HashMap<String, (&'static dyn AutoStart, u32)>,
+}
+
+impl Survey {
+    pub fn take(tunnels: &[TunnelHost], root: &ConfigRoot, cfg: &Config) -> Self {
+        surveying(|| Survey::take_with(&backends(), tunnels, root, cfg))
+    }
+
+    fn take_with(
+        backends: &[&'static dyn AutoStart],
+        tunnels: &[TunnelHost],
+        root: &ConfigRoot,
+        cfg: &Config,
+    ) -> Self {
+        let mut survey = Survey::default();
+        for &backend in backends {
+            let mut supervised = Vec::new();
+            for tunnel in tunnels.iter().filter(|t| backend.is_enabled(&t.name)) {
+                survey.enabled.insert(tunnel.name.clone());
+                if is_drifted(backend, tunnel, root, cfg) {
+                    survey.drifted.insert(tunnel.name.clone());
+                }
+                if backend.supervises() {
+                    supervised.push(tunnel.name.as_str());
+                }
+            }
+            if supervised.is_empty() {
+                continue;
+            }
+            for (name, pid) in backend.main_pids(&supervised) {
+                survey.services.entry(name).or_insert((backend, pid));
+            }
+        }
+        survey
+    }
+
+    /// See [`is_enabled`].
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.enabled.contains(name)
+    }
+
+    /// Whether any of the tunnel's entries has [`drifted`].
+    pub fn is_drifted(&self, name: &str) -> bool {
+        self.drifted.contains(name)
+    }
+
+    /// See [`owner`].
+    pub fn owner(&self, name: &str, pid: u32) -> Owner {
+        match self.services.get(name) {
+            Some(&(backend, running)) if running == pid => Owner::Service(backend),
+            _ => Owner::Mole,
+        }
+    }
+}
+
+thread_local! {
+    /// Answers to [`memo`] while a [`Survey`] is taken on this thread.
+    static MEMO: RefCell<Option<HashMap<String, String>>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with [`memo`] remembering answers until it returns.
+fn surveying<T>(f: impl FnOnce() -> T) -> T {
+    MEMO.set(Some(HashMap::new()));
+    let result = f();
+    MEMO.set(None);
+    result
+}
+
+/// `ask()`, answered once per [`Survey`] for each `key`: a survey asks the
+/// same thing (the crontab, where autossh is) for every tunnel. Outside one,
+/// always asks, so long-running processes never see stale answers.
+pub fn memo(key: &str, ask: impl FnOnce() -> Result<String>) -> Result<String> {
+    let known = MEMO.with_borrow(|memo| memo.as_ref().map(|m| m.get(key).cloned()));
+    match known {
+        None => ask(),
+        Some(Some(answer)) => Ok(answer),
+        Some(None) => {
+            let answer = ask()?;
+            MEMO.with_borrow_mut(|memo| {
+                if let Some(memo) = memo {
+                    memo.insert(key.to_string(), answer.clone());
+                }
+            });
+            Ok(answer)
+        }
+    }
+}
+
+/// Start a tunnel through its service manager and wait for the process.
+/// Returns its PID.
+pub fn start_service(service: &dyn AutoStart, name: &str) -> Result<u32> {
+    service.start(name)?;
+    let deadline = Instant::now() + SERVICE_START;
+    let pid = loop {
+        if let Some(pid) = service.main_pid(name)
+            && process::is_pid_alive(pid)
+        {
+            break pid;
+        }
+        if Instant::now() >= deadline {
+            anyhow::bail!("{} didn't start '{}' — see `mole logs {}`", service.name(), name, name);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+
+    // Brief pause to let the tunnel fail fast, as when mole starts it
+    std::thread::sleep(Duration::from_secs(1));
+    if service.main_pid(name) != Some(pid) {
+        anyhow::bail!(
+            "'{}' exited immediately under {} — is the port already in use or the host unreachable?",
+            name,
+            service.name()
+        );
+    }
+    Ok(pid)
+}
+
+/// Stop a tunnel through its service manager and wait for process `pid` to exit.
+pub fn stop_service(service: &dyn AutoStart, tunnel: &TunnelHost, pid: u32, cfg: &Config) -> Result<()> {
+    service.stop(&tunnel.name)?;
+    // The service manager escalates to SIGKILL after stop_timeout too
+    let deadline = Instant::now() + Duration::from_secs(cfg.stop_timeout + 5);
+    while process::is_pid_alive(pid) {
+        if Instant::now() >= deadline {
+            anyhow::bail!("{} stopped '{}', but process {} is still running", service.name(), tunnel.name, pid);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let _ = fs::remove_file(process::pid_file(&tunnel.name)?);
+    Ok(())
+}
+
+/// Set a tunnel to start at login with the configured backend. Returns the
+/// backend's name. A running tunnel is handed over to a service manager
+/// that would otherwise start a second copy.
+pub fn enable(tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<&'static str> {
+    enable_with(chosen(cfg)?, tunnel, root, cfg)
+}
+
+/// [`enable`] with a given backend.
+pub fn enable_with(
+    backend: &'static dyn AutoStart,
+    tunnel: &TunnelHost,
+    root: &ConfigRoot,
+    cfg: &Config,
+) -> Result<&'static str> {
+    let handover = backend.supervises() && process::is_active(&tunnel.name)?;
+    if handover {
+        process::stop_tunnel(tunnel, cfg)?;
+    }
+    backend.enable(tunnel, root, cfg)?;
+    if handover {
+        start_service(backend, &tunnel.name)?;
+    }
+    Ok(backend.name())
+}
+
+/// The backends whose entry for a tunnel is no longer what they would write
+/// now, e.g. after autossh moved, the home directory changed or the config
+/// was edited.
+pub fn drifted(tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Vec<&'static dyn AutoStart> {
+    enabled_by(&tunnel.name)
+        .into_iter()
+        .filter(|backend| is_drifted(*backend, tunnel, root, cfg))
+        .collect()
+}
+
+fn is_drifted(backend: &dyn AutoStart, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> bool {
+    let Some(installed) = backend.installed(&tunnel.name) else {
+        return false;
+    };
+    // An entry that can't be written now (autossh gone) can't be right either
+    !backend.render(tunnel, root, cfg).is_ok_and(|current| current == installed)
+}
+
+/// Rewrite a drifted entry and reload it. A tunnel the service manager was
+/// running is restarted so it runs the new command.
+pub fn sync(backend: &dyn AutoStart, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<()> {
+    let running = backend.main_pid(&tunnel.name);
+    if let Some(pid) = running {
+        stop_service(backend, tunnel, pid, cfg)?;
+    } else if backend.supervises() {
+        // Unload the old definition even when nothing runs under it
+        backend.stop(&tunnel.name)?;
+    }
+    backend.enable(tunnel, root, cfg)?;
+    if running.is_some() {
+        start_service(backend, &tunnel.name)?;
+    }
+    Ok(())
+}
+
+/// Remove every entry starting a tunnel at login. Returns the names of the
+/// backends cleaned up.
+pub fn disable(name: &str) -> Result<Vec<&'static str>> {
+    let enabled = enabled_by(name);
+    if enabled.is_empty() {
+        anyhow::bail!("tunnel '{}' is not enabled for auto-start", name);
+    }
+    for backend in &enabled {
+        backend.disable(name)?;
+    }
+    Ok(enabled.iter().map(|b| b.name()).collect())
+}
+
+/// A `sh` command line running `program` with the spec's arguments and
+/// environment, output discarded and errors appended to `log_path`. For
+/// backends without their own environment or log settings.
+pub fn shell_command(program: &str, spec: &LaunchSpec, log_path: &Path) -> String {
+    let mut words = vec!["exec".to_string()];
+    if !spec.env.is_empty() {
+        words.push("env".to_string());
+        words.extend(spec.env.iter().map(|(k, v)| sh_quote(&format!("{}={}", k, v))));
+    }
+    words.push(sh_quote(program));
+    words.extend(spec.args.iter().map(|a| sh_quote(a)));
+    format!(
+        "{} >/dev/null 2>>{}",
+        words.join(" "),
+        sh_quote(&log_path.to_string_lossy())
+    )
+}
+
+/// Quote a word for `sh`, leaving plain words alone.
+fn sh_quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@+%".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Backend;
+    use std::path::PathBuf;
+
+    /// A backend with one installed entry, rendering the tunnel's ssh options.
+    struct Fake {
+        installed: Option<String>,
+    }
+
+    impl AutoStart for Fake {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+        fn is_available(&self) -> bool {
+            true
+        }
+        fn is_enabled(&self, _name: &str) -> bool {
+            self.installed.is_some()
+        }
+        fn enable(&self, _tunnel: &TunnelHost, _root: &ConfigRoot, _cfg: &Config) -> Result<()> {
+            Ok(())
+        }
+        fn disable(&self, _name: &str) -> Result<()> {
+            Ok(())
+        }
+        fn render(&self, tunnel: &TunnelHost, _root: &ConfigRoot, _cfg: &Config) -> Result<String> {
+            if tunnel.ssh_options.is_empty() {
+                anyhow::bail!("autossh not found in PATH");
+            }
+            Ok(tunnel.ssh_options.join(" "))
+        }
+        fn installed(&self, _name: &str) -> Option<String> {
+            self.installed.clone()
+        }
+    }
+
+    fn tunnel(ssh_options: &[&str]) -> TunnelHost {
+        TunnelHost {
+            ssh_options: ssh_options.iter().map(|o| o.to_string()).collect(),
+            ..TunnelHost::named("db")
+        }
+    }
+
+    #[test]
+    fn drift_compares_installed_and_rendered_entries() {
+        let root = ConfigRoot {
+            path: PathBuf::from("/home/me/.ssh/config"),
+            ssh_dir: PathBuf::from("/home/me/.ssh"),
+            custom: false,
+        };
+        let cfg = Config::default();
+        let installed = |entry: &str| Fake {
+            installed: Some(entry.to_string()),
+        };
+
+        assert!(!is_drifted(&installed("-v"), &tunnel(&["-v"]), &root, &cfg));
+        assert!(is_drifted(&installed("-v"), &tunnel(&["-q"]), &root, &cfg));
+        // Can't be rendered now: whatever is installed is stale
+        assert!(is_drifted(&installed("-v"), &tunnel(&[]), &root, &cfg));
+        // Nothing installed, nothing to drift
+        assert!(!is_drifted(&Fake { installed: None }, &tunnel(&["-q"]), &root, &cfg));
+    }
+
+    #[test]
+    fn survey_reads_each_backend() {
+        let root = ConfigRoot {
+            path: PathBuf::from("/home/me/.ssh/config"),
+            ssh_dir: PathBuf::from("/home/me/.ssh"),
+            custom: false,
+        };
+        let backend: &'static Fake = Box::leak(Box::new(Fake {
+            installed: Some("-v".to_string()),
+        }));
+        let tunnels = [
+            TunnelHost {
+                name: "current".to_string(),
+                ..tunnel(&["-v"])
+            },
+            TunnelHost {
+                name: "stale".to_string(),
+                ..tunnel(&["-q"])
+            },
+        ];
+        let survey = Survey::take_with(&[backend], &tunnels, &root, &Config::default());
+        assert!(survey.is_enabled("current") && survey.is_enabled("stale"));
+        assert!(!survey.is_drifted("current"));
+        assert!(survey.is_drifted("stale"));
+        assert!(!survey.is_enabled("other"));
+        // Fake doesn't supervise, so mole owns whatever runs
+        assert_eq!(survey.owner("current", 42).name(), "mole");
+    }
+
+    #[test]
+    fn memo_lasts_one_survey() {
+        let asked = std::cell::Cell::new(0);
+        let ask = || {
+            asked.set(asked.get() + 1);
+            Ok("/usr/bin/autossh".to_string())
+        };
+        surveying(|| {
+            assert_eq!(memo("which autossh", ask).unwrap(), "/usr/bin/autossh");
+            assert_eq!(memo("which autossh", ask).unwrap(), "/usr/bin/autossh");
+            // Failures aren't remembered
+            assert!(memo("crontab -l", || anyhow::bail!("no crontab")).is_err());
+            assert_eq!(memo("crontab -l", || Ok(String::new())).unwrap(), "");
+        });
+        assert_eq!(asked.get(), 1);
+        memo("which autossh", ask).unwrap();
+        memo("which autossh", ask).unwrap();
+        assert_eq!(asked.get(), 3);
+    }
+
+    #[test]
+    fn shell_command_quotes_words() {
+        let spec = LaunchSpec {
+            backend: Backend::Autossh,
+            program: "autossh".to_string(),
+            args: vec![
+                "-N".to_string(),
+                "-o".to_string(),
+                "ProxyCommand=nc -x localhost:1080 %h %p".to_string(),
+                "it's".to_string(),
+            ],
+            env: vec![("AUTOSSH_PORT".to_string(), "0".to_string())],
+        };
+        assert_eq!(
+            shell_command("/usr/bin/autossh", &spec, Path::new("/home/me/my logs/db.log")),
+            "exec env AUTOSSH_PORT=0 /usr/bin/autossh -N -o 'ProxyCommand=nc -x localhost:1080 %h %p' \
+             'it'\\''s' >/dev/null 2>>'/home/me/my logs/db.log'"
+        );
+    }
+
+    #[test]
+    fn shell_command_without_env() {
+        let spec = LaunchSpec {
+            backend: Backend::Ssh,
+            program: "/usr/bin/mole".to_string(),
+            args: vec!["supervise".to_string(), "db".to_string()],
+            env: Vec::new(),
+        };
+        assert_eq!(
+            shell_command("/usr/bin/mole", &spec, Path::new("/tmp/db.log")),
+            "exec /usr/bin/mole supervise db >/dev/null 2>>/tmp/db.log"
+        );
+    }
+}
@@ -1,180 +1,273 @@
 use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::fs::{self, OpenOptions};
+use std::os::unix::process::CommandExt;
 use std::path::PathBuf;
-use std::process::Command;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::config::{Backend, Config};
+use crate::daemon;
 use crate::health;
-use crate::tunnel::TunnelHost;
+use crate::paths;
+use crate::persist;
+use crate::proctable::{ProcessInfo, ProcessTable};
+use crate::ssh_config::ConfigRoot;
+use crate::tunnel::{Endpoint, TunnelHost};
+
+pub fn pid_file(name: &str) -> Result<PathBuf> {
+    Ok(paths::pid_dir()?.join(format!("{}.pid", name)))
+}
 
-/// Directory where PID files are stored.
-fn pid_dir() -> Result<PathBuf> {
-    let dir = dirs::home_dir()
-        .context("cannot determine home directory")?
-        .join(".mole")
-        .join("pids");
-    fs::create_dir_all(&dir)?;
-    Ok(dir)
+/// Path to the log file for a specific tunnel.
+pub fn log_file(name: &str) -> Result<PathBuf> {
+    Ok(paths::log_dir()?.join(format!("{}.log", name)))
 }
 
-fn pid_file(name: &str) -> Result<PathBuf> {
-    Ok(pid_dir()?.join(format!("{}.pid", name)))
+/// Check if a process with the given PID is running.
+pub fn is_pid_alive(pid: u32) -> bool {
+    unsafe { libc::kill(pid as i32, 0) == 0 }
 }
 
-/// Directory where tunnel log files are stored.
-pub fn log_dir() -> Result<PathBuf> {
-    let dir = dirs::home_dir()
-        .context("cannot determine home directory")?
-        .join(".mole")
-        .join("logs");
-    fs::create_dir_all(&dir)?;
-    Ok(dir)
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
 
-/// Path to the log file for a specific tunnel.
-pub fn log_file(name: &str) -> Result<PathBuf> {
-    Ok(log_dir()?.join(format!("{}.log", name)))
+/// How long a SIGKILLed tunnel gets to disappear and free its ports.
+const KILL_WAIT: Duration = Duration::from_secs(2);
+
+/// Seconds a recorded start time may differ from the OS's: ps only reports
+/// elapsed time, so start times derived from it drift by a second or so.
+const START_TOLERANCE: u64 = 2;
+
+/// Whether `pid` is alive and running tunnel `name` (not some process that
+/// inherited the PID after the tunnel exited).
+pub fn runs_tunnel(pid: u32, name: &str) -> bool {
+    ProcessInfo::read(pid).is_some_and(|p| tunnel_of(&p.argv) == Some(name))
 }
 
-/// Check if a process with the given PID is running.
-fn is_pid_alive(pid: u32) -> bool {
-    unsafe { libc::kill(pid as i32, 0) == 0 }
+/// What a PID file records about the process it points at.
+#[derive(Debug, PartialEq)]
+struct PidRecord {
+    pid: u32,
+    /// Unix time the process started (as the OS reports it).
+    start_time: Option<u64>,
+    /// The process's command line, arguments joined by spaces.
+    command: Option<String>,
 }
 
-/// Get process start time from the OS (for adopted processes).
-fn get_process_start_epoch(pid: u32) -> Option<u64> {
-    let output = Command::new("ps")
-        .args(["-p", &pid.to_string(), "-o", "lstart="])
-        .output()
-        .ok()?;
-    if !output.status.success() {
-        return None;
-    }
-    // Parse the lstart format, e.g. "Thu Feb 13 22:14:05 2026"
-    // Simpler approach: use ps -o etime= to get elapsed, subtract from now
-    let output = Command::new("ps")
-        .args(["-p", &pid.to_string(), "-o", "etime="])
-        .output()
-        .ok()?;
-    if !output.status.success() {
-        return None;
-    }
-    let etime = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    let elapsed_secs = parse_etime(&etime)?;
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
-    Some(now.saturating_sub(elapsed_secs))
-}
-
-/// Parse ps etime format: [[dd-]hh:]mm:ss
-fn parse_etime(s: &str) -> Option<u64> {
-    let s = s.trim();
-    let (days, rest) = if let Some(pos) = s.find('-') {
-        let d: u64 = s[..pos].parse().ok()?;
-        (d, &s[pos + 1..])
-    } else {
-        (0, s)
-    };
+impl PidRecord {
+    fn parse(content: &str) -> Option<Self> {
+        let mut lines = content.lines();
+        let pid = lines.next()?.trim().parse().ok()?;
+        let start_time = lines.next().and_then(|l| l.trim().parse().ok());
+        let command = lines.next().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string);
+        Some(PidRecord {
+            pid,
+            start_time,
+            command,
+        })
+    }
 
-    let parts: Vec<&str> = rest.split(':').collect();
-    let (hours, minutes, seconds) = match parts.len() {
-        3 => {
-            let h: u64 = parts[0].parse().ok()?;
-            let m: u64 = parts[1].parse().ok()?;
-            let s: u64 = parts[2].parse().ok()?;
-            (h, m, s)
-        }
-        2 => {
-            let m: u64 = parts[0].parse().ok()?;
-            let s: u64 = parts[1].parse().ok()?;
-            (0, m, s)
-        }
-        _ => return None,
-    };
+    /// Whether the process now at `pid` (`live`, None if nothing is) is still
+    /// the one this record was written for: same start time and command line.
+    /// Older files without a command line fall back to checking that it runs
+    /// tunnel `name`.
+    fn matches(&self, name: &str, live: Option<&ProcessInfo>) -> bool {
+        let Some(live) = live else {
+            return false;
+        };
+        let same_command = match self.command {
+            Some(ref command) => {
+                normalize_command(command) == normalize_command(&live.argv.join(" "))
+            }
+            None => tunnel_of(&live.argv) == Some(name),
+        };
+        let same_start = match (self.start_time, live.start_time) {
+            (Some(recorded), Some(actual)) => recorded.abs_diff(actual) <= START_TOLERANCE,
+            _ => true,
+        };
+        same_command && same_start
+    }
+}
 
-    Some(days * 86400 + hours * 3600 + minutes * 60 + seconds)
+/// Collapse whitespace so /proc (exact argv) and ps (space-joined) output compare equal.
+fn normalize_command(command: &str) -> String {
+    command.split_whitespace().collect::<Vec<_>>().join(" ")
 }
 
-/// Write a PID file with format: "<pid>\n<unix_timestamp>"
-fn write_pid_file(name: &str, pid: u32, start_time: u64) -> Result<()> {
+/// Write a PID file recording the process's identity:
+/// "<pid>\n<start unix_timestamp>\n<command line>"
+fn write_pid_file(name: &str, process: &ProcessInfo) -> Result<()> {
     let path = pid_file(name)?;
-    fs::write(&path, format!("{}\n{}", pid, start_time))?;
+    let start_time = process.start_time.unwrap_or_else(now);
+    fs::write(
+        &path,
+        format!("{}\n{}\n{}\n", process.pid, start_time, process.argv.join(" ")),
+    )?;
     Ok(())
 }
 
-/// Read PID and start timestamp from a PID file.
-fn read_pid_file(name: &str) -> Result<Option<(u32, Option<u64>)>> {
+/// Read a PID file, removing it if it's unreadable.
+fn read_pid_file(name: &str) -> Result<Option<PidRecord>> {
     let path = pid_file(name)?;
     if !path.exists() {
         return Ok(None);
     }
-    let content = fs::read_to_string(&path)?;
-    let mut lines = content.lines();
-    let pid: u32 = match lines.next().and_then(|l| l.trim().parse().ok()) {
-        Some(p) => p,
-        None => {
-            let _ = fs::remove_file(&path);
-            return Ok(None);
-        }
-    };
-    let start_time: Option<u64> = lines.next().and_then(|l| l.trim().parse().ok());
-    Ok(Some((pid, start_time)))
+    let record = PidRecord::parse(&fs::read_to_string(&path)?);
+    if record.is_none() {
+        let _ = fs::remove_file(&path);
+    }
+    Ok(record)
 }
 
-/// Find a running autossh process for this tunnel via pgrep.
-fn find_autossh_pid(name: &str) -> Option<u32> {
-    let output = Command::new("pgrep")
-        .args(["-f", &format!("autossh.*{}", name)])
-        .output()
-        .ok()?;
+/// Whether a tunnel's PID file points at its live process (alive, same start
+/// time and command line).
+pub fn pid_file_is_live(name: &str, table: &ProcessTable) -> bool {
+    matches!(read_pid_file(name), Ok(Some(record)) if record.matches(name, table.get(record.pid)))
+}
 
-    if !output.status.success() {
-        return None;
+/// Every PID file on disk as (tunnel name, PID), without touching stale ones.
+/// The PID is None when the file doesn't start with a number.
+pub fn pid_files() -> Result<Vec<(String, Option<u32>)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(paths::pid_dir()?)?.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("pid") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let pid = fs::read_to_string(&path)
+            .ok()
+            .and_then(|c| c.lines().next().and_then(|l| l.trim().parse().ok()));
+        entries.push((name.to_string(), pid));
     }
+    entries.sort();
+    Ok(entries)
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    stdout.lines().next()?.trim().parse().ok()
+/// The tunnel a command line runs, if it is one mole starts:
+/// `autossh -N [ssh options] <name>` or `mole supervise <name> -- ...`.
+pub fn tunnel_of(argv: &[String]) -> Option<&str> {
+    let program = argv.first()?.rsplit('/').next()?;
+    match program {
+        "autossh" => {
+            let (host, options) = argv[1..].split_last()?;
+            (options.iter().any(|a| a == "-N") && !host.starts_with('-')).then_some(host.as_str())
+        }
+        "mole" if argv.get(1).map(String::as_str) == Some("supervise") => {
+            argv.get(2).map(String::as_str)
+        }
+        _ => None,
+    }
 }
 
-/// Get the active PID for a tunnel. Checks PID file first, then falls back to pgrep.
-/// Adopts externally-started autossh processes by writing a PID file.
-pub fn read_pid(name: &str) -> Result<Option<u32>> {
-    // First check our PID file
-    if let Some((pid, _)) = read_pid_file(name)? {
-        if is_pid_alive(pid) {
-            return Ok(Some(pid));
+/// PIDs of every autossh or supervisor process running exactly this tunnel.
+fn find_tunnel_pids(name: &str, table: &ProcessTable) -> Vec<u32> {
+    let mut pids: Vec<u32> = table
+        .iter()
+        .filter(|p| tunnel_of(&p.argv) == Some(name))
+        .map(|p| p.pid)
+        .collect();
+    pids.sort();
+    pids
+}
+
+/// A tunnel's running process.
+#[derive(Debug, Clone)]
+pub struct Running {
+    pub pid: u32,
+    /// Unix time the process started.
+    pub started: Option<u64>,
+    /// Memory and CPU of the process and its children (the ssh under autossh).
+    pub rss_kb: u64,
+    pub cpu_secs: u64,
+}
+
+impl Running {
+    pub fn new(pid: u32, started: Option<u64>, table: &ProcessTable) -> Self {
+        let (rss_kb, cpu_secs) = table.tree_usage(pid);
+        Running {
+            pid,
+            started,
+            rss_kb,
+            cpu_secs,
         }
-        // Stale PID file, clean up
+    }
+}
+
+/// Find a tunnel's process in `table`: the PID file first (verified against
+/// the table), then any process running exactly this tunnel, which is adopted
+/// by writing a PID file.
+fn locate(name: &str, table: &ProcessTable) -> Result<Option<Running>> {
+    if let Some(record) = read_pid_file(name)? {
+        let live = table.get(record.pid);
+        if record.matches(name, live) {
+            let started = record.start_time.or(live.and_then(|p| p.start_time));
+            return Ok(Some(Running::new(record.pid, started, table)));
+        }
+        // Stale PID file (process gone, or its PID now belongs to something else), clean up
         let _ = fs::remove_file(pid_file(name)?);
     }
 
-    // Fallback: check for autossh processes started outside of mole
-    if let Some(pid) = find_autossh_pid(name) {
-        // Adopt it — write PID file with process start time from OS
-        let start_time = get_process_start_epoch(pid)
-            .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
-        let _ = write_pid_file(name, pid, start_time);
-        return Ok(Some(pid));
+    // Fallback: check for tunnel processes started outside of mole
+    match find_tunnel_pids(name, table).as_slice() {
+        [] => Ok(None),
+        &[pid] => {
+            // Adopt it — write PID file with its identity from the process table
+            let process = table.get(pid).context("process vanished")?;
+            let _ = write_pid_file(name, process);
+            Ok(Some(Running::new(pid, process.start_time, table)))
+        }
+        pids => {
+            // Adopting one at random could mean stopping the wrong one later
+            let pids: Vec<String> = pids.iter().map(u32::to_string).collect();
+            anyhow::bail!(
+                "{} processes are running tunnel '{}' (pids {}); stop the extra ones with kill",
+                pids.len(),
+                name,
+                pids.join(", ")
+            )
+        }
     }
+}
 
-    Ok(None)
+/// The running process of every tunnel in `names` that has one, from a single
+/// process table scan (plus one daemon request when the daemon is running).
+/// Tunnels whose state can't be determined (duplicates) are left out.
+pub fn running_tunnels<'a>(names: impl IntoIterator<Item = &'a str>) -> HashMap<String, Running> {
+    let table = ProcessTable::scan();
+    if let Some(listed) = daemon::with_client(daemon::Client::list) {
+        let wanted: Vec<&str> = names.into_iter().collect();
+        return listed
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|t| wanted.contains(&t.name.as_str()))
+            .map(|t| {
+                let running = Running::new(t.pid, Some(t.started), &table);
+                (t.name, running)
+            })
+            .collect();
+    }
+    names
+        .into_iter()
+        .filter_map(|name| Some((name.to_string(), locate(name, &table).ok()??)))
+        .collect()
 }
 
-/// Get the start time (unix epoch) for an active tunnel.
-/// Falls back to querying the OS if the PID file lacks a timestamp.
-pub fn get_start_time(name: &str) -> Result<Option<u64>> {
-    if let Some((pid, start_time)) = read_pid_file(name)? {
-        if is_pid_alive(pid) {
-            if let Some(ts) = start_time {
-                return Ok(Some(ts));
-            }
-            // PID file has no timestamp (old format) — look it up and backfill
-            if let Some(ts) = get_process_start_epoch(pid) {
-                let _ = write_pid_file(name, pid, ts);
-                return Ok(Some(ts));
-            }
-        }
+/// Get the active PID for a tunnel. Asks the daemon when one is running;
+/// otherwise checks the PID file, then looks for a matching process.
+/// Adopts externally-started tunnel processes by writing a PID file.
+pub fn read_pid(name: &str) -> Result<Option<u32>> {
+    if let Some(status) = daemon::with_client(|client| client.status(name)) {
+        return Ok(status?.map(|t| t.pid));
     }
-    Ok(None)
+    Ok(locate(name, &ProcessTable::scan())?.map(|r| r.pid))
 }
 
 /// Check if a tunnel is currently active (has a running process).
@@ -206,78 +299,157 @@ pub fn format_uptime(start_epoch: u64) -> String {
 /// If a log file exceeds max_bytes, rename it to .log.old (replacing any
 /// previous .old file) so the new run starts with a fresh log.
 fn rotate_log(path: &std::path::Path, max_bytes: u64) {
-    if let Ok(meta) = fs::metadata(path) {
-        if meta.len() > max_bytes {
-            let mut old = path.to_path_buf();
-            old.set_extension("log.old");
-            let _ = fs::rename(path, old);
+    if let Ok(meta) = fs::metadata(path)
+        && meta.len() > max_bytes
+    {
+        let mut old = path.to_path_buf();
+        old.set_extension("log.old");
+        let _ = fs::rename(path, old);
+    }
+}
+
+/// The command that keeps a tunnel up. Shared by `start_tunnel` and the
+/// persistence backends so both run a tunnel the same way.
+#[derive(Debug)]
+pub struct LaunchSpec {
+    pub backend: Backend,
+    pub program: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+}
+
+pub fn launch_spec(tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<LaunchSpec> {
+    let backend = tunnel.backend(cfg.backend);
+    let mut ssh_args = vec!["-N".to_string()];
+    ssh_args.extend(cfg.ssh_options(tunnel));
+    ssh_args.extend(root.ssh_args());
+    ssh_args.push(tunnel.name.clone());
+
+    Ok(match backend {
+        Backend::Autossh => LaunchSpec {
+            backend,
+            program: "autossh".to_string(),
+            args: ssh_args,
+            env: cfg.autossh_env(tunnel).into_iter().collect(),
+        },
+        Backend::Ssh => {
+            let exe = std::env::current_exe().context("cannot locate the mole executable")?;
+            let mut args = vec![
+                "supervise".to_string(),
+                tunnel.name.clone(),
+                "--gate-time".to_string(),
+                cfg.gate_time.to_string(),
+                "--".to_string(),
+            ];
+            args.extend(ssh_args);
+            LaunchSpec {
+                backend,
+                program: exe.to_string_lossy().into_owned(),
+                args,
+                env: Vec::new(),
+            }
         }
+    })
+}
+
+/// Find the absolute path to a program; service managers don't search the
+/// user's PATH.
+pub fn which(program: &str) -> Result<String> {
+    if program.starts_with('/') {
+        return Ok(program.to_string());
     }
+    // Checking many auto-start entries for drift looks autossh up once
+    persist::memo(&format!("which {}", program), || {
+        let output = Command::new("which")
+            .arg(program)
+            .output()
+            .with_context(|| format!("failed to run 'which {}'", program))?;
+        if !output.status.success() {
+            anyhow::bail!("{} not found in PATH", program);
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    })
 }
 
-/// Start a tunnel using autossh. Returns the PID of the spawned process.
-pub fn start_tunnel(tunnel: &TunnelHost, max_log_bytes: u64) -> Result<u32> {
+/// Start a tunnel with its backend (autossh, or ssh under mole's supervisor),
+/// through the daemon when one is running. A tunnel enabled with a service
+/// manager that restarts it is started through that instead. Returns the PID
+/// of the tunnel process.
+pub fn start_tunnel(tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<u32> {
+    if let Some(service) = persist::supervisor(&tunnel.name) {
+        return persist::start_service(service, &tunnel.name);
+    }
+    if let Some(started) = daemon::with_client(|client| client.up(&tunnel.name)) {
+        return started;
+    }
+    Ok(spawn_tunnel(tunnel, root, cfg)?.id())
+}
+
+/// Spawn a tunnel process and record it in a PID file. The caller owns the child.
+pub fn spawn_tunnel(tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<Child> {
     if is_active(&tunnel.name)? {
         anyhow::bail!("tunnel '{}' is already active", tunnel.name);
     }
 
     // Check for port conflicts before spawning
-    let mut conflicts = Vec::new();
-    for fwd in &tunnel.forwards {
-        if !health::is_port_free(fwd.local_port) {
-            conflicts.push(fwd.local_port);
-        }
-    }
-    for fwd in &tunnel.dynamic_forwards {
-        if !health::is_port_free(fwd.listen_port) {
-            conflicts.push(fwd.listen_port);
-        }
-    }
+    let conflicts: Vec<String> = tunnel
+        .local_listeners()
+        .filter_map(health::endpoint_conflict)
+        .collect();
     if !conflicts.is_empty() {
-        let ports: Vec<String> = conflicts.iter().map(|p| p.to_string()).collect();
         anyhow::bail!(
-            "local port(s) {} already in use — stop the conflicting process first",
-            ports.join(", ")
+            "{} already in use — stop the conflicting process first",
+            conflicts.join(", ")
         );
     }
 
     let log_path = log_file(&tunnel.name)?;
-    rotate_log(&log_path, max_log_bytes);
+    rotate_log(&log_path, cfg.max_log_size);
     let log = OpenOptions::new()
         .create(true)
         .append(true)
         .open(&log_path)
         .context("failed to open log file")?;
 
-    let child = Command::new("autossh")
-        .env("AUTOSSH_PORT", "0")
-        .arg("-N")
-        .arg(&tunnel.name)
+    let spec = launch_spec(tunnel, root, cfg)?;
+    let mut child = Command::new(&spec.program)
+        // Own process group, so stopping reaches the ssh child too
+        .process_group(0)
+        .envs(spec.env.iter().map(|(k, v)| (k, v)))
+        .args(&spec.args)
         .stdin(std::process::Stdio::null())
         .stdout(std::process::Stdio::null())
         .stderr(log)
         .spawn()
-        .context("failed to spawn autossh — is it installed?")?;
+        .with_context(|| match spec.backend {
+            Backend::Autossh => "failed to spawn autossh — is it installed? (or set backend = \"ssh\")".to_string(),
+            Backend::Ssh => format!("failed to spawn {}", spec.program),
+        })?;
 
     let pid = child.id();
 
-    // Brief pause to let autossh fail fast on port conflicts / auth errors
-    std::thread::sleep(std::time::Duration::from_secs(1));
+    // Brief pause to let the backend fail fast on port conflicts / auth errors
+    std::thread::sleep(Duration::from_secs(1));
 
-    if !is_pid_alive(pid) {
+    if !matches!(child.try_wait(), Ok(None)) {
         let _ = fs::remove_file(pid_file(&tunnel.name)?);
         anyhow::bail!(
-            "autossh exited immediately — is the port already in use or the host unreachable?"
+            "{} exited immediately — is the port already in use or the host unreachable?",
+            spec.backend
         );
     }
 
-    let start_time = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    write_pid_file(&tunnel.name, pid, start_time)?;
-
-    Ok(pid)
+    let process = ProcessInfo::read(pid).unwrap_or(ProcessInfo {
+        pid,
+        ppid: std::process::id(),
+        argv: std::iter::once(spec.program.clone()).chain(spec.args.clone()).collect(),
+        start_time: None,
+        rss_kb: 0,
+        cpu_secs: 0,
+    });
+    write_pid_file(&tunnel.name, &process)?;
+
+    Ok(child)
 }
 
 /// Remove all mole-managed files for a tunnel (PID file, log files).
@@ -314,54 +486,256 @@ pub fn rename_files(old_name: &str, new_name: &str) -> Result<()> {
     Ok(())
 }
 
-/// Stop a tunnel by killing its autossh process.
-pub fn stop_tunnel(name: &str) -> Result<()> {
-    let pid = read_pid(name)?.context(format!("tunnel '{}' is not active", name))?;
-
-    // Send SIGTERM
-    let ret = unsafe { libc::kill(pid as i32, libc::SIGTERM) };
-    if ret != 0 {
-        anyhow::bail!("failed to kill process {}", pid);
+/// Stop a tunnel gracefully, through the daemon when one is running, or
+/// through the service manager that owns it (which would restart it if
+/// killed). See [`terminate`].
+pub fn stop_tunnel(tunnel: &TunnelHost, cfg: &Config) -> Result<()> {
+    let pid = read_pid(&tunnel.name)?.context(format!("tunnel '{}' is not active", tunnel.name))?;
+    if let persist::Owner::Service(service) = persist::owner(&tunnel.name, pid) {
+        return persist::stop_service(service, tunnel, pid, cfg);
+    }
+    let grace = Duration::from_secs(cfg.stop_timeout);
+    if let Some(stopped) = daemon::with_client(|client| client.down(&tunnel.name, grace)) {
+        return stopped;
     }
 
+    let listeners: Vec<&Endpoint> = tunnel.local_listeners().collect();
+    let result = terminate(pid, &listeners, grace, || !is_pid_alive(pid));
+
     // Remove PID file
-    let path = pid_file(name)?;
+    let path = pid_file(&tunnel.name)?;
     let _ = fs::remove_file(&path);
 
-    Ok(())
+    result
+}
+
+/// SIGTERM a tunnel process, wait up to `grace` for it to exit and for its
+/// `listeners` to be released, then SIGKILL whatever is left.
+///
+/// Tunnels mole starts lead their own process group, so the signals reach the
+/// ssh child as well as autossh or the supervisor. Other processes are
+/// signalled on their own. `exited` reports whether the process is gone (the
+/// daemon reaps its own children through it).
+pub fn terminate(
+    pid: u32,
+    listeners: &[&Endpoint],
+    grace: Duration,
+    mut exited: impl FnMut() -> bool,
+) -> Result<()> {
+    let group = unsafe { libc::getpgid(pid as i32) } == pid as i32;
+    let signal = |sig| unsafe {
+        if group {
+            libc::killpg(pid as i32, sig)
+        } else {
+            libc::kill(pid as i32, sig)
+        }
+    };
+    // A Unix socket forward's file can outlive ssh, so only TCP ports are awaited
+    let busy = || -> Vec<String> {
+        listeners
+            .iter()
+            .filter(|e| matches!(e, Endpoint::Tcp { .. }))
+            .filter_map(|e| health::endpoint_conflict(e))
+            .collect()
+    };
+
+    if signal(libc::SIGTERM) != 0 {
+        anyhow::bail!("failed to kill process {}", pid);
+    }
+    let deadline = Instant::now() + grace;
+    loop {
+        if exited() && busy().is_empty() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    // Still running, or an ssh child outlived its parent and holds the ports
+    signal(libc::SIGKILL);
+    let deadline = Instant::now() + KILL_WAIT;
+    loop {
+        let gone = exited();
+        let busy = busy();
+        if gone && busy.is_empty() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            if !gone {
+                anyhow::bail!("process {} did not exit, even after SIGKILL", pid);
+            }
+            anyhow::bail!(
+                "stopped, but {} still in use — another process holds the port",
+                busy.join(", ")
+            );
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn custom_root() -> ConfigRoot {
+        ConfigRoot {
+            path: PathBuf::from("/tmp/mole_config"),
+            ssh_dir: PathBuf::from("/tmp"),
+            custom: true,
+        }
+    }
+
+    fn config(backend: Backend) -> Config {
+        Config {
+            backend,
+            ..Config::default()
+        }
+    }
+
+    fn env(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
     #[test]
-    fn parse_etime_mm_ss() {
-        assert_eq!(parse_etime("05:30"), Some(330));
+    fn launch_spec_autossh() {
+        let spec = launch_spec(&TunnelHost::named("db"), &custom_root(), &config(Backend::Autossh)).unwrap();
+        assert_eq!(spec.program, "autossh");
+        assert_eq!(spec.args, ["-N", "-F", "/tmp/mole_config", "db"]);
+        assert_eq!(spec.env, env(&[("AUTOSSH_PORT", "0")]));
     }
 
     #[test]
-    fn parse_etime_hh_mm_ss() {
-        assert_eq!(parse_etime("02:14:05"), Some(2 * 3600 + 14 * 60 + 5));
+    fn launch_spec_with_options() {
+        let mut cfg = config(Backend::Autossh);
+        cfg.ssh_options = vec!["-o".to_string(), "ServerAliveInterval=15".to_string()];
+        cfg.autossh_env.insert("AUTOSSH_GATETIME".to_string(), "0".to_string());
+        let mut db = TunnelHost::named("db");
+        db.ssh_options = vec!["-v".to_string()];
+
+        let spec = launch_spec(&db, &custom_root(), &cfg).unwrap();
+        assert_eq!(
+            spec.args,
+            ["-N", "-v", "-o", "ServerAliveInterval=15", "-F", "/tmp/mole_config", "db"]
+        );
+        assert_eq!(spec.env, env(&[("AUTOSSH_GATETIME", "0"), ("AUTOSSH_PORT", "0")]));
+        // The host stays last, so the process is still recognised as this tunnel
+        let argv: Vec<String> = std::iter::once(spec.program).chain(spec.args).collect();
+        assert_eq!(tunnel_of(&argv), Some("db"));
+
+        // The supervisor passes the same options to ssh and needs no autossh settings
+        db.backend = Some(Backend::Ssh);
+        let spec = launch_spec(&db, &custom_root(), &cfg).unwrap();
+        assert_eq!(spec.args[..7], ["supervise", "db", "--gate-time", "30", "--", "-N", "-v"]);
+        assert!(spec.env.is_empty());
     }
 
     #[test]
-    fn parse_etime_days() {
+    fn launch_spec_supervisor() {
+        let mut cfg = config(Backend::Ssh);
+        cfg.gate_time = 0;
+        let spec = launch_spec(&TunnelHost::named("db"), &custom_root(), &cfg).unwrap();
+        assert_eq!(spec.program, std::env::current_exe().unwrap().to_string_lossy());
         assert_eq!(
-            parse_etime("3-01:00:00"),
-            Some(3 * 86400 + 3600)
+            spec.args,
+            ["supervise", "db", "--gate-time", "0", "--", "-N", "-F", "/tmp/mole_config", "db"]
         );
+        assert!(spec.env.is_empty());
+        let argv: Vec<String> = std::iter::once("/usr/bin/mole".to_string()).chain(spec.args).collect();
+        assert_eq!(tunnel_of(&argv), Some("db"));
     }
 
     #[test]
-    fn parse_etime_with_whitespace() {
-        assert_eq!(parse_etime("  10:00  "), Some(600));
+    fn pid_record_formats() {
+        assert_eq!(
+            PidRecord::parse("812\n1700000000\nautossh -N db\n"),
+            Some(PidRecord {
+                pid: 812,
+                start_time: Some(1700000000),
+                command: Some("autossh -N db".to_string()),
+            })
+        );
+        // Files written before start times and command lines were recorded
+        assert_eq!(
+            PidRecord::parse("812"),
+            Some(PidRecord {
+                pid: 812,
+                start_time: None,
+                command: None,
+            })
+        );
+        assert_eq!(PidRecord::parse("garbage\n"), None);
+    }
+
+    #[test]
+    fn pid_record_rejects_reused_pid() {
+        // Our own PID: alive, but neither the command nor the start time match
+        let pid = std::process::id();
+        let live = ProcessInfo::read(pid);
+        let stale = PidRecord {
+            pid,
+            start_time: Some(1),
+            command: Some("autossh -N db".to_string()),
+        };
+        assert!(!stale.matches("db", live.as_ref()));
+        let old_format = PidRecord {
+            pid,
+            start_time: None,
+            command: None,
+        };
+        assert!(!old_format.matches("db", live.as_ref()));
+
+        let argv: Vec<String> = std::env::args().collect();
+        let current = PidRecord {
+            pid,
+            start_time: live.as_ref().and_then(|p| p.start_time),
+            command: Some(argv.join(" ")),
+        };
+        assert!(current.matches("db", live.as_ref()));
+        assert!(!current.matches("db", None));
+    }
+
+    #[test]
+    fn terminate_escalates_to_sigkill() {
+        // Ignores SIGTERM, in its own process group like a mole-started tunnel
+        let mut child = Command::new("sh")
+            .args(["-c", "trap '' TERM; while :; do sleep 1; done"])
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        let started = Instant::now();
+        terminate(child.id(), &[], Duration::from_millis(300), || {
+            matches!(child.try_wait(), Ok(Some(_)))
+        })
+        .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    fn argv(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn tunnel_of_matches_exact_host() {
+        assert_eq!(tunnel_of(&argv("autossh -N db")), Some("db"));
+        assert_eq!(tunnel_of(&argv("autossh -M 0 -N cache")), Some("cache"));
+        assert_eq!(tunnel_of(&argv("/usr/lib/autossh/autossh -N -F /tmp/cfg db-prod")), Some("db-prod"));
+        assert_eq!(
+            tunnel_of(&argv("/usr/local/bin/mole supervise db -- -N db")),
+            Some("db")
+        );
     }
 
     #[test]
-    fn parse_etime_invalid() {
-        assert_eq!(parse_etime(""), None);
-        assert_eq!(parse_etime("abc"), None);
+    fn tunnel_of_ignores_other_processes() {
+        // ssh children, manual autossh without -N, and unrelated commands
+        assert_eq!(tunnel_of(&argv("/usr/bin/ssh -N db")), None);
+        assert_eq!(tunnel_of(&argv("autossh -M 0 db uptime")), None);
+        assert_eq!(tunnel_of(&argv("vim autossh.txt")), None);
+        assert_eq!(tunnel_of(&argv("autossh -N")), None);
+        assert_eq!(tunnel_of(&argv("mole logs db")), None);
     }
 
     #[test]
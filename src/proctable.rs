@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One process as the OS reports it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub ppid: u32,
+    pub argv: Vec<String>,
+    /// Unix time the process started.
+    pub start_time: Option<u64>,
+    /// Resident memory in KiB.
+    pub rss_kb: u64,
+    /// User + system CPU time in seconds.
+    pub cpu_secs: u64,
+}
+
+/// A snapshot of every process, taken with one scan: /proc on Linux, a single
+/// `ps` call elsewhere. Take one per command and look everything up in it
+/// rather than asking the OS per tunnel.
+#[derive(Debug, Default)]
+pub struct ProcessTable {
+    procs: HashMap<u32, ProcessInfo>,
+}
+
+impl ProcessTable {
+    pub fn scan() -> Self {
+        #[cfg(target_os = "linux")]
+        if let Some(table) = linux::scan() {
+            return table;
+        }
+        ps::scan(None)
+    }
+
+    pub fn from_procs(procs: impl IntoIterator<Item = ProcessInfo>) -> Self {
+        Self {
+            procs: procs.into_iter().map(|p| (p.pid, p)).collect(),
+        }
+    }
+
+    pub fn get(&self, pid: u32) -> Option<&ProcessInfo> {
+        self.procs.get(&pid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ProcessInfo> {
+        self.procs.values()
+    }
+
+    /// `pid` and every process below it (autossh and its ssh, say), parents first.
+    pub fn tree(&self, pid: u32) -> Vec<&ProcessInfo> {
+        let mut out: Vec<&ProcessInfo> = self.get(pid).into_iter().collect();
+        let mut i = 0;
+        while i < out.len() {
+            let parent = out[i].pid;
+            let mut children: Vec<&ProcessInfo> =
+                self.procs.values().filter(|p| p.ppid == parent && p.pid != parent).collect();
+            children.sort_by_key(|p| p.pid);
+            out.extend(children);
+            i += 1;
+        }
+        out
+    }
+
+    /// Total (RSS KiB, CPU seconds) of `pid` and its descendants.
+    pub fn tree_usage(&self, pid: u32) -> (u64, u64) {
+        self.tree(pid)
+            .iter()
+            .fold((0, 0), |(rss, cpu), p| (rss + p.rss_kb, cpu + p.cpu_secs))
+    }
+}
+
+impl ProcessInfo {
+    /// Read a single process without scanning the whole table.
+    pub fn read(pid: u32) -> Option<ProcessInfo> {
+        #[cfg(target_os = "linux")]
+        if let Some(info) = linux::read(pid) {
+            return Some(info);
+        }
+        ps::scan(Some(pid)).procs.remove(&pid)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{ProcessInfo, ProcessTable};
+    use std::fs;
+
+    /// Clock ticks per second, page size in KiB and boot time, shared by every process read.
+    struct Clock {
+        hz: u64,
+        page_kb: u64,
+        boot: u64,
+    }
+
+    impl Clock {
+        fn get() -> Option<Clock> {
+            let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+            let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+            if hz <= 0 || page <= 0 {
+                return None;
+            }
+            let boot = fs::read_to_string("/proc/stat")
+                .ok()?
+                .lines()
+                .find_map(|l| l.strip_prefix("btime "))?
+                .trim()
+                .parse()
+                .ok()?;
+            Some(Clock {
+                hz: hz as u64,
+                page_kb: page as u64 / 1024,
+                boot,
+            })
+        }
+    }
+
+    pub fn scan() -> Option<ProcessTable> {
+        let clock = Clock::get()?;
+        let procs = fs::read_dir("/proc")
+            .ok()?
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            // Processes can exit mid-scan; skip them
+            .filter_map(|pid| read_with(pid, &clock));
+        Some(ProcessTable::from_procs(procs))
+    }
+
+    pub fn read(pid: u32) -> Option<ProcessInfo> {
+        read_with(pid, &Clock::get()?)
+    }
+
+    fn read_with(pid: u32, clock: &Clock) -> Option<ProcessInfo> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        let fields = super::parse_stat(&stat)?;
+        let raw = fs::read(format!("/proc/{}/cmdline", pid)).unwrap_or_default();
+        Some(ProcessInfo {
+            pid,
+            ppid: fields.ppid,
+            argv: super::parse_cmdline(&raw),
+            start_time: Some(clock.boot + fields.start_ticks / clock.hz),
+            rss_kb: fields.rss_pages * clock.page_kb,
+            cpu_secs: fields.cpu_ticks / clock.hz,
+        })
+    }
+}
+
+/// The parts of /proc/<pid>/stat mole uses, still in kernel units.
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, PartialEq)]
+struct StatFields {
+    ppid: u32,
+    cpu_ticks: u64,
+    start_ticks: u64,
+    rss_pages: u64,
+}
+
+#[cfg(any(target_os = "linux", test))]
+/// Parse /proc/<pid>/stat. The command name in field 2 can contain spaces and
+/// parentheses, so fields are counted from its closing paren (field 3 = index 0).
+fn parse_stat(stat: &str) -> Option<StatFields> {
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let field = |n: usize| -> Option<u64> { fields.get(n - 3)?.parse().ok() };
+    Some(StatFields {
+        ppid: field(4)? as u32,
+        cpu_ticks: field(14)? + field(15)?,
+        start_ticks: field(22)?,
+        rss_pages: field(24)?,
+    })
+}
+
+/// /proc/<pid>/cmdline: NUL-separated arguments (empty for kernel threads).
+#[cfg(any(target_os = "linux", test))]
+fn parse_cmdline(raw: &[u8]) -> Vec<String> {
+    raw.split(|&b| b == 0)
+        .filter(|a| !a.is_empty())
+        .map(|a| String::from_utf8_lossy(a).into_owned())
+        .collect()
+}
+
+mod ps {
+    use super::ProcessTable;
+    use std::process::Command;
+
+    /// Every process (or just `pid`) from one `ps` call.
+    pub fn scan(pid: Option<u32>) -> ProcessTable {
+        let mut cmd = Command::new("ps");
+        match pid {
+            Some(pid) => cmd.args(["-p", &pid.to_string()]),
+            None => cmd.arg("-A"),
+        };
+        cmd.args(["-o", "pid=,ppid=,rss=,time=,etime=,args="]);
+        match cmd.output() {
+            Ok(output) => {
+                super::parse_ps(&String::from_utf8_lossy(&output.stdout), super::now())
+            }
+            Err(_) => ProcessTable::default(),
+        }
+    }
+}
+
+/// Parse `ps -o pid=,ppid=,rss=,time=,etime=,args=`. ps joins argv with spaces,
+/// so arguments containing spaces split apart; good enough to recognize what
+/// mole itself spawns.
+fn parse_ps(output: &str, now: u64) -> ProcessTable {
+    let procs = output.lines().filter_map(|line| {
+        let mut words = line.split_whitespace();
+        let pid = words.next()?.parse().ok()?;
+        let ppid = words.next()?.parse().ok()?;
+        let rss_kb = words.next()?.parse().ok()?;
+        // macOS prints CPU time as m:ss.cc; drop the hundredths
+        let time = words.next()?;
+        let cpu_secs = parse_etime(time.split('.').next()?)?;
+        let elapsed = parse_etime(words.next()?)?;
+        Some(ProcessInfo {
+            pid,
+            ppid,
+            argv: words.map(str::to_string).collect(),
+            start_time: Some(now.saturating_sub(elapsed)),
+            rss_kb,
+            cpu_secs,
+        })
+    });
+    ProcessTable::from_procs(procs)
+}
+
+/// Parse ps etime format: [[dd-]hh:]mm:ss
+fn parse_etime(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (days, rest) = if let Some(pos) = s.find('-') {
+        let d: u64 = s[..pos].parse().ok()?;
+        (d, &s[pos + 1..])
+    } else {
+        (0, s)
+    };
+
+    let parts: Vec<&str> = rest.split(':').collect();
+    let (hours, minutes, seconds) = match parts.len() {
+        3 => {
+            let h: u64 = parts[0].parse().ok()?;
+            let m: u64 = parts[1].parse().ok()?;
+            let s: u64 = parts[2].parse().ok()?;
+            (h, m, s)
+        }
+        2 => {
+            let m: u64 = parts[0].parse().ok()?;
+            let s: u64 = parts[1].parse().ok()?;
+            (0, m, s)
+        }
+        _ => return None,
+    };
+
+    Some(days * 86400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Format KiB as "812K", "6.1M" or "1.2G".
+pub fn format_rss(kb: u64) -> String {
+    match kb {
+        0..1024 => format!("{}K", kb),
+        1024..1_048_576 => format!("{:.1}M", kb as f64 / 1024.0),
+        _ => format!("{:.1}G", kb as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(pid: u32, ppid: u32, rss_kb: u64) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            ppid,
+            argv: vec![format!("p{}", pid)],
+            start_time: None,
+            rss_kb,
+            cpu_secs: 1,
+        }
+    }
+
+    #[test]
+    fn parse_stat_fields() {
+        let stat = "812 (auto ssh) (x) S 1 812 812 0 -1 4194560 1 0 0 0 250 50 0 0 20 0 1 0 123456 10000 300";
+        assert_eq!(
+            parse_stat(stat),
+            Some(StatFields {
+                ppid: 1,
+                cpu_ticks: 300,
+                start_ticks: 123456,
+                rss_pages: 300,
+            })
+        );
+        assert_eq!(parse_stat("812 (x) S"), None);
+    }
+
+    #[test]
+    fn parse_cmdline_args() {
+        assert_eq!(parse_cmdline(b"autossh\0-N\0db\0"), ["autossh", "-N", "db"]);
+        assert!(parse_cmdline(b"").is_empty());
+    }
+
+    #[test]
+    fn parse_ps_output() {
+        let ps = "    1     0  9000 00:00:03 3-01:00:00 /sbin/init\n  812     1  1200 0:00.52    05:30 /usr/lib/autossh/autossh -N db\n\n";
+        let table = parse_ps(ps, 100_000);
+        let autossh = table.get(812).unwrap();
+        assert_eq!(autossh.ppid, 1);
+        assert_eq!(autossh.rss_kb, 1200);
+        assert_eq!(autossh.cpu_secs, 0);
+        assert_eq!(autossh.start_time, Some(100_000 - 330));
+        assert_eq!(autossh.argv, ["/usr/lib/autossh/autossh", "-N", "db"]);
+        assert_eq!(table.get(1).unwrap().cpu_secs, 3);
+    }
+
+    #[test]
+    fn tree_walks_descendants() {
+        let table = ProcessTable::from_procs([
+            info(1, 0, 10),
+            info(812, 1, 100),
+            info(813, 812, 200),
+            info(900, 813, 50),
+            info(901, 1, 999),
+        ]);
+        let pids: Vec<u32> = table.tree(812).iter().map(|p| p.pid).collect();
+        assert_eq!(pids, [812, 813, 900]);
+        assert_eq!(table.tree_usage(812), (350, 3));
+        assert!(table.tree(4242).is_empty());
+    }
+
+    #[test]
+    fn scan_finds_this_process() {
+        let table = ProcessTable::scan();
+        let me = table.get(std::process::id()).unwrap();
+        assert!(me.start_time.is_some());
+        assert!(!me.argv.is_empty());
+    }
+
+    #[test]
+    fn format_rss_units() {
+        assert_eq!(format_rss(812), "812K");
+        assert_eq!(format_rss(6246), "6.1M");
+        assert_eq!(format_rss(1_258_291), "1.2G");
+    }
+
+    #[test]
+    fn parse_etime_mm_ss() {
+        assert_eq!(parse_etime("05:30"), Some(330));
+    }
+
+    #[test]
+    fn parse_etime_hh_mm_ss() {
+        assert_eq!(parse_etime("02:14:05"), Some(2 * 3600 + 14 * 60 + 5));
+    }
+
+    #[test]
+    fn parse_etime_days() {
+        assert_eq!(
+            parse_etime("3-01:00:00"),
+            Some(3 * 86400 + 3600)
+        );
+    }
+
+    #[test]
+    fn parse_etime_with_whitespace() {
+        assert_eq!(parse_etime("  10:00  "), Some(600));
+    }
+
+    #[test]
+    fn parse_etime_invalid() {
+        assert_eq!(parse_etime(""), None);
+        assert_eq!(parse_etime("abc"), None);
+    }
+
+}
@@ -1,395 +1,779 @@
 use anyhow::{Context, Result};
-use std::fs;
-use std::path::{Path, PathBuf};
-
-use crate::tunnel::{DynamicForward, PortForward, RemotePortForward, TunnelHost};
-
-/// Get a list of SSH config files (main config + included files).
-fn config_files() -> Result<Vec<PathBuf>> {
-    let ssh_dir = dirs::home_dir()
-        .context("cannot determine home directory")?
-        .join(".ssh");
-    let config_path = ssh_dir.join("config");
-
-    if !config_path.exists() {
-        anyhow::bail!("~/.ssh/config not found. If you are using a custom SSH config path, set it in ~/.mole/config.toml under ssh_config.");
-    }
-
-    let mut files = vec![config_path.clone()];
-
-    let content = fs::read_to_string(&config_path)?;
-    for line in content.lines() {
-        let trimmed = line.trim();
-        if let Some((key, value)) = split_directive(trimmed) {
-            if key.eq_ignore_ascii_case("include") {
-                let expanded = expand_include_path(value, &ssh_dir)?;
-                let pattern_str = expanded.to_string_lossy().to_string();
-                for entry in glob::glob(&pattern_str).unwrap_or_else(|_| glob::glob("").unwrap()) {
-                    if let Ok(path) = entry {
-                        if path.is_file() {
-                            files.push(path);
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    Ok(files)
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::config::{self, Config};
+use crate::tunnel::{DynamicForward, Endpoint, PortForward, RemotePortForward, TunnelHost};
+
+mod document;
+mod include;
+mod resolve;
+
+use document::{Block, Document, PatternError};
+
+pub use include::{ConfigSet, LineKind};
+pub use resolve::{Exec, HostConfig, host_matches, is_concrete, parse_annotation};
+
+/// The SSH config file mole reads and edits, resolved from `ssh_config` in
+/// config.toml and defaulting to ~/.ssh/config.
+#[derive(Debug, Clone)]
+pub struct ConfigRoot {
+    /// Top-level SSH config file.
+    pub path: PathBuf,
+    /// Directory relative Include paths resolve against (~/.ssh, as in OpenSSH).
+    pub ssh_dir: PathBuf,
+    /// Whether the file differs from ssh's default and must be passed with -F.
+    pub custom: bool,
 }
 
-fn expand_include_path(pattern: &str, ssh_dir: &Path) -> Result<PathBuf> {
-    if pattern.starts_with('~') {
-        let home = dirs::home_dir().context("cannot determine home directory")?;
-        Ok(home.join(&pattern[2..]))
-    } else if pattern.starts_with('/') {
-        Ok(PathBuf::from(pattern))
-    } else {
-        Ok(ssh_dir.join(pattern))
+impl ConfigRoot {
+    /// Resolve the SSH config root from mole's config, expanding `~` and env vars.
+    pub fn resolve(cfg: &Config) -> Result<Self> {
+        let ssh_dir = dirs::home_dir()
+            .context("cannot determine home directory")?
+            .join(".ssh");
+        let default = ssh_dir.join("config");
+        let path = match cfg.ssh_config.as_deref() {
+            Some(p) if !p.trim().is_empty() => config::expand_path(p.trim())
+                .with_context(|| format!("invalid ssh_config path '{}'", p))?,
+            _ => default.clone(),
+        };
+        let custom = path != default;
+        Ok(Self {
+            path,
+            ssh_dir,
+            custom,
+        })
     }
-}
-
-/// Find the line range [start, end) of a Host block in a file.
-fn find_host_range(path: &Path, name: &str) -> Result<Option<(usize, usize)>> {
-    let content = fs::read_to_string(path)?;
-    let lines: Vec<&str> = content.lines().collect();
 
-    let mut block_start: Option<usize> = None;
-
-    for (i, line) in lines.iter().enumerate() {
-        let trimmed = line.trim();
-        if let Some((key, value)) = split_directive(trimmed) {
-            if key.eq_ignore_ascii_case("host") || key.eq_ignore_ascii_case("match") {
-                if block_start.is_some() {
-                    return Ok(Some((block_start.unwrap(), i)));
-                }
-                if key.eq_ignore_ascii_case("host") {
-                    let host_name = value.split_whitespace().next().unwrap_or("");
-                    if host_name == name {
-                        block_start = Some(i);
-                    }
-                }
-            }
+    /// Extra arguments ssh needs to read this config (`-F <path>` for custom roots).
+    pub fn ssh_args(&self) -> Vec<String> {
+        if self.custom {
+            vec!["-F".to_string(), self.path.to_string_lossy().to_string()]
+        } else {
+            Vec::new()
         }
     }
 
-    if let Some(start) = block_start {
-        return Ok(Some((start, lines.len())));
+    fn ensure_exists(&self) -> Result<()> {
+        if !self.path.exists() {
+            anyhow::bail!(
+                "{} not found. If you are using a custom SSH config path, set ssh_config with `mole config`.",
+                self.path.display()
+            );
+        }
+        Ok(())
     }
+}
 
-    Ok(None)
+/// Get a list of SSH config files (main config + every file it includes, recursively).
+fn config_files(root: &ConfigRoot) -> Result<Vec<PathBuf>> {
+    Ok(load(root)?.files)
 }
 
-/// Read a Host block from SSH config without modifying the file.
-/// Returns (file_path, block_content) or None if not found.
-pub fn read_host_block(name: &str) -> Result<Option<(PathBuf, String)>> {
-    let files = config_files()?;
-    for file_path in &files {
-        if let Some((start, end)) = find_host_range(file_path, name)? {
-            let content = fs::read_to_string(file_path)?;
-            let lines: Vec<&str> = content.lines().collect();
-            let block = lines[start..end].join("\n");
-            return Ok(Some((file_path.clone(), block)));
+/// Read the SSH config and everything it includes.
+pub fn load(root: &ConfigRoot) -> Result<ConfigSet> {
+    root.ensure_exists()?;
+    include::load(&root.path, &root.ssh_dir)
+}
+
+/// Find the first file whose Host lines list `name`, parsed for editing.
+fn find_host(root: &ConfigRoot, name: &str) -> Result<Option<(PathBuf, Document, Block)>> {
+    for file_path in config_files(root)? {
+        let doc = Document::load(&file_path)?;
+        if let Some(block) = doc.find_host(name) {
+            return Ok(Some((file_path, doc, block)));
         }
     }
     Ok(None)
 }
 
-/// Remove a Host block from the SSH config. Returns the file path it was removed from.
-pub fn remove_host_block(name: &str) -> Result<PathBuf> {
-    let files = config_files()?;
-    for file_path in &files {
-        if let Some((start, end)) = find_host_range(file_path, name)? {
-            let content = fs::read_to_string(file_path)?;
-            let lines: Vec<&str> = content.lines().collect();
-
-            let mut new_lines: Vec<&str> = Vec::new();
-            new_lines.extend_from_slice(&lines[..start]);
-            new_lines.extend_from_slice(&lines[end..]);
-
-            // Trim trailing blank lines
-            while new_lines.last().map_or(false, |l| l.trim().is_empty()) {
-                new_lines.pop();
-            }
+/// Read a Host block (with the comments directly above it) without modifying the file.
+/// Returns (file_path, block_content) or None if not found.
+pub fn read_host_block(root: &ConfigRoot, name: &str) -> Result<Option<(PathBuf, String)>> {
+    Ok(find_host(root, name)?.map(|(path, doc, block)| (path, doc.block_text(block))))
+}
 
-            let mut new_content = new_lines.join("\n");
-            if !new_content.is_empty() {
-                new_content.push('\n');
-            }
+/// Remove a Host block from the SSH config. Returns the file path it was removed from.
+pub fn remove_host_block(root: &ConfigRoot, name: &str) -> Result<PathBuf> {
+    let (file_path, mut doc, block) = find_host(root, name)?
+        .with_context(|| format!("Host block '{}' not found in SSH config files", name))?;
+    doc.remove_block(block);
+    doc.save(&file_path)?;
+    Ok(file_path)
+}
 
-            fs::write(file_path, &new_content)?;
-            return Ok(file_path.clone());
+/// Remove one alias from its Host line, leaving the other patterns alone.
+/// Removes the whole block if `alias` is the line's only pattern.
+pub fn remove_host_alias(root: &ConfigRoot, alias: &str) -> Result<PathBuf> {
+    let (file_path, mut doc, block) = find_host(root, alias)?
+        .with_context(|| format!("Host '{}' not found in SSH config files", alias))?;
+    match doc.edit_host_pattern(block, alias, None) {
+        Ok(()) => {}
+        Err(PatternError::OnlyPattern) => doc.remove_block(block),
+        Err(PatternError::NotFound) => {
+            anyhow::bail!("could not find '{}' on its Host line in {}", alias, file_path.display())
         }
     }
-    anyhow::bail!("Host block '{}' not found in SSH config files", name);
+    doc.save(&file_path)?;
+    Ok(file_path)
 }
 
-/// Rename a Host block in the SSH config. Returns the file path it was found in.
-pub fn rename_host_block(old_name: &str, new_name: &str) -> Result<PathBuf> {
-    let files = config_files()?;
-    for file_path in &files {
-        if let Some((start, _end)) = find_host_range(file_path, old_name)? {
-            let content = fs::read_to_string(file_path)?;
-            let lines: Vec<&str> = content.lines().collect();
-
-            let mut new_lines: Vec<String> = Vec::new();
-            for (i, line) in lines.iter().enumerate() {
-                if i == start {
-                    // Replace the Host line, preserving any leading whitespace
-                    let trimmed = line.trim();
-                    let leading = &line[..line.len() - trimmed.len()];
-                    new_lines.push(format!("{}Host {}", leading, new_name));
-                } else {
-                    new_lines.push(line.to_string());
-                }
-            }
+/// Rename a host in the SSH config, replacing only that pattern on its Host line.
+/// Returns the file path it was found in.
+pub fn rename_host_block(root: &ConfigRoot, old_name: &str, new_name: &str) -> Result<PathBuf> {
+    check_host_name(new_name)?;
+    let (file_path, mut doc, block) = find_host(root, old_name)?
+        .with_context(|| format!("Host block '{}' not found in SSH config files", old_name))?;
+    if doc.edit_host_pattern(block, old_name, Some(new_name)).is_err() {
+        anyhow::bail!("could not find '{}' on its Host line in {}", old_name, file_path.display());
+    }
+    doc.save(&file_path)?;
+    Ok(file_path)
+}
 
-            let mut new_content = new_lines.join("\n");
-            if content.ends_with('\n') {
-                new_content.push('\n');
-            }
+/// Fail unless `name` can go on a Host line as one concrete name: ssh would
+/// split it at whitespace, and read wildcards, `!` and `#` as pattern syntax.
+pub fn check_host_name(name: &str) -> Result<()> {
+    let problem = if name.is_empty() {
+        "it is empty"
+    } else if name.contains(char::is_whitespace) || name.contains('"') {
+        "it contains whitespace or quotes"
+    } else if name.contains(['*', '?']) {
+        "it contains wildcards"
+    } else if name.starts_with(['!', '#']) {
+        "it starts with '!' or '#'"
+    } else {
+        return Ok(());
+    };
+    anyhow::bail!("'{}' can't be a host name: {}", name, problem)
+}
 
-            fs::write(file_path, &new_content)?;
-            return Ok(file_path.clone());
-        }
-    }
-    anyhow::bail!("Host block '{}' not found in SSH config files", old_name);
+/// Append a new Host block to the top-level SSH config, separated from what
+/// precedes it by a blank line. Returns the file path written.
+pub fn append_host_block(root: &ConfigRoot, block: &str) -> Result<PathBuf> {
+    root.ensure_exists()?;
+    let mut doc = Document::load(&root.path)?;
+    doc.append_block(block);
+    doc.save(&root.path)?;
+    Ok(root.path.clone())
 }
 
-/// Parse ~/.ssh/config (and included files) to find all hosts with LocalForward directives.
-pub fn discover_tunnels() -> Result<Vec<TunnelHost>> {
-    let ssh_dir = dirs::home_dir()
-        .context("cannot determine home directory")?
-        .join(".ssh");
-    let config_path = ssh_dir.join("config");
+/// Tunnels found in the SSH config, plus problems hit while reading it.
+pub struct Discovery {
+    pub tunnels: Vec<TunnelHost>,
+    pub warnings: Vec<String>,
+}
 
-    if !config_path.exists() {
-        anyhow::bail!("~/.ssh/config not found");
-    }
+/// Parse the SSH config (and included files) to find all hosts with forward directives.
+pub fn discover(root: &ConfigRoot) -> Result<Discovery> {
+    let set = load(root)?;
+    let mut tunnels = Vec::new();
+    let mut warnings = set.warnings.clone();
+    warnings.extend(collect_tunnels(&set, &mut tunnels, Exec::Run));
+    Ok(Discovery { tunnels, warnings })
+}
+
+/// Like [`discover`], for callers that have nowhere to report warnings.
+pub fn discover_tunnels(root: &ConfigRoot) -> Result<Vec<TunnelHost>> {
+    discover_tunnels_with(root, Exec::Run)
+}
 
+/// Like [`discover_tunnels`]; with [`Exec::Skip`] no `Match exec` command runs.
+pub fn discover_tunnels_with(root: &ConfigRoot, exec: Exec) -> Result<Vec<TunnelHost>> {
+    let set = load(root)?;
     let mut tunnels = Vec::new();
-    parse_file(&config_path, &ssh_dir, &mut tunnels)?;
+    collect_tunnels(&set, &mut tunnels, exec);
     Ok(tunnels)
 }
 
-fn parse_file(path: &Path, ssh_dir: &Path, tunnels: &mut Vec<TunnelHost>) -> Result<()> {
-    let content = fs::read_to_string(path)
-        .with_context(|| format!("failed to read {}", path.display()))?;
-
-    let mut current_host: Option<String> = None;
-    let mut current_hostname: Option<String> = None;
-    let mut current_forwards: Vec<PortForward> = Vec::new();
-    let mut current_remote_forwards: Vec<RemotePortForward> = Vec::new();
-    let mut current_dynamic_forwards: Vec<DynamicForward> = Vec::new();
-    let mut current_group: Option<String> = None;
+/// A concrete host named in the config, with its effective options.
+pub struct ResolvedHost {
+    /// Every concrete pattern of its Host line, primary name first.
+    pub aliases: Vec<String>,
+    pub config: HostConfig,
+}
 
-    for line in content.lines() {
-        let line = line.trim();
+/// Resolve every concrete host named on a Host line. Returns warnings about
+/// Match criteria that could not be evaluated.
+pub fn resolve_hosts(set: &ConfigSet, exec: Exec) -> (Vec<ResolvedHost>, Vec<String>) {
+    let mut resolver = resolve::Resolver::new(set, exec);
+    let hosts = candidate_hosts(set)
+        .into_iter()
+        .map(|aliases| ResolvedHost {
+            config: resolver.resolve(&aliases[0]),
+            aliases,
+        })
+        .collect();
+    (hosts, resolver.warnings)
+}
 
-        if line.is_empty() {
-            continue;
-        }
+/// Secondary aliases of `host` that get different options than its primary
+/// name, from Host patterns or Match blocks matching only them. mole connects
+/// with the primary name, so those options never apply to the tunnel.
+pub fn divergent_aliases(set: &ConfigSet, host: &ResolvedHost) -> Vec<String> {
+    let mut resolver = resolve::Resolver::new(set, Exec::Run);
+    host.aliases[1..]
+        .iter()
+        .filter(|alias| resolver.resolve(alias) != host.config)
+        .cloned()
+        .collect()
+}
 
-        // Check for group comment inside a Host block before skipping comments
-        if line.starts_with('#') {
-            if current_host.is_some() {
-                if let Some(g) = line.strip_prefix("# mole:group=") {
-                    let g = g.trim();
-                    if !g.is_empty() {
-                        current_group = Some(g.to_string());
-                    }
-                }
-            }
-            continue;
+/// Resolve every candidate host and keep those with forwards. Returns warnings
+/// about Match criteria that could not be evaluated.
+fn collect_tunnels(set: &ConfigSet, tunnels: &mut Vec<TunnelHost>, exec: Exec) -> Vec<String> {
+    let (hosts, warnings) = resolve_hosts(set, exec);
+    for host in hosts {
+        if let Some(tunnel) = tunnel_from_config(host.aliases, &host.config) {
+            tunnels.push(tunnel);
         }
+    }
+    warnings
+}
 
-        let (key, value) = match split_directive(line) {
-            Some(pair) => pair,
-            None => continue,
-        };
-
-        match key.to_lowercase().as_str() {
-            "include" => {
-                // Flush current host before processing includes
-                flush_host(&mut current_host, &mut current_hostname, &mut current_forwards, &mut current_remote_forwards, &mut current_dynamic_forwards, &mut current_group, tunnels);
-                process_include(value, ssh_dir, tunnels)?;
-            }
-            "host" => {
-                // Flush previous host
-                flush_host(&mut current_host, &mut current_hostname, &mut current_forwards, &mut current_remote_forwards, &mut current_dynamic_forwards, &mut current_group, tunnels);
-
-                // Skip wildcard patterns
-                let name = value.split_whitespace().next().unwrap_or("");
-                if !name.contains('*') && !name.contains('?') {
-                    current_host = Some(name.to_string());
-                }
-            }
-            "hostname" => {
-                if current_host.is_some() {
-                    current_hostname = Some(value.to_string());
-                }
-            }
-            "localforward" => {
-                if current_host.is_some() {
-                    if let Some(fwd) = parse_local_forward(value) {
-                        current_forwards.push(fwd);
-                    }
+/// Concrete host names declared by each Host line (wildcard and negated
+/// patterns skipped), in config order. Every line yields one alias list whose
+/// first entry is the primary name; names claimed by an earlier line are dropped.
+fn candidate_hosts(set: &ConfigSet) -> Vec<Vec<String>> {
+    let mut candidates: Vec<Vec<String>> = Vec::new();
+    for line in &set.lines {
+        if let LineKind::Directive { key, value } = &line.kind
+            && key.eq_ignore_ascii_case("host")
+        {
+            let mut aliases: Vec<String> = Vec::new();
+            for pattern in split_args(value) {
+                if resolve::is_concrete(&pattern)
+                    && !aliases.contains(&pattern)
+                    && !candidates.iter().flatten().any(|a| *a == pattern)
+                {
+                    aliases.push(pattern);
                 }
             }
-            "remoteforward" => {
-                if current_host.is_some() {
-                    if let Some(fwd) = parse_remote_forward(value) {
-                        current_remote_forwards.push(fwd);
-                    }
-                }
-            }
-            "dynamicforward" => {
-                if current_host.is_some() {
-                    if let Some(fwd) = parse_dynamic_forward(value) {
-                        current_dynamic_forwards.push(fwd);
-                    }
-                }
+            if !aliases.is_empty() {
+                candidates.push(aliases);
             }
-            _ => {}
         }
     }
-
-    // Flush the last host
-    flush_host(&mut current_host, &mut current_hostname, &mut current_forwards, &mut current_remote_forwards, &mut current_dynamic_forwards, &mut current_group, tunnels);
-
-    Ok(())
+    candidates
 }
 
-fn flush_host(
-    host: &mut Option<String>,
-    hostname: &mut Option<String>,
-    forwards: &mut Vec<PortForward>,
-    remote_forwards: &mut Vec<RemotePortForward>,
-    dynamic_forwards: &mut Vec<DynamicForward>,
-    group: &mut Option<String>,
-    tunnels: &mut Vec<TunnelHost>,
-) {
-    if let Some(name) = host.take() {
-        if !forwards.is_empty() || !remote_forwards.is_empty() || !dynamic_forwards.is_empty() {
-            tunnels.push(TunnelHost {
-                name,
-                hostname: hostname.take(),
-                forwards: std::mem::take(forwards),
-                remote_forwards: std::mem::take(remote_forwards),
-                dynamic_forwards: std::mem::take(dynamic_forwards),
-                group: group.take(),
-            });
-        } else {
-            *hostname = None;
-            *group = None;
-        }
+/// Build a tunnel from a host's effective config. Returns None for hosts without forwards.
+/// The first alias names the tunnel and is the host mole passes to ssh, so
+/// `config` is resolved for it; see [`divergent_aliases`].
+pub fn tunnel_from_config(aliases: Vec<String>, config: &HostConfig) -> Option<TunnelHost> {
+    let name = aliases[0].clone();
+    let forwards: Vec<PortForward> = config
+        .list("LocalForward")
+        .iter()
+        .filter_map(|v| parse_local_forward(v))
+        .collect();
+    let remote_forwards: Vec<RemotePortForward> = config
+        .list("RemoteForward")
+        .iter()
+        .filter_map(|v| parse_remote_forward(v))
+        .collect();
+    let dynamic_forwards: Vec<DynamicForward> = config
+        .list("DynamicForward")
+        .iter()
+        .filter_map(|v| parse_dynamic_forward(v))
+        .collect();
+
+    if forwards.is_empty() && remote_forwards.is_empty() && dynamic_forwards.is_empty() {
+        return None;
     }
-    forwards.clear();
-    remote_forwards.clear();
-    dynamic_forwards.clear();
+
+    Some(TunnelHost {
+        hostname: config
+            .get("HostName")
+            .map(|h| resolve::expand_hostname(h, &name)),
+        name,
+        aliases,
+        user: config.get("User").map(str::to_string),
+        port: config.get("Port").and_then(|p| p.parse().ok()),
+        proxy_jump: config
+            .get("ProxyJump")
+            .filter(|p| !p.eq_ignore_ascii_case("none"))
+            .map(str::to_string),
+        identity_files: config.list("IdentityFile").to_vec(),
+        forwards,
+        remote_forwards,
+        dynamic_forwards,
+        group: config.get("mole:group").map(str::to_string),
+        backend: config.get("mole:backend").and_then(|b| b.parse().ok()),
+        depends: config
+            .get("mole:depends")
+            .map(|d| {
+                d.split(',')
+                    .map(str::trim)
+                    .filter(|n| !n.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        ssh_options: config
+            .list("mole:ssh-option")
+            .iter()
+            .flat_map(|o| split_args(o))
+            .collect(),
+    })
 }
 
 fn split_directive(line: &str) -> Option<(&str, &str)> {
-    // SSH config directives can use whitespace or '=' as separator
+    // As in ssh: the keyword ends at the first whitespace or '=', and one '='
+    // may separate it from the value. A later '=' belongs to the value.
     let line = line.trim();
-    if let Some(eq_pos) = line.find('=') {
-        let key = line[..eq_pos].trim();
-        let value = line[eq_pos + 1..].trim();
-        if !key.is_empty() && !value.is_empty() {
-            return Some((key, value));
-        }
-    }
-    let mut parts = line.splitn(2, char::is_whitespace);
-    let key = parts.next()?;
-    let value = parts.next()?.trim();
-    if value.is_empty() {
+    let key_end = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let key = &line[..key_end];
+    let rest = line[key_end..].trim_start();
+    let value = rest.strip_prefix('=').unwrap_or(rest).trim();
+    if key.is_empty() || value.is_empty() {
         return None;
     }
     Some((key, value))
 }
 
-fn process_include(pattern: &str, ssh_dir: &Path, tunnels: &mut Vec<TunnelHost>) -> Result<()> {
-    let expanded = if pattern.starts_with('~') {
-        let home = dirs::home_dir().context("cannot determine home directory")?;
-        home.join(&pattern[2..]) // skip "~/"
-    } else if pattern.starts_with('/') {
-        PathBuf::from(pattern)
-    } else {
-        ssh_dir.join(pattern)
-    };
+/// Split a directive value into arguments, honouring double quotes
+/// (e.g. `Include a.conf "dir with space/*.conf"`) and trailing `# comments`.
+pub fn split_args(value: &str) -> Vec<String> {
+    arg_spans(value).into_iter().map(|(_, arg)| arg).collect()
+}
 
-    let pattern_str = expanded.to_string_lossy().to_string();
-    for entry in glob::glob(&pattern_str).unwrap_or_else(|_| glob::glob("").unwrap()) {
-        if let Ok(path) = entry {
-            if path.is_file() {
-                parse_file(&path, ssh_dir, tunnels)?;
+/// Like [`split_args`], with each argument's byte range in `value`, quotes
+/// included, for editing it in place.
+pub fn arg_spans(value: &str) -> Vec<(Range<usize>, String)> {
+    let mut args = Vec::new();
+    let mut cur = String::new();
+    let mut in_quotes = false;
+    let mut start: Option<usize> = None;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                start.get_or_insert(i);
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if let Some(s) = start.take() {
+                    args.push((s..i, std::mem::take(&mut cur)));
+                }
+            }
+            // A `#` starting an unquoted argument begins a trailing comment
+            '#' if !in_quotes && start.is_none() => return args,
+            c => {
+                cur.push(c);
+                start.get_or_insert(i);
             }
         }
     }
-
-    Ok(())
+    if let Some(s) = start {
+        args.push((s..value.len(), cur));
+    }
+    args
 }
 
-/// Parse a LocalForward value like "16443 localhost:6443" or "16443 10.0.0.1:6443"
-fn parse_local_forward(value: &str) -> Option<PortForward> {
-    let parts: Vec<&str> = value.split_whitespace().collect();
-    if parts.len() != 2 {
+/// Parse a LocalForward value: `[bind_address:]port host:hostport`, with either
+/// side optionally a Unix socket path and IPv6 addresses in brackets
+/// (e.g. "16443 localhost:6443", "[::1]:8080 [fe80::1]:80", "/tmp/d.sock /var/run/docker.sock").
+pub fn parse_local_forward(value: &str) -> Option<PortForward> {
+    let args = split_args(value);
+    if args.len() != 2 {
         return None;
     }
-
-    let local_port: u16 = parts[0].parse().ok()?;
-
-    // remote part is host:port
-    let remote = parts[1];
-    let colon_pos = remote.rfind(':')?;
-    let remote_host = &remote[..colon_pos];
-    let remote_port: u16 = remote[colon_pos + 1..].parse().ok()?;
-
     Some(PortForward {
-        local_port,
-        remote_host: remote_host.to_string(),
-        remote_port,
+        listen: parse_listen(&args[0])?,
+        target: parse_target(&args[1])?,
     })
 }
 
-/// Parse a DynamicForward value like "1080" or "127.0.0.1:1080"
-fn parse_dynamic_forward(value: &str) -> Option<DynamicForward> {
-    let value = value.trim();
-    // DynamicForward can be just a port or bind_address:port
-    if let Some(colon_pos) = value.rfind(':') {
-        let port_str = &value[colon_pos + 1..];
-        let listen_port: u16 = port_str.parse().ok()?;
-        Some(DynamicForward { listen_port })
-    } else {
-        let listen_port: u16 = value.parse().ok()?;
-        Some(DynamicForward { listen_port })
+/// Parse a DynamicForward value like "1080", "127.0.0.1:1080" or "[::1]:1080"
+pub fn parse_dynamic_forward(value: &str) -> Option<DynamicForward> {
+    let args = split_args(value);
+    if args.len() != 1 {
+        return None;
+    }
+    match parse_listen(&args[0])? {
+        listen @ Endpoint::Tcp { .. } => Some(DynamicForward { listen }),
+        Endpoint::Unix(_) => None,
     }
 }
 
-/// Parse a RemoteForward value like "9090 localhost:3000"
-fn parse_remote_forward(value: &str) -> Option<RemotePortForward> {
-    let parts: Vec<&str> = value.split_whitespace().collect();
-    if parts.len() != 2 {
-        return None;
+/// Parse a RemoteForward value like "9090 localhost:3000" or "*:9090 /tmp/app.sock".
+/// A lone `[bind_address:]port` is a remote SOCKS proxy.
+pub fn parse_remote_forward(value: &str) -> Option<RemotePortForward> {
+    let args = split_args(value);
+    match args.as_slice() {
+        [listen] => match parse_listen(listen)? {
+            listen @ Endpoint::Tcp { .. } => Some(RemotePortForward {
+                listen,
+                target: None,
+            }),
+            Endpoint::Unix(_) => None,
+        },
+        [listen, target] => Some(RemotePortForward {
+            listen: parse_listen(listen)?,
+            target: Some(parse_target(target)?),
+        }),
+        _ => None,
     }
+}
 
-    let bind_port: u16 = parts[0].parse().ok()?;
+/// Parse the listening side of a forward: a socket path, `port`, or `bind_address:port`.
+/// An empty bind address (":8080") means all interfaces, like "*".
+fn parse_listen(spec: &str) -> Option<Endpoint> {
+    if spec.contains('/') {
+        return Some(Endpoint::Unix(spec.to_string()));
+    }
+    let (host, port) = split_host_port(spec)?;
+    let host = host.map(|h| if h.is_empty() { "*".to_string() } else { h });
+    Some(Endpoint::Tcp { host, port })
+}
 
-    let target = parts[1];
-    let colon_pos = target.rfind(':')?;
-    let remote_host = &target[..colon_pos];
-    let remote_port: u16 = target[colon_pos + 1..].parse().ok()?;
+/// Parse the connecting side of a forward: a socket path or `host:port`.
+fn parse_target(spec: &str) -> Option<Endpoint> {
+    if spec.contains('/') {
+        return Some(Endpoint::Unix(spec.to_string()));
+    }
+    match split_host_port(spec)? {
+        (Some(host), port) if !host.is_empty() => Some(Endpoint::Tcp {
+            host: Some(host),
+            port,
+        }),
+        _ => None,
+    }
+}
 
-    Some(RemotePortForward {
-        bind_port,
-        remote_host: remote_host.to_string(),
-        remote_port,
-    })
+/// Split `host:port`, `[v6addr]:port` or a bare `port`. IPv6 addresses must be bracketed.
+fn split_host_port(spec: &str) -> Option<(Option<String>, u16)> {
+    let (host, port) = if let Some(rest) = spec.strip_prefix('[') {
+        let (host, port) = rest.split_once("]:")?;
+        (Some(host), port)
+    } else {
+        match spec.split_once(':') {
+            Some((_, port)) if port.contains(':') => return None,
+            Some((host, port)) => (Some(host), port),
+            None => (None, spec),
+        }
+    };
+    Some((host.map(str::to_string), port.parse().ok()?))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::Path;
+
+    fn find_block_range(path: &Path, name: &str) -> Result<Option<(usize, usize)>> {
+        Ok(Document::load(path)?.find_host(name).map(|b| (b.start, b.end)))
+    }
+
+    fn parse_file(path: &Path, ssh_dir: &Path, tunnels: &mut Vec<TunnelHost>) -> Result<()> {
+        let set = include::load(path, ssh_dir)?;
+        collect_tunnels(&set, tunnels, Exec::Run);
+        Ok(())
+    }
+
+    #[test]
+    fn config_root_default() {
+        let root = ConfigRoot::resolve(&Config::default()).unwrap();
+        assert!(!root.custom);
+        assert!(root.path.ends_with(".ssh/config"));
+        assert!(root.ssh_args().is_empty());
+    }
+
+    #[test]
+    fn config_root_custom_path() {
+        let cfg = Config {
+            ssh_config: Some("/srv/project/ssh_config".to_string()),
+            ..Config::default()
+        };
+        let root = ConfigRoot::resolve(&cfg).unwrap();
+        assert!(root.custom);
+        assert_eq!(root.path, PathBuf::from("/srv/project/ssh_config"));
+        assert_eq!(root.ssh_args(), vec!["-F", "/srv/project/ssh_config"]);
+    }
+
+    #[test]
+    fn discover_tunnels_from_custom_root() {
+        let dir = std::env::temp_dir();
+        let config = dir.join("mole_test_custom_root");
+        std::fs::write(&config, "Host custom\n  LocalForward 8080 localhost:80\n").unwrap();
+
+        let cfg = Config {
+            ssh_config: Some(config.to_string_lossy().to_string()),
+            ..Config::default()
+        };
+        let root = ConfigRoot::resolve(&cfg).unwrap();
+        let tunnels = discover_tunnels(&root).unwrap();
+        std::fs::remove_file(&config).unwrap();
+
+        assert_eq!(tunnels.len(), 1);
+        assert_eq!(tunnels[0].name, "custom");
+    }
+
+    #[test]
+    fn nested_include_blocks_are_editable() {
+        let dir = std::env::temp_dir().join("mole_test_nested_edit");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        std::fs::write(dir.join("config"), "Include conf.d/*\n").unwrap();
+        std::fs::write(dir.join("conf.d/team"), "Include projects\n").unwrap();
+        std::fs::write(
+            dir.join("projects"),
+            "Host deep\n  LocalForward 8080 localhost:80\n\nHost other\n  LocalForward 9090 localhost:90\n",
+        )
+        .unwrap();
+
+        let root = ConfigRoot {
+            path: dir.join("config"),
+            ssh_dir: dir.clone(),
+            custom: true,
+        };
+        let found = discover(&root).unwrap();
+        assert_eq!(found.tunnels.len(), 2);
+
+        let (path, block) = read_host_block(&root, "deep").unwrap().unwrap();
+        assert_eq!(path, dir.join("projects"));
+        assert!(block.starts_with("Host deep"));
+
+        rename_host_block(&root, "deep", "deeper").unwrap();
+        remove_host_block(&root, "other").unwrap();
+        let content = std::fs::read_to_string(dir.join("projects")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(content, "Host deeper\n  LocalForward 8080 localhost:80\n");
+    }
+
+    #[test]
+    fn parse_config_match_adds_forwards() {
+        let dir = std::env::temp_dir();
+        let config = dir.join("mole_test_ssh_match_forwards");
+        std::fs::write(
+            &config,
+            "\
+Host cache
+  HostName cache.internal
+
+Match host *.internal
+  LocalForward 6379 localhost:6379
+
+Match canonical host cache.internal
+  LocalForward 7000 localhost:7000
+",
+        )
+        .unwrap();
+
+        let set = include::load(&config, &dir).unwrap();
+        let mut tunnels = Vec::new();
+        let warnings = collect_tunnels(&set, &mut tunnels, Exec::Run);
+        std::fs::remove_file(&config).unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(tunnels.len(), 1);
+        assert_eq!(tunnels[0].forwards.len(), 1);
+        assert_eq!(tunnels[0].forwards[0].listen.port(), Some(6379));
+    }
+
+    #[test]
+    fn parse_config_wildcard_blocks_merge() {
+        let dir = std::env::temp_dir();
+        let config = dir.join("mole_test_ssh_wildcard_merge");
+        std::fs::write(
+            &config,
+            "\
+Host db.prod
+  HostName 10.0.0.5
+  User dba
+  LocalForward 5432 localhost:5432
+
+Host *.prod !bastion.prod
+  User ubuntu
+  ProxyJump bastion.prod
+  LocalForward 9100 localhost:9100
+  IdentityFile ~/.ssh/prod
+
+Host bastion.prod
+  HostName bastion.example.com
+
+Host web.prod
+  HostName 10.0.0.6
+
+Host *
+  Port 2222
+  IdentityFile ~/.ssh/default
+  LocalForward 5432 localhost:5432
+",
+        )
+        .unwrap();
+
+        let mut tunnels = Vec::new();
+        parse_file(&config, &dir, &mut tunnels).unwrap();
+        std::fs::remove_file(&config).unwrap();
+
+        let names: Vec<&str> = tunnels.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["db.prod", "bastion.prod", "web.prod"]);
+
+        let db = &tunnels[0];
+        assert_eq!(db.user.as_deref(), Some("dba"));
+        assert_eq!(db.proxy_jump.as_deref(), Some("bastion.prod"));
+        assert_eq!(db.port, Some(2222));
+        assert_eq!(db.identity_files, vec!["~/.ssh/prod", "~/.ssh/default"]);
+        // Duplicate forward from Host * is ignored, as ssh does
+        let ports: Vec<u16> = db.forwards.iter().filter_map(|f| f.listen.port()).collect();
+        assert_eq!(ports, vec![5432, 9100]);
+
+        // Negated pattern keeps the bastion out of the *.prod block
+        let bastion = &tunnels[1];
+        assert_eq!(bastion.proxy_jump, None);
+        assert_eq!(bastion.forwards.len(), 1);
+
+        let web = &tunnels[2];
+        assert_eq!(web.user.as_deref(), Some("ubuntu"));
+        assert_eq!(web.forwards.len(), 2);
+    }
+
+    #[test]
+    fn parse_config_multi_pattern_host_line() {
+        let dir = std::env::temp_dir();
+        let config = dir.join("mole_test_ssh_multi_pattern");
+        std::fs::write(
+            &config,
+            "Host api\n  HostName %h.internal\n\nHost web api\n  LocalForward 8080 localhost:80\n  # mole:group=apps\n",
+        )
+        .unwrap();
+
+        let mut tunnels = Vec::new();
+        parse_file(&config, &dir, &mut tunnels).unwrap();
+        std::fs::remove_file(&config).unwrap();
+
+        assert_eq!(tunnels.len(), 2);
+        assert_eq!(tunnels[0].name, "api");
+        assert_eq!(tunnels[0].hostname.as_deref(), Some("api.internal"));
+        assert_eq!(tunnels[0].forwards.len(), 1);
+        assert_eq!(tunnels[0].group.as_deref(), Some("apps"));
+        assert_eq!(tunnels[1].name, "web");
+    }
+
+    #[test]
+    fn parse_config_host_aliases() {
+        let dir = std::env::temp_dir();
+        let config = dir.join("mole_test_ssh_aliases");
+        std::fs::write(
+            &config,
+            "Host db db.prod 10.1.2.3 *.db\n  LocalForward 5432 localhost:5432\n\nHost web db\n  LocalForward 8080 localhost:80\n",
+        )
+        .unwrap();
+
+        let mut tunnels = Vec::new();
+        parse_file(&config, &dir, &mut tunnels).unwrap();
+        std::fs::remove_file(&config).unwrap();
+
+        assert_eq!(tunnels.len(), 2);
+        assert_eq!(tunnels[0].name, "db");
+        assert_eq!(tunnels[0].aliases, vec!["db", "db.prod", "10.1.2.3"]);
+        assert!(tunnels[0].has_name("db.prod"));
+        assert!(!tunnels[0].has_name("x.db"));
+        // `db` is already claimed by the first line
+        assert_eq!(tunnels[1].aliases, vec!["web"]);
+    }
+
+    #[test]
+    fn rename_and_remove_single_alias() {
+        let dir = std::env::temp_dir().join("mole_test_alias_edit");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("config"),
+            "Host db db.prod 10.1.2.3\n  LocalForward 5432 localhost:5432\n",
+        )
+        .unwrap();
+        let root = ConfigRoot {
+            path: dir.join("config"),
+            ssh_dir: dir.clone(),
+            custom: true,
+        };
+
+        rename_host_block(&root, "db.prod", "db.production").unwrap();
+        remove_host_alias(&root, "10.1.2.3").unwrap();
+        let content = std::fs::read_to_string(dir.join("config")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(content, "Host db db.production\n  LocalForward 5432 localhost:5432\n");
+    }
+
+    #[test]
+    fn remove_quoted_alias_keeps_block() {
+        let dir = std::env::temp_dir().join("mole_test_quoted_alias_remove");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("config"),
+            "Host \"db\" db.prod\n  LocalForward 5432 localhost:5432\n",
+        )
+        .unwrap();
+        let root = ConfigRoot {
+            path: dir.join("config"),
+            ssh_dir: dir.clone(),
+            custom: true,
+        };
+
+        remove_host_alias(&root, "db").unwrap();
+        let content = std::fs::read_to_string(dir.join("config")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(content, "Host db.prod\n  LocalForward 5432 localhost:5432\n");
+    }
+
+    #[test]
+    fn rename_quoted_host() {
+        let dir = std::env::temp_dir().join("mole_test_quoted_rename");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("config"), "Host \"db\"\n  LocalForward 5432 localhost:5432\n").unwrap();
+        let root = ConfigRoot {
+            path: dir.join("config"),
+            ssh_dir: dir.clone(),
+            custom: true,
+        };
+
+        rename_host_block(&root, "db", "primary").unwrap();
+        let content = std::fs::read_to_string(dir.join("config")).unwrap();
+        let tunnels = discover_tunnels(&root).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(content, "Host primary\n  LocalForward 5432 localhost:5432\n");
+        assert_eq!(tunnels[0].name, "primary");
+    }
+
+    #[test]
+    fn rename_rejects_names_that_split() {
+        let dir = std::env::temp_dir().join("mole_test_rename_bad_name");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = "Host db\n  LocalForward 5432 localhost:5432\n";
+        std::fs::write(dir.join("config"), config).unwrap();
+        let root = ConfigRoot {
+            path: dir.join("config"),
+            ssh_dir: dir.clone(),
+            custom: true,
+        };
+
+        for name in ["my db", "db\tprod", "\"db\"", "db*", "!db", "#db", ""] {
+            assert!(rename_host_block(&root, "db", name).is_err(), "{:?}", name);
+        }
+        let content = std::fs::read_to_string(dir.join("config")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(content, config);
+        assert!(check_host_name("db-prod.internal").is_ok());
+    }
+
+    #[test]
+    fn split_args_quotes() {
+        assert_eq!(split_args("a.conf  b/*.conf"), vec!["a.conf", "b/*.conf"]);
+        assert_eq!(split_args("\"dir with space/x\" y"), vec!["dir with space/x", "y"]);
+        assert_eq!(split_args("db db.prod # primary"), vec!["db", "db.prod"]);
+        assert!(split_args("").is_empty());
+        assert_eq!(arg_spans("\"db\" x"), vec![(0..4, "db".to_string()), (5..6, "x".to_string())]);
+    }
 
     #[test]
     fn split_directive_whitespace() {
@@ -410,22 +794,31 @@ mod tests {
         assert!(split_directive("").is_none());
         assert!(split_directive("   ").is_none());
         assert!(split_directive("KeyOnly").is_none());
+        assert!(split_directive("=value").is_none());
+    }
+
+    #[test]
+    fn split_directive_equals_in_value() {
+        assert_eq!(
+            split_directive("Match exec \"test %n = x\""),
+            Some(("Match", "exec \"test %n = x\""))
+        );
+        assert_eq!(split_directive("Host=a=b"), Some(("Host", "a=b")));
+        assert_eq!(split_directive("SetEnv FOO=bar"), Some(("SetEnv", "FOO=bar")));
     }
 
     #[test]
     fn parse_forward_localhost() {
         let fwd = parse_local_forward("16443 localhost:6443").unwrap();
-        assert_eq!(fwd.local_port, 16443);
-        assert_eq!(fwd.remote_host, "localhost");
-        assert_eq!(fwd.remote_port, 6443);
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: None, port: 16443 });
+        assert_eq!(fwd.target, Endpoint::Tcp { host: Some("localhost".to_string()), port: 6443 });
     }
 
     #[test]
     fn parse_forward_ip() {
         let fwd = parse_local_forward("8080 10.0.0.1:80").unwrap();
-        assert_eq!(fwd.local_port, 8080);
-        assert_eq!(fwd.remote_host, "10.0.0.1");
-        assert_eq!(fwd.remote_port, 80);
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: None, port: 8080 });
+        assert_eq!(fwd.target, Endpoint::Tcp { host: Some("10.0.0.1".to_string()), port: 80 });
     }
 
     #[test]
@@ -453,7 +846,7 @@ mod tests {
         assert_eq!(tunnels[0].name, "my-tunnel");
         assert_eq!(tunnels[0].hostname.as_deref(), Some("10.0.0.1"));
         assert_eq!(tunnels[0].forwards.len(), 1);
-        assert_eq!(tunnels[0].forwards[0].local_port, 16443);
+        assert_eq!(tunnels[0].forwards[0].listen.port(), Some(16443));
         assert!(tunnels[0].remote_forwards.is_empty());
     }
 
@@ -484,7 +877,7 @@ mod tests {
         )
         .unwrap();
 
-        let range = find_host_range(&config, "b").unwrap();
+        let range = find_block_range(&config, "b").unwrap();
         assert_eq!(range, Some((4, 8)));
 
         std::fs::remove_file(&config).unwrap();
@@ -500,7 +893,7 @@ mod tests {
         )
         .unwrap();
 
-        let range = find_host_range(&config, "a").unwrap();
+        let range = find_block_range(&config, "a").unwrap();
         assert_eq!(range, Some((0, 4)));
 
         std::fs::remove_file(&config).unwrap();
@@ -516,7 +909,7 @@ mod tests {
         )
         .unwrap();
 
-        let range = find_host_range(&config, "b").unwrap();
+        let range = find_block_range(&config, "b").unwrap();
         assert_eq!(range, Some((4, 7)));
 
         std::fs::remove_file(&config).unwrap();
@@ -532,7 +925,7 @@ mod tests {
         )
         .unwrap();
 
-        let range = find_host_range(&config, "missing").unwrap();
+        let range = find_block_range(&config, "missing").unwrap();
         assert_eq!(range, None);
 
         std::fs::remove_file(&config).unwrap();
@@ -581,6 +974,41 @@ Host tunnel-b\n  HostName b.example.com\n  LocalForward 9090 localhost:90\n  Loc
         assert_eq!(tunnels[0].group.as_deref(), Some("prod"));
     }
 
+    #[test]
+    fn parse_config_with_depends() {
+        let dir = std::env::temp_dir();
+        let config = dir.join("mole_test_ssh_depends");
+        std::fs::write(
+            &config,
+            "Host socks\n  DynamicForward 1080\n\nHost api\n  # mole:depends=socks, db\n  LocalForward 8080 localhost:80\n",
+        )
+        .unwrap();
+
+        let mut tunnels = Vec::new();
+        parse_file(&config, &dir, &mut tunnels).unwrap();
+        std::fs::remove_file(&config).unwrap();
+
+        assert!(tunnels[0].depends.is_empty());
+        assert_eq!(tunnels[1].depends, ["socks", "db"]);
+    }
+
+    #[test]
+    fn parse_config_with_ssh_options() {
+        let dir = std::env::temp_dir();
+        let config = dir.join("mole_test_ssh_options");
+        std::fs::write(
+            &config,
+            "Host db\n  # mole:ssh-option=-o ServerAliveInterval=15\n  # mole:ssh-option=-v\n  LocalForward 5432 localhost:5432\n",
+        )
+        .unwrap();
+
+        let mut tunnels = Vec::new();
+        parse_file(&config, &dir, &mut tunnels).unwrap();
+        std::fs::remove_file(&config).unwrap();
+
+        assert_eq!(tunnels[0].ssh_options, ["-o", "ServerAliveInterval=15", "-v"]);
+    }
+
     #[test]
     fn parse_config_without_group() {
         let dir = std::env::temp_dir();
@@ -624,26 +1052,78 @@ Host tunnel-c\n  HostName c.example.com\n  LocalForward 7070 localhost:70\n",
         assert_eq!(tunnels[2].group, None);
     }
 
+    #[test]
+    fn parse_forward_bind_addresses() {
+        let fwd = parse_local_forward("127.0.0.2:5432 db:5432").unwrap();
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: Some("127.0.0.2".to_string()), port: 5432 });
+        assert_eq!(fwd.to_string(), "127.0.0.2:5432:db:5432");
+
+        let fwd = parse_local_forward("*:8080 localhost:80").unwrap();
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: Some("*".to_string()), port: 8080 });
+        let fwd = parse_local_forward(":8080 localhost:80").unwrap();
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: Some("*".to_string()), port: 8080 });
+    }
+
+    #[test]
+    fn parse_forward_ipv6() {
+        let fwd = parse_local_forward("[::1]:8080 [fe80::1]:80").unwrap();
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: Some("::1".to_string()), port: 8080 });
+        assert_eq!(fwd.target, Endpoint::Tcp { host: Some("fe80::1".to_string()), port: 80 });
+        assert_eq!(fwd.to_string(), "[::1]:8080:[fe80::1]:80");
+
+        // Unbracketed IPv6 is ambiguous and rejected, as ssh does
+        assert!(parse_local_forward("::1:8080 localhost:80").is_none());
+        assert!(parse_local_forward("8080 fe80::1:80").is_none());
+    }
+
+    #[test]
+    fn parse_forward_unix_sockets() {
+        let fwd = parse_local_forward("/tmp/docker.sock /var/run/docker.sock").unwrap();
+        assert_eq!(fwd.listen, Endpoint::Unix("/tmp/docker.sock".to_string()));
+        assert_eq!(fwd.target, Endpoint::Unix("/var/run/docker.sock".to_string()));
+
+        let fwd = parse_local_forward("5432 /run/postgresql/.s.PGSQL.5432").unwrap();
+        assert_eq!(fwd.listen.port(), Some(5432));
+
+        let fwd = parse_remote_forward("/tmp/remote.sock localhost:3000").unwrap();
+        assert_eq!(fwd.listen, Endpoint::Unix("/tmp/remote.sock".to_string()));
+
+        assert!(parse_dynamic_forward("/tmp/socks.sock").is_none());
+    }
+
+    #[test]
+    fn parse_remote_forward_socks() {
+        let fwd = parse_remote_forward("0.0.0.0:1080").unwrap();
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: Some("0.0.0.0".to_string()), port: 1080 });
+        assert!(fwd.target.is_none());
+        assert_eq!(fwd.to_string(), "R:0.0.0.0:1080→SOCKS");
+    }
+
+    #[test]
+    fn parse_dynamic_forward_ipv6() {
+        let fwd = parse_dynamic_forward("[::1]:1080").unwrap();
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: Some("::1".to_string()), port: 1080 });
+        assert_eq!(fwd.to_string(), "D:[::1]:1080");
+    }
+
     #[test]
     fn parse_remote_forward_basic() {
         let fwd = parse_remote_forward("9090 localhost:3000").unwrap();
-        assert_eq!(fwd.bind_port, 9090);
-        assert_eq!(fwd.remote_host, "localhost");
-        assert_eq!(fwd.remote_port, 3000);
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: None, port: 9090 });
+        assert_eq!(fwd.target, Some(Endpoint::Tcp { host: Some("localhost".to_string()), port: 3000 }));
     }
 
     #[test]
     fn parse_remote_forward_ip() {
         let fwd = parse_remote_forward("8080 10.0.0.1:80").unwrap();
-        assert_eq!(fwd.bind_port, 8080);
-        assert_eq!(fwd.remote_host, "10.0.0.1");
-        assert_eq!(fwd.remote_port, 80);
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: None, port: 8080 });
+        assert_eq!(fwd.target, Some(Endpoint::Tcp { host: Some("10.0.0.1".to_string()), port: 80 }));
     }
 
     #[test]
     fn parse_remote_forward_invalid() {
         assert!(parse_remote_forward("not_a_port localhost:80").is_none());
-        assert!(parse_remote_forward("8080").is_none());
+        assert!(parse_remote_forward("/tmp/remote.sock").is_none());
         assert!(parse_remote_forward("").is_none());
     }
 
@@ -665,9 +1145,7 @@ Host tunnel-c\n  HostName c.example.com\n  LocalForward 7070 localhost:70\n",
         assert_eq!(tunnels[0].name, "reverse-tunnel");
         assert!(tunnels[0].forwards.is_empty());
         assert_eq!(tunnels[0].remote_forwards.len(), 1);
-        assert_eq!(tunnels[0].remote_forwards[0].bind_port, 9090);
-        assert_eq!(tunnels[0].remote_forwards[0].remote_host, "localhost");
-        assert_eq!(tunnels[0].remote_forwards[0].remote_port, 3000);
+        assert_eq!(tunnels[0].remote_forwards[0].to_string(), "R:9090→localhost:3000");
     }
 
     #[test]
@@ -687,20 +1165,20 @@ Host tunnel-c\n  HostName c.example.com\n  LocalForward 7070 localhost:70\n",
         assert_eq!(tunnels.len(), 1);
         assert_eq!(tunnels[0].forwards.len(), 1);
         assert_eq!(tunnels[0].remote_forwards.len(), 1);
-        assert_eq!(tunnels[0].forwards[0].local_port, 8080);
-        assert_eq!(tunnels[0].remote_forwards[0].bind_port, 9090);
+        assert_eq!(tunnels[0].forwards[0].listen.port(), Some(8080));
+        assert_eq!(tunnels[0].remote_forwards[0].listen.port(), Some(9090));
     }
 
     #[test]
     fn parse_dynamic_forward_port_only() {
         let fwd = parse_dynamic_forward("1080").unwrap();
-        assert_eq!(fwd.listen_port, 1080);
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: None, port: 1080 });
     }
 
     #[test]
     fn parse_dynamic_forward_with_bind_address() {
         let fwd = parse_dynamic_forward("127.0.0.1:1080").unwrap();
-        assert_eq!(fwd.listen_port, 1080);
+        assert_eq!(fwd.listen, Endpoint::Tcp { host: Some("127.0.0.1".to_string()), port: 1080 });
     }
 
     #[test]
@@ -728,7 +1206,7 @@ Host tunnel-c\n  HostName c.example.com\n  LocalForward 7070 localhost:70\n",
         assert!(tunnels[0].forwards.is_empty());
         assert!(tunnels[0].remote_forwards.is_empty());
         assert_eq!(tunnels[0].dynamic_forwards.len(), 1);
-        assert_eq!(tunnels[0].dynamic_forwards[0].listen_port, 1080);
+        assert_eq!(tunnels[0].dynamic_forwards[0].listen.port(), Some(1080));
     }
 
     #[test]
@@ -749,6 +1227,6 @@ Host tunnel-c\n  HostName c.example.com\n  LocalForward 7070 localhost:70\n",
         assert_eq!(tunnels[0].forwards.len(), 1);
         assert_eq!(tunnels[0].remote_forwards.len(), 1);
         assert_eq!(tunnels[0].dynamic_forwards.len(), 1);
-        assert_eq!(tunnels[0].dynamic_forwards[0].listen_port, 1080);
+        assert_eq!(tunnels[0].dynamic_forwards[0].listen.port(), Some(1080));
     }
 }
@@ -0,0 +1,373 @@
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use super::{arg_spans, split_directive};
+
+/// One SSH config file as a list of lines, each kept byte-for-byte with its own
+/// line ending, so an unmodified document writes back exactly as it was read.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// The line's text, without its ending (indentation, separators and
+    /// trailing comments untouched).
+    pub text: String,
+    /// "\n", "\r\n", or "" for a final line without a newline.
+    pub ending: String,
+}
+
+impl Line {
+    /// The `(key, value)` of a directive line; None for blanks and comments.
+    pub fn directive(&self) -> Option<(&str, &str)> {
+        let trimmed = self.text.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            return None;
+        }
+        split_directive(trimmed)
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.text.trim().is_empty()
+    }
+
+    pub fn is_comment(&self) -> bool {
+        self.text.trim_start().starts_with('#')
+    }
+
+    /// Whether this line starts a `Host` or `Match` block.
+    fn is_header(&self) -> bool {
+        self.directive().is_some_and(|(key, _)| {
+            key.eq_ignore_ascii_case("host") || key.eq_ignore_ascii_case("match")
+        })
+    }
+
+    fn indent(&self) -> usize {
+        self.text.len() - self.text.trim_start().len()
+    }
+}
+
+/// A `Host`/`Match` block as a line range [start, end) of its document.
+/// `start` includes the comments directly above the header (no blank line
+/// between, indented no deeper than the header); `end` runs up to the next
+/// block's first line, so it includes the blank lines that separate them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub header: usize,
+    pub end: usize,
+}
+
+impl Document {
+    pub fn parse(content: &str) -> Self {
+        let lines = content
+            .split_inclusive('\n')
+            .map(|chunk| {
+                let (text, ending) = if let Some(text) = chunk.strip_suffix("\r\n") {
+                    (text, "\r\n")
+                } else if let Some(text) = chunk.strip_suffix('\n') {
+                    (text, "\n")
+                } else {
+                    (chunk, "")
+                };
+                Line {
+                    text: text.to_string(),
+                    ending: ending.to_string(),
+                }
+            })
+            .collect();
+        Self { lines }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(Self::parse(&content))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_string())
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    /// Every `Host`/`Match` block in file order.
+    pub fn blocks(&self) -> Vec<Block> {
+        let headers: Vec<usize> = (0..self.lines.len())
+            .filter(|&i| self.lines[i].is_header())
+            .collect();
+        let starts: Vec<usize> = headers.iter().map(|&h| self.block_start(h)).collect();
+
+        headers
+            .iter()
+            .enumerate()
+            .map(|(i, &header)| Block {
+                start: starts[i],
+                header,
+                end: starts.get(i + 1).copied().unwrap_or(self.lines.len()),
+            })
+            .collect()
+    }
+
+    /// First line of the block headed at `header`: walk up over the comments
+    /// that sit directly above it. A comment indented deeper than the header
+    /// (e.g. a trailing `  # mole:group=...`) still belongs to the block above.
+    fn block_start(&self, header: usize) -> usize {
+        let indent = self.lines[header].indent();
+        let mut start = header;
+        while start > 0 {
+            let line = &self.lines[start - 1];
+            if !line.is_comment() || line.indent() > indent {
+                break;
+            }
+            start -= 1;
+        }
+        start
+    }
+
+    /// The block whose `Host` line lists `name` as one of its patterns.
+    pub fn find_host(&self, name: &str) -> Option<Block> {
+        self.blocks().into_iter().find(|b| {
+            matches!(self.lines[b.header].directive(), Some((key, value))
+                if key.eq_ignore_ascii_case("host")
+                    && super::split_args(value).iter().any(|p| p == name))
+        })
+    }
+
+    /// The block's text without the blank lines that separate it from the next.
+    pub fn block_text(&self, block: Block) -> String {
+        let mut end = block.end;
+        while end > block.header + 1 && self.lines[end - 1].is_blank() {
+            end -= 1;
+        }
+        self.lines[block.start..end]
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                if block.start + i + 1 == end {
+                    line.text.clone()
+                } else {
+                    format!("{}{}", line.text, line.ending)
+                }
+            })
+            .collect()
+    }
+
+    /// Remove a block with its leading comments. The blank lines after it go too,
+    /// so the separator above it now separates its neighbours; when it was the
+    /// last block, the blank lines left dangling above it are dropped instead.
+    pub fn remove_block(&mut self, block: Block) {
+        let was_last = block.end == self.lines.len();
+        self.lines.drain(block.start..block.end);
+        if was_last {
+            while self.lines.last().is_some_and(|l| l.is_blank()) {
+                self.lines.pop();
+            }
+        }
+    }
+
+    /// Replace or drop one pattern on the block's Host line; the line is left
+    /// alone on error.
+    pub fn edit_host_pattern(&mut self, block: Block, old: &str, new: Option<&str>) -> Result<(), PatternError> {
+        self.lines[block.header].text = edit_host_line(&self.lines[block.header].text, old, new)?;
+        Ok(())
+    }
+
+    /// Append `text` (lines separated by "\n") as a new block, after a blank line,
+    /// using the file's own line endings.
+    pub fn append_block(&mut self, text: &str) {
+        let newline = if self.lines.iter().any(|l| l.ending == "\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        if let Some(last) = self.lines.last_mut() {
+            if last.ending.is_empty() {
+                last.ending = newline.to_string();
+            }
+            if !last.is_blank() {
+                self.lines.push(Line {
+                    text: String::new(),
+                    ending: newline.to_string(),
+                });
+            }
+        }
+        for line in text.lines() {
+            self.lines.push(Line {
+                text: line.to_string(),
+                ending: newline.to_string(),
+            });
+        }
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            f.write_str(&line.text)?;
+            f.write_str(&line.ending)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a pattern on a `Host` line couldn't be edited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternError {
+    /// The line doesn't list the pattern.
+    NotFound,
+    /// Dropping it would leave the line without patterns; the block must go instead.
+    OnlyPattern,
+}
+
+/// Rewrite one pattern of a `Host` line, leaving the keyword, separator and every
+/// other pattern byte-for-byte. Patterns are matched as ssh reads them, so `"db"`
+/// matches `db`. With `new` the pattern (quotes and all) is replaced; with None
+/// it is dropped.
+fn edit_host_line(line: &str, old: &str, new: Option<&str>) -> Result<String, PatternError> {
+    // Offset of the first pattern: past indentation, keyword and separator
+    let indent = line.len() - line.trim_start().len();
+    let Some(key_len) = line[indent..].find(|c: char| c.is_whitespace() || c == '=') else {
+        return Err(PatternError::NotFound);
+    };
+    let after_key = indent + key_len;
+    let value_start = after_key
+        + line[after_key..]
+            .find(|c: char| !c.is_whitespace() && c != '=')
+            .unwrap_or(line.len() - after_key);
+
+    let args = arg_spans(&line[value_start..]);
+    let idx = args.iter().position(|(_, arg)| arg == old).ok_or(PatternError::NotFound)?;
+    let spans: Vec<Range<usize>> = args
+        .into_iter()
+        .map(|(span, _)| value_start + span.start..value_start + span.end)
+        .collect();
+    let (s, e) = (spans[idx].start, spans[idx].end);
+    match new {
+        Some(new) => Ok(format!("{}{}{}", &line[..s], new, &line[e..])),
+        None if spans.len() == 1 => Err(PatternError::OnlyPattern),
+        None if idx + 1 < spans.len() => {
+            // Drop the pattern and the whitespace that follows it
+            Ok(format!("{}{}", &line[..s], &line[spans[idx + 1].start..]))
+        }
+        None => {
+            // Last pattern: drop it and the whitespace before it
+            Ok(format!("{}{}", &line[..spans[idx - 1].end], &line[e..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIXED: &str = "# global\r\nHost=a\r\n\tUser  x\r\n\r\n\r\n  # mole:group=prod\r\n# Tunnel: b\r\nHost b b2 # note\r\n  LocalForward 1 localhost:1";
+
+    #[test]
+    fn round_trips_byte_for_byte() {
+        for content in [
+            MIXED,
+            "",
+            "\n\n",
+            "Host a\n  User x\n\n\n",
+            "Host a\r\n  User = x\n",
+            "Include ~/.ssh/conf.d/*\nHost *\n   ServerAliveInterval 30",
+        ] {
+            assert_eq!(Document::parse(content).to_string(), content);
+        }
+    }
+
+    #[test]
+    fn leading_comments_belong_to_next_block() {
+        let doc = Document::parse(MIXED);
+        let blocks = doc.blocks();
+        assert_eq!(blocks.len(), 2);
+        // The indented annotation stays with `a`; `# Tunnel: b` moves to `b`
+        assert_eq!(blocks[0], Block { start: 0, header: 1, end: 6 });
+        assert_eq!(blocks[1], Block { start: 6, header: 7, end: 9 });
+        assert_eq!(doc.find_host("b2"), Some(blocks[1]));
+        assert_eq!(doc.find_host("note"), None);
+    }
+
+    #[test]
+    fn remove_middle_block_keeps_neighbours() {
+        let content = "Host a\n  User x\n\n# Tunnel: b\nHost b\n  User y\n\nHost c\n  User z\n";
+        let mut doc = Document::parse(content);
+        let block = doc.find_host("b").unwrap();
+        assert_eq!(doc.block_text(block), "# Tunnel: b\nHost b\n  User y");
+        doc.remove_block(block);
+        assert_eq!(doc.to_string(), "Host a\n  User x\n\nHost c\n  User z\n");
+    }
+
+    #[test]
+    fn remove_last_block_drops_dangling_blank_lines() {
+        let content = "Host a\r\n  User x\r\n  # mole:group=prod\r\n\r\nHost b\r\n  User y\r\n";
+        let mut doc = Document::parse(content);
+        doc.remove_block(doc.find_host("b").unwrap());
+        assert_eq!(doc.to_string(), "Host a\r\n  User x\r\n  # mole:group=prod\r\n");
+    }
+
+    #[test]
+    fn edit_keeps_line_ending_and_spacing() {
+        let mut doc = Document::parse("Host  a\tb # keep\r\n  User x\r\n");
+        let block = doc.find_host("b").unwrap();
+        assert_eq!(doc.edit_host_pattern(block, "b", Some("c")), Ok(()));
+        assert_eq!(doc.edit_host_pattern(block, "a", None), Ok(()));
+        assert_eq!(doc.edit_host_pattern(block, "c", None), Err(PatternError::OnlyPattern));
+        assert_eq!(doc.edit_host_pattern(block, "a", Some("x")), Err(PatternError::NotFound));
+        assert_eq!(doc.to_string(), "Host  c # keep\r\n  User x\r\n");
+    }
+
+    #[test]
+    fn append_matches_line_endings() {
+        let mut doc = Document::parse("Host a\r\n  User x");
+        doc.append_block("# Tunnel: b\nHost b\n  User y\n");
+        assert_eq!(
+            doc.to_string(),
+            "Host a\r\n  User x\r\n\r\n# Tunnel: b\r\nHost b\r\n  User y\r\n"
+        );
+
+        let mut empty = Document::parse("");
+        empty.append_block("Host b\n");
+        assert_eq!(empty.to_string(), "Host b\n");
+    }
+
+    #[test]
+    fn edit_host_line_rename_alias() {
+        assert_eq!(
+            edit_host_line("Host db  db.prod\t10.1.2.3", "db.prod", Some("db.production")).unwrap(),
+            "Host db  db.production\t10.1.2.3"
+        );
+        assert_eq!(
+            edit_host_line("  Host=db", "db", Some("primary")).unwrap(),
+            "  Host=primary"
+        );
+        assert_eq!(edit_host_line("Host db", "other", Some("x")), Err(PatternError::NotFound));
+    }
+
+    #[test]
+    fn edit_host_line_remove_alias() {
+        assert_eq!(edit_host_line("Host db db.prod 10.1.2.3", "db", None).unwrap(), "Host db.prod 10.1.2.3");
+        assert_eq!(edit_host_line("Host db db.prod 10.1.2.3", "db.prod", None).unwrap(), "Host db 10.1.2.3");
+        assert_eq!(edit_host_line("Host db db.prod  # note", "db.prod", None).unwrap(), "Host db  # note");
+        assert_eq!(edit_host_line("Host db db.prod", "db.prod", None).unwrap(), "Host db");
+        assert_eq!(edit_host_line("Host db", "db", None), Err(PatternError::OnlyPattern));
+        assert_eq!(edit_host_line("Host db", "other", None), Err(PatternError::NotFound));
+    }
+
+    #[test]
+    fn edit_host_line_quoted_patterns() {
+        assert_eq!(edit_host_line("Host \"db\" db.prod", "db", None).unwrap(), "Host db.prod");
+        assert_eq!(edit_host_line("Host db.prod \"db\"", "db", Some("primary")).unwrap(), "Host db.prod primary");
+        assert_eq!(edit_host_line("Host \"db\"", "db", None), Err(PatternError::OnlyPattern));
+        assert_eq!(edit_host_line("Host \"my db\" x", "my", None), Err(PatternError::NotFound));
+    }
+}
@@ -0,0 +1,285 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::document::Document;
+use super::split_args;
+
+/// OpenSSH refuses to nest Include directives deeper than this (READCONF_MAX_DEPTH).
+const MAX_DEPTH: usize = 16;
+
+/// A line from one of the config files, tagged with where it came from.
+#[derive(Debug, Clone)]
+pub struct ConfigLine {
+    /// Index into [`ConfigSet::files`] (for Include markers, the including file).
+    pub file: usize,
+    /// Zero-based line number within that file.
+    pub number: usize,
+    pub kind: LineKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineKind {
+    /// A `Key value` or `Key=value` directive. Include lines never appear here —
+    /// they are replaced by the contents of the files they pull in.
+    Directive { key: String, value: String },
+    /// A `#` comment, kept so `# mole:` annotations can be read.
+    Comment(String),
+    /// The lines up to the matching `IncludeEnd` come from an included file.
+    IncludeStart,
+    IncludeEnd,
+}
+
+/// Every file reachable from a root config, flattened in the order ssh reads them.
+#[derive(Debug, Default)]
+pub struct ConfigSet {
+    /// Each file read, root first, in first-visit order.
+    pub files: Vec<PathBuf>,
+    pub lines: Vec<ConfigLine>,
+    /// Include targets that were missing, unreadable or cyclic.
+    pub warnings: Vec<String>,
+}
+
+/// Read `path` and follow its Include directives recursively, the way OpenSSH does:
+/// patterns are globbed in sorted order, relative paths resolve against `ssh_dir`,
+/// and files already being read are skipped to break cycles.
+pub fn load(path: &Path, ssh_dir: &Path) -> Result<ConfigSet> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let mut walker = Walker {
+        set: ConfigSet::default(),
+        ssh_dir,
+        stack: Vec::new(),
+    };
+    walker.visit(path, &content);
+    Ok(walker.set)
+}
+
+struct Walker<'a> {
+    set: ConfigSet,
+    ssh_dir: &'a Path,
+    /// Canonical paths of the files currently open, innermost last.
+    stack: Vec<PathBuf>,
+}
+
+impl Walker<'_> {
+    fn visit(&mut self, path: &Path, content: &str) {
+        let file = self.file_index(path);
+        self.stack.push(canonical(path));
+
+        let doc = Document::parse(content);
+        for (number, line) in doc.lines().iter().enumerate() {
+            if line.is_comment() {
+                self.push(file, number, LineKind::Comment(line.text.trim().to_string()));
+                continue;
+            }
+            let (key, value) = match line.directive() {
+                Some(pair) => pair,
+                None => continue,
+            };
+            if key.eq_ignore_ascii_case("include") {
+                for pattern in split_args(value) {
+                    self.include(&pattern, file, number);
+                }
+            } else {
+                self.push(
+                    file,
+                    number,
+                    LineKind::Directive {
+                        key: key.to_string(),
+                        value: value.to_string(),
+                    },
+                );
+            }
+        }
+
+        self.stack.pop();
+    }
+
+    fn include(&mut self, pattern: &str, file: usize, number: usize) {
+        let location = format!("{}:{}", self.set.files[file].display(), number + 1);
+        let expanded = expand_include_path(pattern, self.ssh_dir);
+
+        let mut matches: Vec<PathBuf> = match glob::glob(&expanded.to_string_lossy()) {
+            Ok(paths) => paths.flatten().collect(),
+            Err(e) => {
+                self.set
+                    .warnings
+                    .push(format!("{}: invalid Include pattern '{}': {}", location, pattern, e));
+                return;
+            }
+        };
+        matches.sort();
+
+        if matches.is_empty() && !has_glob_chars(pattern) {
+            self.set.warnings.push(format!(
+                "{}: included file {} not found",
+                location,
+                expanded.display()
+            ));
+            return;
+        }
+
+        for path in matches {
+            if path.is_dir() {
+                continue;
+            }
+            if self.stack.contains(&canonical(&path)) {
+                self.set.warnings.push(format!(
+                    "{}: Include cycle — {} is already being read, skipping",
+                    location,
+                    path.display()
+                ));
+                continue;
+            }
+            if self.stack.len() >= MAX_DEPTH {
+                self.set.warnings.push(format!(
+                    "{}: Include nested more than {} levels deep, skipping {}",
+                    location,
+                    MAX_DEPTH,
+                    path.display()
+                ));
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(content) => {
+                    self.push(file, number, LineKind::IncludeStart);
+                    self.visit(&path, &content);
+                    self.push(file, number, LineKind::IncludeEnd);
+                }
+                Err(e) => self.set.warnings.push(format!(
+                    "{}: cannot read included file {}: {}",
+                    location,
+                    path.display(),
+                    e
+                )),
+            }
+        }
+    }
+
+    fn file_index(&mut self, path: &Path) -> usize {
+        if let Some(idx) = self.set.files.iter().position(|f| f == path) {
+            return idx;
+        }
+        self.set.files.push(path.to_path_buf());
+        self.set.files.len() - 1
+    }
+
+    fn push(&mut self, file: usize, number: usize, kind: LineKind) {
+        self.set.lines.push(ConfigLine { file, number, kind });
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn has_glob_chars(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+fn expand_include_path(pattern: &str, ssh_dir: &Path) -> PathBuf {
+    if let Some(rest) = pattern.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        home.join(rest)
+    } else if pattern.starts_with('/') {
+        PathBuf::from(pattern)
+    } else {
+        ssh_dir.join(pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn hosts(set: &ConfigSet) -> Vec<String> {
+        set.lines
+            .iter()
+            .filter_map(|l| match &l.kind {
+                LineKind::Directive { key, value } if key.eq_ignore_ascii_case("host") => {
+                    Some(value.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn follows_nested_includes() {
+        let dir = test_dir("mole_test_inc_nested");
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        fs::write(dir.join("config"), "Include conf.d/*.conf\nHost top\n").unwrap();
+        fs::write(dir.join("conf.d/a.conf"), "Include deeper\nHost a\n").unwrap();
+        fs::write(dir.join("deeper"), "Host deep\n").unwrap();
+
+        let set = load(&dir.join("config"), &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(hosts(&set), vec!["deep", "a", "top"]);
+        assert_eq!(set.files.len(), 3);
+        assert!(set.warnings.is_empty());
+    }
+
+    #[test]
+    fn globs_in_sorted_order_with_multiple_patterns() {
+        let dir = test_dir("mole_test_inc_sorted");
+        fs::write(dir.join("config"), "Include z.conf b-*.conf\n").unwrap();
+        fs::write(dir.join("z.conf"), "Host z\n").unwrap();
+        fs::write(dir.join("b-2.conf"), "Host b2\n").unwrap();
+        fs::write(dir.join("b-1.conf"), "Host b1\n").unwrap();
+
+        let set = load(&dir.join("config"), &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(hosts(&set), vec!["z", "b1", "b2"]);
+    }
+
+    #[test]
+    fn skips_cycles() {
+        let dir = test_dir("mole_test_inc_cycle");
+        fs::write(dir.join("config"), "Include a\nHost root\n").unwrap();
+        fs::write(dir.join("a"), "Include config\nHost a\n").unwrap();
+
+        let set = load(&dir.join("config"), &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(hosts(&set), vec!["a", "root"]);
+        assert_eq!(set.warnings.len(), 1);
+        assert!(set.warnings[0].contains("cycle"));
+    }
+
+    #[test]
+    fn warns_about_missing_literal_include() {
+        let dir = test_dir("mole_test_inc_missing");
+        fs::write(dir.join("config"), "Include nope.conf\nInclude empty/*.conf\nHost a\n").unwrap();
+
+        let set = load(&dir.join("config"), &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(hosts(&set), vec!["a"]);
+        assert_eq!(set.warnings.len(), 1);
+        assert!(set.warnings[0].contains("nope.conf"));
+        assert!(set.warnings[0].contains("config:1"));
+    }
+
+    #[test]
+    fn keeps_comments_for_annotations() {
+        let dir = test_dir("mole_test_inc_comments");
+        fs::write(dir.join("config"), "Host a\n  # mole:group=prod\n").unwrap();
+
+        let set = load(&dir.join("config"), &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(set.lines[1].kind, LineKind::Comment("# mole:group=prod".to_string()));
+        assert_eq!(set.lines[1].number, 1);
+    }
+}
@@ -0,0 +1,616 @@
+use std::collections::{BTreeMap, HashMap};
+use std::process::{Command, Stdio};
+
+use super::include::{ConfigSet, LineKind};
+use super::split_args;
+
+/// Options that accumulate across matching blocks instead of first-value-wins.
+const LIST_OPTIONS: &[&str] = &[
+    "localforward",
+    "remoteforward",
+    "dynamicforward",
+    "identityfile",
+    "certificatefile",
+    "sendenv",
+    "mole:ssh-option",
+];
+
+/// The effective options ssh would use for one host, built by evaluating
+/// every Host block that matches it, in file order.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HostConfig {
+    /// First value seen for each single-valued option, keyed by lowercase name.
+    values: BTreeMap<String, String>,
+    /// Every distinct value of each accumulating option, in order.
+    lists: BTreeMap<String, Vec<String>>,
+}
+
+impl HostConfig {
+    /// First value of a single-valued option (case-insensitive key).
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(&key.to_lowercase()).map(|v| v.as_str())
+    }
+
+    /// All values of an accumulating option such as LocalForward or IdentityFile.
+    pub fn list(&self, key: &str) -> &[String] {
+        self.lists
+            .get(&key.to_lowercase())
+            .map(|v| v.as_slice())
+            .unwrap_or(&[])
+    }
+
+    fn apply(&mut self, key: &str, value: &str) {
+        let key = key.to_lowercase();
+        if LIST_OPTIONS.contains(&key.as_str()) {
+            let list = self.lists.entry(key).or_default();
+            // ssh ignores exact duplicates (e.g. the same forward from two blocks)
+            if !list.iter().any(|v| v == value) {
+                list.push(value.to_string());
+            }
+        } else {
+            self.values.entry(key).or_insert_with(|| value.to_string());
+        }
+    }
+}
+
+/// Whether resolving runs the `Match exec` commands in the user's config.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exec {
+    Run,
+    /// Treat every `exec` criterion as not matching. For shell completion and
+    /// background scans, which must not stall on the commands or keep rerunning them.
+    Skip,
+}
+
+/// Evaluates the config for individual hosts, caching `Match exec` results
+/// and collecting warnings (deduplicated) across hosts.
+pub struct Resolver<'a> {
+    set: &'a ConfigSet,
+    exec: Exec,
+    exec_cache: HashMap<String, bool>,
+    pub warnings: Vec<String>,
+}
+
+/// What a host is being evaluated as, for Host patterns and Match criteria.
+struct Target<'a> {
+    /// Name as typed on the command line (`originalhost`, `%n`).
+    original: &'a str,
+    /// Name Host lines match against: the original name, or HostName on the final pass.
+    host: &'a str,
+    final_pass: bool,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(set: &'a ConfigSet, exec: Exec) -> Self {
+        Self {
+            set,
+            exec,
+            exec_cache: HashMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Evaluate the whole config for `host` the way ssh does: global options and
+    /// every matching `Host`/`Match` block apply, scalars keep their first value
+    /// and forwards accumulate. If any `Match final` is present the config is
+    /// evaluated a second time as ssh's final pass. `# mole:key=value` annotations
+    /// inside a block are treated as options named `mole:key`.
+    pub fn resolve(&mut self, host: &str) -> HostConfig {
+        let mut config = HostConfig::default();
+        let first = Target {
+            original: host,
+            host,
+            final_pass: false,
+        };
+        let want_final = self.evaluate(&first, &mut config);
+
+        if want_final {
+            let hostname = config
+                .get("hostname")
+                .map(|h| expand_hostname(h, host))
+                .unwrap_or_else(|| host.to_string());
+            let last = Target {
+                original: host,
+                host: &hostname,
+                final_pass: true,
+            };
+            self.evaluate(&last, &mut config);
+        }
+
+        config
+    }
+
+    /// One pass over the config. Returns whether a `Match final` asked for another pass.
+    fn evaluate(&mut self, target: &Target, config: &mut HostConfig) -> bool {
+        let set = self.set;
+        let mut want_final = false;
+        // Options before the first Host line apply to every host
+        let mut active = true;
+        let mut in_block = false;
+        // Files included from an inactive block can never match (OpenSSH's NEVERMATCH)
+        let mut never_match = false;
+        let mut include_stack: Vec<(bool, bool, bool)> = Vec::new();
+
+        for line in &set.lines {
+            match &line.kind {
+                LineKind::IncludeStart => {
+                    include_stack.push((active, in_block, never_match));
+                    never_match |= !active;
+                }
+                LineKind::IncludeEnd => {
+                    if let Some((a, b, n)) = include_stack.pop() {
+                        active = a;
+                        in_block = b;
+                        never_match = n;
+                    }
+                }
+                LineKind::Comment(text) => {
+                    if active
+                        && in_block
+                        && let Some((key, value)) = parse_annotation(text)
+                    {
+                        config.apply(&format!("mole:{}", key), value);
+                    }
+                }
+                LineKind::Directive { key, value } => {
+                    if key.eq_ignore_ascii_case("host") {
+                        in_block = true;
+                        active = !never_match && host_matches(target.host, &split_args(value));
+                    } else if key.eq_ignore_ascii_case("match") {
+                        in_block = true;
+                        let location =
+                            format!("{}:{}", set.files[line.file].display(), line.number + 1);
+                        let (matched, wants_final) =
+                            self.match_criteria(value, target, config, &location);
+                        want_final |= wants_final;
+                        active = !never_match && matched;
+                    } else if active {
+                        config.apply(key, value);
+                    }
+                }
+            }
+        }
+
+        want_final
+    }
+
+    /// Evaluate the criteria of a `Match` line. All criteria must hold.
+    /// Returns (matched, whether the line uses `final`).
+    fn match_criteria(
+        &mut self,
+        value: &str,
+        target: &Target,
+        config: &HostConfig,
+        location: &str,
+    ) -> (bool, bool) {
+        let args = split_args(value);
+        let mut result = true;
+        let mut wants_final = false;
+        let mut i = 0;
+
+        while i < args.len() {
+            let (negate, attrib) = match args[i].strip_prefix('!') {
+                Some(rest) => (true, rest.to_lowercase()),
+                None => (false, args[i].to_lowercase()),
+            };
+            i += 1;
+
+            let matched = match attrib.as_str() {
+                "all" => true,
+                "canonical" | "final" => {
+                    // Both match only on the final pass (mole never canonicalizes)
+                    wants_final |= attrib == "final";
+                    target.final_pass
+                }
+                _ => {
+                    let Some(arg) = args.get(i) else {
+                        self.warn(format!("{}: Match {} is missing its argument", location, attrib));
+                        return (false, wants_final);
+                    };
+                    i += 1;
+                    match attrib.as_str() {
+                        "host" => {
+                            let hostname = config
+                                .get("hostname")
+                                .map(|h| expand_hostname(h, target.original))
+                                .unwrap_or_else(|| target.host.to_string());
+                            match_pattern_list(&hostname, arg)
+                        }
+                        "originalhost" => match_pattern_list(target.original, arg),
+                        "user" => {
+                            let user = config
+                                .get("user")
+                                .map(str::to_string)
+                                .unwrap_or_else(whoami::username);
+                            match_pattern_list(&user, arg)
+                        }
+                        "localuser" => match_pattern_list(&whoami::username(), arg),
+                        "tagged" => match_pattern_list(config.get("tag").unwrap_or(""), arg),
+                        "exec" => {
+                            // Like ssh, skip running commands once the line has already failed
+                            if !result {
+                                continue;
+                            }
+                            let command = expand_tokens(arg, target, config);
+                            self.run_exec(&command)
+                        }
+                        other => {
+                            self.warn(format!(
+                                "{}: Match criterion '{}' is not supported by mole; treating the block as not matching",
+                                location, other
+                            ));
+                            false
+                        }
+                    }
+                }
+            };
+
+            if matched == negate {
+                result = false;
+            }
+        }
+
+        (result, wants_final)
+    }
+
+    /// Run a `Match exec` command through /bin/sh; exit status 0 means match.
+    fn run_exec(&mut self, command: &str) -> bool {
+        if self.exec == Exec::Skip {
+            return false;
+        }
+        if let Some(&cached) = self.exec_cache.get(command) {
+            return cached;
+        }
+        let matched = Command::new("/bin/sh")
+            .args(["-c", command])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+        self.exec_cache.insert(command.to_string(), matched);
+        matched
+    }
+
+    fn warn(&mut self, message: String) {
+        if !self.warnings.contains(&message) {
+            self.warnings.push(message);
+        }
+    }
+}
+
+/// Expand the `%` tokens ssh allows in `Match exec` commands.
+fn expand_tokens(value: &str, target: &Target, config: &HostConfig) -> String {
+    let hostname = config
+        .get("hostname")
+        .map(|h| expand_hostname(h, target.original))
+        .unwrap_or_else(|| target.host.to_string());
+    let local_host = whoami::fallible::hostname().unwrap_or_default();
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => out.push_str(&hostname),
+            Some('n') => out.push_str(target.original),
+            Some('p') => out.push_str(config.get("port").unwrap_or("22")),
+            Some('r') => out.push_str(
+                &config
+                    .get("user")
+                    .map(str::to_string)
+                    .unwrap_or_else(whoami::username),
+            ),
+            Some('u') => out.push_str(&whoami::username()),
+            Some('l') => out.push_str(&local_host),
+            Some('L') => out.push_str(local_host.split('.').next().unwrap_or("")),
+            Some('d') => {
+                if let Some(home) = dirs::home_dir() {
+                    out.push_str(&home.to_string_lossy());
+                }
+            }
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+/// Whether `name` matches a comma-separated pattern list (`Match host a,*.b,!c`).
+pub fn match_pattern_list(name: &str, list: &str) -> bool {
+    let patterns: Vec<String> = list.split(',').map(|p| p.trim().to_string()).collect();
+    host_matches(name, &patterns)
+}
+
+/// Split a `# mole:key=value` comment into its key and value.
+pub fn parse_annotation(comment: &str) -> Option<(&str, &str)> {
+    let rest = comment.strip_prefix('#')?.trim_start().strip_prefix("mole:")?;
+    let (key, value) = rest.split_once('=')?;
+    let (key, value) = (key.trim(), value.trim());
+    if key.is_empty() || value.is_empty() {
+        return None;
+    }
+    Some((key, value))
+}
+
+/// Whether the patterns of a `Host` line apply to `host`: at least one
+/// pattern must match and no negated (`!pattern`) one may.
+pub fn host_matches(host: &str, patterns: &[String]) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        if let Some(negated) = pattern.strip_prefix('!') {
+            if match_pattern(host, negated) {
+                return false;
+            }
+        } else if match_pattern(host, pattern) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// Whether an alias is a concrete name rather than a wildcard or negated pattern.
+pub fn is_concrete(pattern: &str) -> bool {
+    !pattern.is_empty() && !pattern.starts_with('!') && !pattern.contains(['*', '?'])
+}
+
+/// Case-insensitive glob match supporting `*` and `?`, as in OpenSSH's match_pattern.
+pub fn match_pattern(s: &str, pattern: &str) -> bool {
+    let s: Vec<char> = s.to_lowercase().chars().collect();
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let (mut si, mut pi) = (0, 0);
+    let mut star: Option<usize> = None;
+    let mut mark = 0;
+
+    while si < s.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == s[si]) {
+            si += 1;
+            pi += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some(pi);
+            mark = si;
+            pi += 1;
+        } else if let Some(sp) = star {
+            pi = sp + 1;
+            mark += 1;
+            si = mark;
+        } else {
+            return false;
+        }
+    }
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+/// Expand the `%h` and `%%` tokens ssh allows in HostName.
+pub fn expand_hostname(value: &str, host: &str) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => out.push_str(host),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(s: &str) -> Vec<String> {
+        split_args(s)
+    }
+
+    #[test]
+    fn match_pattern_wildcards() {
+        assert!(match_pattern("db.prod", "*.prod"));
+        assert!(match_pattern("db.prod", "db.*"));
+        assert!(match_pattern("db1", "db?"));
+        assert!(match_pattern("DB.Prod", "db.prod"));
+        assert!(match_pattern("anything", "*"));
+        assert!(!match_pattern("db.staging", "*.prod"));
+        assert!(!match_pattern("db12", "db?"));
+    }
+
+    #[test]
+    fn host_matches_negation() {
+        assert!(host_matches("db.prod", &patterns("*.prod !bastion.prod")));
+        assert!(!host_matches("bastion.prod", &patterns("*.prod !bastion.prod")));
+        assert!(!host_matches("web", &patterns("!db")));
+        assert!(host_matches("web", &patterns("db web")));
+    }
+
+    #[test]
+    fn expand_hostname_tokens() {
+        assert_eq!(expand_hostname("%h.example.com", "db"), "db.example.com");
+        assert_eq!(expand_hostname("100%%", "db"), "100%");
+    }
+
+    #[test]
+    fn parse_annotation_forms() {
+        assert_eq!(parse_annotation("# mole:group=prod"), Some(("group", "prod")));
+        assert_eq!(parse_annotation("#mole:group = prod "), Some(("group", "prod")));
+        assert_eq!(parse_annotation("# mole:group="), None);
+        assert_eq!(parse_annotation("# Tunnel: db"), None);
+    }
+
+    fn resolve_str(name: &str, content: &str, host: &str) -> (HostConfig, Vec<String>) {
+        resolve_with(name, content, host, Exec::Run)
+    }
+
+    fn resolve_with(name: &str, content: &str, host: &str, exec: Exec) -> (HostConfig, Vec<String>) {
+        let dir = std::env::temp_dir();
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        let set = crate::ssh_config::include::load(&path, &dir).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut resolver = Resolver::new(&set, exec);
+        let config = resolver.resolve(host);
+        (config, resolver.warnings)
+    }
+
+    #[test]
+    fn match_host_and_originalhost() {
+        let content = "\
+Host db
+  HostName db.internal
+
+Match host *.internal
+  User ops
+  LocalForward 5432 localhost:5432
+
+Match originalhost db !host other
+  Port 2222
+
+Match originalhost web
+  Port 3333
+";
+        let (config, warnings) = resolve_str("mole_test_resolve_match_host", content, "db");
+        assert!(warnings.is_empty());
+        assert_eq!(config.get("user"), Some("ops"));
+        assert_eq!(config.get("port"), Some("2222"));
+        assert_eq!(config.list("LocalForward"), ["5432 localhost:5432"]);
+    }
+
+    #[test]
+    fn match_user_localuser_all() {
+        let me = whoami::username();
+        let content = format!(
+            "\
+Host db
+  User deploy
+
+Match user deploy,admin
+  Port 2200
+
+Match localuser {me}
+  IdentityFile ~/.ssh/mine
+
+Match !localuser {me}
+  IdentityFile ~/.ssh/other
+
+Match all
+  LocalForward 8080 localhost:80
+"
+        );
+        let (config, _) = resolve_str("mole_test_resolve_match_user", &content, "db");
+        assert_eq!(config.get("port"), Some("2200"));
+        assert_eq!(config.list("IdentityFile"), ["~/.ssh/mine"]);
+        assert_eq!(config.list("LocalForward"), ["8080 localhost:80"]);
+    }
+
+    #[test]
+    fn match_exec_runs_shell() {
+        let content = "\
+Match exec \"test %n = db\"
+  User fromexec
+
+Match exec false
+  Port 1
+
+Match originalhost nope exec \"exit 1\"
+  Port 2
+";
+        let (config, _) = resolve_str("mole_test_resolve_match_exec", content, "db");
+        assert_eq!(config.get("user"), Some("fromexec"));
+        assert_eq!(config.get("port"), None);
+    }
+
+    #[test]
+    fn match_exec_skipped() {
+        let marker = std::env::temp_dir().join("mole_test_resolve_exec_skipped_ran");
+        let _ = std::fs::remove_file(&marker);
+        let content = format!(
+            "\
+Match exec \"touch {}\"
+  User fromexec
+
+Match !exec true
+  Port 2222
+",
+            marker.display()
+        );
+        let (config, _) = resolve_with("mole_test_resolve_exec_skipped", &content, "db", Exec::Skip);
+        assert!(!marker.exists());
+        assert_eq!(config.get("user"), None);
+        // Not matching, so a negated exec holds
+        assert_eq!(config.get("port"), Some("2222"));
+
+        let (config, _) = resolve_with("mole_test_resolve_exec_skipped", &content, "db", Exec::Run);
+        assert!(marker.exists());
+        assert_eq!(config.get("user"), Some("fromexec"));
+        let _ = std::fs::remove_file(&marker);
+    }
+
+    #[test]
+    fn match_exec_with_equals_keeps_its_block() {
+        let content = "\
+Host other
+  Port 2222
+
+Match exec \"test %n = db\"
+  User fromexec
+";
+        let (other, _) = resolve_str("mole_test_resolve_match_exec_eq_other", content, "other");
+        assert_eq!(other.get("port"), Some("2222"));
+        assert_eq!(other.get("user"), None);
+
+        let (db, _) = resolve_str("mole_test_resolve_match_exec_eq_db", content, "db");
+        assert_eq!(db.get("user"), Some("fromexec"));
+        assert_eq!(db.get("port"), None);
+    }
+
+    #[test]
+    fn match_final_second_pass() {
+        let content = "\
+Host db
+  HostName db.internal
+
+Match final host db.internal
+  LocalForward 6379 localhost:6379
+
+Host db.internal
+  User resolved
+";
+        let (config, _) = resolve_str("mole_test_resolve_match_final", content, "db");
+        assert_eq!(config.list("LocalForward"), ["6379 localhost:6379"]);
+        // On the final pass Host lines match the resolved hostname
+        assert_eq!(config.get("user"), Some("resolved"));
+    }
+
+    #[test]
+    fn match_unsupported_criterion_warns() {
+        let content = "\
+Match localnetwork 10.0.0.0/8
+  User lan
+
+Host db
+  Port 22
+";
+        let (config, warnings) = resolve_str("mole_test_resolve_match_unsupported", content, "db");
+        assert_eq!(config.get("user"), None);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("mole_test_resolve_match_unsupported:1"));
+        assert!(warnings[0].contains("localnetwork"));
+    }
+}
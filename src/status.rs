@@ -0,0 +1,310 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::health;
+use crate::process::{self, Running};
+use crate::tunnel::{Endpoint, TunnelHost};
+
+/// How long `list` and `check` wait for port probes, all tunnels together.
+/// Probes still running after this are reported as timed out.
+pub const DEADLINE: Duration = Duration::from_secs(3);
+/// Probes running at once.
+const MAX_WORKERS: usize = 16;
+
+/// Where tunnel status comes from: [`Live`] asks the process table and the
+/// network; tests substitute a fake.
+pub trait Source: Send + Sync + 'static {
+    /// The running process of every tunnel in `names` that has one.
+    fn running(&self, names: &[&str]) -> HashMap<String, Running>;
+    /// Probe one local listener of a running tunnel.
+    fn probe(&self, endpoint: &Endpoint) -> Result<(), String>;
+}
+
+pub struct Live;
+
+impl Source for Live {
+    fn running(&self, names: &[&str]) -> HashMap<String, Running> {
+        process::running_tunnels(names.iter().copied())
+    }
+
+    fn probe(&self, endpoint: &Endpoint) -> Result<(), String> {
+        health::check_endpoint(endpoint)
+    }
+}
+
+/// Outcome of probing one listener.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Probe {
+    Healthy,
+    Failed(String),
+    /// No answer before the deadline.
+    TimedOut,
+}
+
+/// The probes of one tunnel, in `local_listeners()` order. Empty for tunnels
+/// that aren't running or have nothing to probe locally.
+#[derive(Debug)]
+pub struct Report {
+    /// Index into the tunnels passed to [`collect`].
+    pub tunnel: usize,
+    pub probes: Vec<Probe>,
+}
+
+/// Status of a set of tunnels. Process state is known up front (one process
+/// table scan); port probes run in the background and arrive through `reports`.
+pub struct Status {
+    /// Running process per tunnel, in the order the tunnels were given.
+    pub running: Vec<Option<Running>>,
+    pub reports: Reports,
+}
+
+/// Reports in the order tunnels finish: ones with nothing to probe first, then
+/// each tunnel as its last probe completes. Ends by the deadline at the latest.
+pub struct Reports {
+    rx: Receiver<(usize, usize, Probe)>,
+    deadline: Instant,
+    /// Tunnels that are already complete, waiting to be handed out.
+    ready: VecDeque<usize>,
+    /// Per unfinished tunnel, one slot per listener (None while in flight).
+    pending: BTreeMap<usize, Vec<Option<Probe>>>,
+    done: HashMap<usize, Vec<Probe>>,
+}
+
+impl Iterator for Reports {
+    type Item = Report;
+
+    fn next(&mut self) -> Option<Report> {
+        loop {
+            if let Some(tunnel) = self.ready.pop_front() {
+                let probes = self.done.remove(&tunnel).unwrap_or_default();
+                return Some(Report { tunnel, probes });
+            }
+            let (&tunnel, _) = self.pending.first_key_value()?;
+            let wait = self.deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(wait) {
+                Ok((tunnel, listener, probe)) => {
+                    let Some(slots) = self.pending.get_mut(&tunnel) else {
+                        continue;
+                    };
+                    slots[listener] = Some(probe);
+                    if slots.iter().all(Option::is_some) {
+                        self.finish(tunnel);
+                    }
+                }
+                // Out of time (or the workers are gone): whatever is still
+                // in flight has timed out, one tunnel at a time
+                Err(_) => self.finish(tunnel),
+            }
+        }
+    }
+}
+
+impl Reports {
+    fn finish(&mut self, tunnel: usize) {
+        if let Some(slots) = self.pending.remove(&tunnel) {
+            let probes = slots.into_iter().map(|p| p.unwrap_or(Probe::TimedOut)).collect();
+            self.done.insert(tunnel, probes);
+            self.ready.push_back(tunnel);
+        }
+    }
+}
+
+/// Look up which tunnels are running and start probing their local listeners
+/// concurrently. Returns as soon as process state is known; iterate
+/// `reports` to get probe results as they come in, within `deadline`.
+pub fn collect<S: Source>(tunnels: &[TunnelHost], source: Arc<S>, deadline: Duration) -> Status {
+    let deadline = Instant::now() + deadline;
+    let names: Vec<&str> = tunnels.iter().map(|t| t.name.as_str()).collect();
+    let mut running_by_name = source.running(&names);
+    let running: Vec<Option<Running>> =
+        tunnels.iter().map(|t| running_by_name.remove(&t.name)).collect();
+
+    let mut jobs = VecDeque::new();
+    let mut ready = VecDeque::new();
+    let mut pending = BTreeMap::new();
+    for (i, tunnel) in tunnels.iter().enumerate() {
+        let listeners: Vec<&Endpoint> = tunnel.local_listeners().collect();
+        if running[i].is_none() || listeners.is_empty() {
+            ready.push_back(i);
+            continue;
+        }
+        pending.insert(i, vec![None; listeners.len()]);
+        jobs.extend(listeners.into_iter().enumerate().map(|(j, e)| (i, j, e.clone())));
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let workers = jobs.len().min(MAX_WORKERS);
+    let jobs = Arc::new(Mutex::new(jobs));
+    for _ in 0..workers {
+        let (jobs, tx, source) = (jobs.clone(), tx.clone(), source.clone());
+        // Detached: a probe stuck past the deadline must not hold up the command
+        std::thread::spawn(move || {
+            loop {
+                let Some((tunnel, listener, endpoint)) = jobs.lock().unwrap().pop_front() else {
+                    break;
+                };
+                let probe = match source.probe(&endpoint) {
+                    Ok(()) => Probe::Healthy,
+                    Err(failure) => Probe::Failed(failure),
+                };
+                if tx.send((tunnel, listener, probe)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    Status {
+        running,
+        reports: Reports {
+            rx,
+            deadline,
+            ready,
+            pending,
+            done: HashMap::new(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proctable::{ProcessInfo, ProcessTable};
+    use crate::tunnel::PortForward;
+
+    /// A process table made up in memory, and probes that take a set time per port.
+    struct Fake {
+        table: ProcessTable,
+        /// Probe delay per port; ports not listed fail immediately.
+        delays: HashMap<u16, Duration>,
+    }
+
+    impl Source for Fake {
+        fn running(&self, names: &[&str]) -> HashMap<String, Running> {
+            self.table
+                .iter()
+                .filter_map(|p| {
+                    let name = process::tunnel_of(&p.argv)?;
+                    names.contains(&name).then(|| {
+                        (name.to_string(), Running::new(p.pid, p.start_time, &self.table))
+                    })
+                })
+                .collect()
+        }
+
+        fn probe(&self, endpoint: &Endpoint) -> Result<(), String> {
+            match endpoint.port().and_then(|port| self.delays.get(&port)) {
+                Some(delay) => {
+                    std::thread::sleep(*delay);
+                    Ok(())
+                }
+                None => Err(format!("{}: connection refused", endpoint)),
+            }
+        }
+    }
+
+    fn process(pid: u32, ppid: u32, cmd: &str, rss_kb: u64) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            ppid,
+            argv: cmd.split_whitespace().map(String::from).collect(),
+            start_time: Some(1_700_000_000),
+            rss_kb,
+            cpu_secs: 1,
+        }
+    }
+
+    fn tunnel(name: &str, ports: &[u16]) -> TunnelHost {
+        let forward = |port: u16| PortForward {
+            listen: Endpoint::Tcp { host: None, port },
+            target: Endpoint::Tcp {
+                host: Some("localhost".to_string()),
+                port,
+            },
+        };
+        TunnelHost {
+            forwards: ports.iter().map(|&p| forward(p)).collect(),
+            ..TunnelHost::named(name)
+        }
+    }
+
+    /// Every tunnel in `names` running under autossh, pids 100, 200, ...
+    fn fake(names: &[&str], delays: &[(u16, u64)]) -> Arc<Fake> {
+        let table = ProcessTable::from_procs(names.iter().enumerate().flat_map(|(i, name)| {
+            let pid = 100 * (i as u32 + 1);
+            [
+                process(pid, 1, &format!("autossh -N {}", name), 1024),
+                process(pid + 1, pid, &format!("ssh -N {}", name), 4096),
+            ]
+        }));
+        Arc::new(Fake {
+            table,
+            delays: delays
+                .iter()
+                .map(|&(port, ms)| (port, Duration::from_millis(ms)))
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn process_state_comes_from_the_table() {
+        let tunnels = [tunnel("db", &[5432]), tunnel("cache", &[6379])];
+        let status = collect(&tunnels, fake(&["db"], &[(5432, 0)]), DEADLINE);
+
+        let db = status.running[0].as_ref().unwrap();
+        assert_eq!(db.pid, 100);
+        assert_eq!(db.started, Some(1_700_000_000));
+        assert_eq!((db.rss_kb, db.cpu_secs), (5120, 2)); // autossh + its ssh
+        assert!(status.running[1].is_none());
+
+        // The stopped tunnel is reported at once, without probes
+        let reports: Vec<Report> = status.reports.collect();
+        assert_eq!(reports[0].tunnel, 1);
+        assert!(reports[0].probes.is_empty());
+        assert_eq!(reports[1].tunnel, 0);
+        assert_eq!(reports[1].probes, [Probe::Healthy]);
+    }
+
+    #[test]
+    fn probes_run_concurrently() {
+        let names = ["a", "b", "c", "d", "e", "f"];
+        let tunnels: Vec<TunnelHost> =
+            names.iter().zip(1u16..).map(|(n, port)| tunnel(n, &[port])).collect();
+        let delays: Vec<(u16, u64)> = (1..=6).map(|port| (port, 300)).collect();
+
+        let start = Instant::now();
+        let status = collect(&tunnels, fake(&names, &delays), DEADLINE);
+        let reports: Vec<Report> = status.reports.collect();
+
+        assert_eq!(reports.len(), 6);
+        assert!(reports.iter().all(|r| r.probes == [Probe::Healthy]));
+        // One at a time this would take 1.8s
+        assert!(start.elapsed() < Duration::from_millis(1200), "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn reports_arrive_as_tunnels_finish() {
+        let tunnels = [tunnel("slow", &[1, 2]), tunnel("fast", &[3])];
+        let status = collect(&tunnels, fake(&["slow", "fast"], &[(1, 400), (2, 0), (3, 0)]), DEADLINE);
+        let order: Vec<usize> = status.reports.map(|r| r.tunnel).collect();
+        assert_eq!(order, [1, 0]);
+    }
+
+    #[test]
+    fn deadline_times_out_stuck_probes() {
+        let tunnels = [tunnel("stuck", &[1, 2]), tunnel("ok", &[3])];
+        let source = fake(&["stuck", "ok"], &[(1, 10_000), (3, 0)]);
+
+        let start = Instant::now();
+        let status = collect(&tunnels, source, Duration::from_millis(300));
+        let mut reports: Vec<Report> = status.reports.collect();
+        assert!(start.elapsed() < Duration::from_secs(2), "{:?}", start.elapsed());
+
+        reports.sort_by_key(|r| r.tunnel);
+        assert_eq!(reports[0].probes[0], Probe::TimedOut);
+        assert_eq!(reports[0].probes[1], Probe::Failed("2: connection refused".to_string()));
+        assert_eq!(reports[1].probes, [Probe::Healthy]);
+    }
+}
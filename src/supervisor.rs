@@ -0,0 +1,308 @@
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// First restart delay; doubles after every quick exit.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// An ssh that ran this long was working; the next failure starts backoff over.
+const STABLE_AFTER: Duration = Duration::from_secs(30);
+/// Consecutive quick exits that count as a crash loop...
+const CRASH_LOOP_LIMIT: u32 = 5;
+/// ...after which the supervisor waits this long before trying again.
+const COOL_DOWN: Duration = Duration::from_secs(300);
+/// Give up entirely after this many restarts within RESTART_WINDOW.
+const MAX_RESTARTS_PER_WINDOW: usize = 40;
+const RESTART_WINDOW: Duration = Duration::from_secs(3600);
+/// How long ssh gets to exit after SIGTERM before it is killed.
+const STOP_GRACE: Duration = Duration::from_secs(5);
+/// Exit status after giving up. Service units and plists are written not to
+/// restart on it, since a fresh supervisor would forget why it gave up.
+pub const GIVE_UP_STATUS: i32 = 3;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_stop(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Restart(Duration),
+    /// Crash loop detected: wait out the cool-down before the next attempt.
+    CoolDown(Duration),
+    GiveUp(String),
+}
+
+/// When and whether to restart ssh after it exits.
+pub struct RestartPolicy {
+    backoff: Duration,
+    /// Consecutive runs shorter than STABLE_AFTER.
+    quick_exits: u32,
+    /// Restart times within the current window, oldest first.
+    restarts: VecDeque<Instant>,
+    first_run: bool,
+    /// The startup gate: if the very first ssh exits sooner, the tunnel is
+    /// misconfigured and retrying won't help. Zero disables it.
+    gate: Duration,
+    rng: u64,
+}
+
+impl RestartPolicy {
+    pub fn new(seed: u64, gate: Duration) -> Self {
+        Self {
+            backoff: INITIAL_BACKOFF,
+            quick_exits: 0,
+            restarts: VecDeque::new(),
+            first_run: true,
+            gate,
+            rng: seed | 1,
+        }
+    }
+
+    /// Decide what to do after ssh exited at `now` having run for `ran_for`.
+    pub fn on_exit(&mut self, now: Instant, ran_for: Duration) -> Decision {
+        let first_run = std::mem::replace(&mut self.first_run, false);
+        if ran_for >= STABLE_AFTER {
+            self.backoff = INITIAL_BACKOFF;
+            self.quick_exits = 0;
+        } else if first_run && ran_for < self.gate {
+            return Decision::GiveUp(format!(
+                "ssh exited within {}s of starting; not retrying (check `mole logs`)",
+                ran_for.as_secs()
+            ));
+        } else {
+            self.quick_exits += 1;
+        }
+
+        while self
+            .restarts
+            .front()
+            .is_some_and(|&t| now.duration_since(t) >= RESTART_WINDOW)
+        {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= MAX_RESTARTS_PER_WINDOW {
+            return Decision::GiveUp(format!(
+                "restarted {} times in the last {} minutes; giving up",
+                self.restarts.len(),
+                RESTART_WINDOW.as_secs() / 60
+            ));
+        }
+        self.restarts.push_back(now);
+
+        if self.quick_exits >= CRASH_LOOP_LIMIT {
+            self.quick_exits = 0;
+            return Decision::CoolDown(COOL_DOWN);
+        }
+
+        let delay = self.jitter(self.backoff);
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        Decision::Restart(delay)
+    }
+
+    /// Spread `d` by ±20% so tunnels that dropped together don't reconnect in lockstep.
+    fn jitter(&mut self, d: Duration) -> Duration {
+        // xorshift64: plenty for spreading retries
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let factor = 0.8 + (self.rng % 401) as f64 / 1000.0;
+        d.mul_f64(factor)
+    }
+}
+
+fn log(name: &str, message: &str) {
+    eprintln!("mole-supervise[{}] {}: {}", std::process::id(), name, message);
+}
+
+/// Run `ssh <ssh_args>` for tunnel `name` until told to stop, restarting it
+/// according to [`RestartPolicy`]. ssh's stderr goes wherever ours does (the
+/// tunnel log). SIGTERM, SIGINT or SIGHUP stop ssh and then the supervisor;
+/// giving up exits with [`GIVE_UP_STATUS`].
+pub fn run(name: &str, gate: Duration, ssh_args: &[String]) -> Result<()> {
+    unsafe {
+        for sig in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+            libc::signal(sig, handle_stop as *const () as libc::sighandler_t);
+        }
+    }
+
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+        ^ u64::from(std::process::id());
+    let mut policy = RestartPolicy::new(seed, gate);
+
+    loop {
+        let started = Instant::now();
+        let mut child = Command::new("ssh")
+            .args(ssh_args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .context("failed to run ssh — is the OpenSSH client installed?")?;
+        log(name, &format!("started ssh (pid {})", child.id()));
+
+        let status = loop {
+            if STOP.load(Ordering::SeqCst) {
+                terminate(&mut child);
+                log(name, "stopped");
+                return Ok(());
+            }
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        };
+
+        let ran_for = started.elapsed();
+        let delay = match policy.on_exit(Instant::now(), ran_for) {
+            Decision::Restart(delay) => {
+                log(
+                    name,
+                    &format!(
+                        "ssh exited ({}) after {}s; restarting in {:.1}s",
+                        status,
+                        ran_for.as_secs(),
+                        delay.as_secs_f64()
+                    ),
+                );
+                delay
+            }
+            Decision::CoolDown(delay) => {
+                log(
+                    name,
+                    &format!(
+                        "ssh keeps exiting ({}); crash loop detected, retrying in {}s",
+                        status,
+                        delay.as_secs()
+                    ),
+                );
+                delay
+            }
+            Decision::GiveUp(reason) => {
+                log(name, &reason);
+                std::process::exit(GIVE_UP_STATUS);
+            }
+        };
+
+        let until = Instant::now() + delay;
+        while Instant::now() < until {
+            if STOP.load(Ordering::SeqCst) {
+                log(name, "stopped");
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+/// SIGTERM ssh, then SIGKILL it if it hasn't exited within STOP_GRACE.
+fn terminate(child: &mut Child) {
+    unsafe {
+        libc::kill(child.id() as i32, libc::SIGTERM);
+    }
+    let deadline = Instant::now() + STOP_GRACE;
+    while Instant::now() < deadline {
+        if let Ok(Some(_)) = child.try_wait() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GATE: Duration = Duration::from_secs(30);
+
+    fn quick() -> Duration {
+        Duration::from_secs(2)
+    }
+
+    fn delay(d: Decision) -> Duration {
+        match d {
+            Decision::Restart(d) => d,
+            other => panic!("expected restart, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn first_run_failure_gives_up() {
+        let mut policy = RestartPolicy::new(1, GATE);
+        assert!(matches!(policy.on_exit(Instant::now(), quick()), Decision::GiveUp(_)));
+    }
+
+    #[test]
+    fn gate_is_configurable() {
+        // Past a shorter gate, a quick first exit is retried like any other
+        let mut policy = RestartPolicy::new(1, Duration::from_secs(1));
+        assert!(matches!(policy.on_exit(Instant::now(), quick()), Decision::Restart(_)));
+        assert_eq!(policy.quick_exits, 1);
+
+        let mut never = RestartPolicy::new(1, Duration::ZERO);
+        assert!(matches!(never.on_exit(Instant::now(), Duration::ZERO), Decision::Restart(_)));
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_and_resets_when_stable() {
+        let mut policy = RestartPolicy::new(42, GATE);
+        let now = Instant::now();
+        let first = delay(policy.on_exit(now, STABLE_AFTER));
+        assert!(first >= Duration::from_millis(800) && first <= Duration::from_millis(1200));
+        let second = delay(policy.on_exit(now, quick()));
+        assert!(second >= Duration::from_millis(1600) && second <= Duration::from_millis(2400));
+        let third = delay(policy.on_exit(now, quick()));
+        assert!(third >= Duration::from_millis(3200) && third <= Duration::from_millis(4800));
+
+        let reset = delay(policy.on_exit(now, STABLE_AFTER * 2));
+        assert!(reset <= Duration::from_millis(1200));
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let mut policy = RestartPolicy::new(7, GATE);
+        let now = Instant::now();
+        policy.on_exit(now, STABLE_AFTER);
+        for _ in 0..3 {
+            policy.on_exit(now, quick());
+        }
+        policy.backoff = MAX_BACKOFF;
+        policy.quick_exits = 0;
+        let d = delay(policy.on_exit(now, quick()));
+        assert!(d <= MAX_BACKOFF.mul_f64(1.2));
+        assert_eq!(policy.backoff, MAX_BACKOFF);
+    }
+
+    #[test]
+    fn crash_loop_cools_down() {
+        let mut policy = RestartPolicy::new(3, GATE);
+        let now = Instant::now();
+        policy.on_exit(now, STABLE_AFTER);
+        for _ in 1..CRASH_LOOP_LIMIT {
+            assert!(matches!(policy.on_exit(now, quick()), Decision::Restart(_)));
+        }
+        assert_eq!(policy.on_exit(now, quick()), Decision::CoolDown(COOL_DOWN));
+        assert!(matches!(policy.on_exit(now, quick()), Decision::Restart(_)));
+    }
+
+    #[test]
+    fn restarts_are_capped_per_window() {
+        let mut policy = RestartPolicy::new(9, GATE);
+        let start = Instant::now();
+        for _ in 0..MAX_RESTARTS_PER_WINDOW {
+            assert!(!matches!(policy.on_exit(start, STABLE_AFTER), Decision::GiveUp(_)));
+        }
+        assert!(matches!(policy.on_exit(start, STABLE_AFTER), Decision::GiveUp(_)));
+
+        // Once the window has passed, restarts are allowed again
+        let later = start + RESTART_WINDOW;
+        assert!(matches!(policy.on_exit(later, STABLE_AFTER), Decision::Restart(_)));
+    }
+}
@@ -0,0 +1,363 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{Backend, Config};
+use crate::persist::AutoStart;
+use crate::process::{self, LaunchSpec};
+use crate::ssh_config::ConfigRoot;
+use crate::supervisor;
+use crate::tunnel::TunnelHost;
+
+/// User units in `$XDG_CONFIG_HOME/systemd/user`, for machines running a
+/// systemd user session.
+pub struct Systemd;
+
+impl AutoStart for Systemd {
+    fn name(&self) -> &'static str {
+        "systemd"
+    }
+
+    fn is_available(&self) -> bool {
+        Path::new("/run/systemd/system").exists() && process::which("systemctl").is_ok()
+    }
+
+    fn is_enabled(&self, name: &str) -> bool {
+        is_enabled(name)
+    }
+
+    fn enable(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<()> {
+        enable(tunnel, root, cfg)
+    }
+
+    fn disable(&self, name: &str) -> Result<()> {
+        disable(name)
+    }
+
+    fn render(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<String> {
+        unit_for(tunnel, root, cfg)
+    }
+
+    fn installed(&self, name: &str) -> Option<String> {
+        fs::read_to_string(unit_path(name).ok()?).ok()
+    }
+
+    fn supervises(&self) -> bool {
+        true
+    }
+
+    fn main_pid(&self, name: &str) -> Option<u32> {
+        let output = Command::new("systemctl")
+            .args(["--user", "show", "--property=MainPID", "--value", &unit_name(name)])
+            .output()
+            .ok()?;
+        parse_main_pid(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    fn main_pids(&self, names: &[&str]) -> HashMap<String, u32> {
+        let units: Vec<String> = names.iter().map(|name| unit_name(name)).collect();
+        let Ok(output) = Command::new("systemctl")
+            .args(["--user", "show", "--property=Id,MainPID"])
+            .args(&units)
+            .output()
+        else {
+            return HashMap::new();
+        };
+        parse_main_pids(&String::from_utf8_lossy(&output.stdout))
+            .into_iter()
+            .filter_map(|(unit, pid)| {
+                let i = units.iter().position(|u| *u == unit)?;
+                Some((names[i].to_string(), pid))
+            })
+            .collect()
+    }
+
+    fn start(&self, name: &str) -> Result<()> {
+        systemctl(&["start", &unit_name(name)])
+    }
+
+    fn stop(&self, name: &str) -> Result<()> {
+        systemctl(&["stop", &unit_name(name)])
+    }
+}
+
+/// `$XDG_CONFIG_HOME/systemd/user`, where systemd looks for user units.
+fn unit_dir() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .context("cannot determine config directory")?
+        .join("systemd")
+        .join("user"))
+}
+
+/// Unit name for a tunnel. Characters systemd doesn't allow in unit names
+/// are escaped the way `systemd-escape` does.
+fn unit_name(name: &str) -> String {
+    let mut escaped = String::new();
+    for (i, b) in name.bytes().enumerate() {
+        let plain = b.is_ascii_alphanumeric() || b"_:-".contains(&b) || (b == b'.' && i > 0);
+        if plain {
+            escaped.push(b as char);
+        } else {
+            escaped.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    format!("mole-{}.service", escaped)
+}
+
+/// Path to the systemd user unit for a tunnel.
+fn unit_path(name: &str) -> Result<PathBuf> {
+    Ok(unit_dir()?.join(unit_name(name)))
+}
+
+/// Check if a tunnel's unit is installed and enabled.
+fn is_enabled(name: &str) -> bool {
+    unit_dir().is_ok_and(|dir| is_enabled_in(&dir, name))
+}
+
+/// `systemctl --user enable` links the unit into `default.target.wants`;
+/// checking the link spares `list` a systemctl call per tunnel.
+fn is_enabled_in(dir: &Path, name: &str) -> bool {
+    let unit = unit_name(name);
+    dir.join(&unit).exists() && dir.join("default.target.wants").join(&unit).exists()
+}
+
+/// Write a user unit for a tunnel and enable it, so it starts at login. The
+/// tunnel isn't started now; it's either already running or `mole up` starts it.
+fn enable(tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<()> {
+    let dir = unit_dir()?;
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(unit_name(&tunnel.name));
+    fs::write(&path, unit_for(tunnel, root, cfg)?)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    systemctl(&["daemon-reload"])?;
+    systemctl(&["enable", &unit_name(&tunnel.name)])
+}
+
+/// Disable and stop a tunnel's unit, then remove it.
+fn disable(name: &str) -> Result<()> {
+    let path = unit_path(name)?;
+    if !path.exists() {
+        anyhow::bail!("tunnel '{}' is not enabled for auto-start", name);
+    }
+
+    systemctl(&["disable", "--now", &unit_name(name)])?;
+    fs::remove_file(&path)
+        .with_context(|| format!("failed to remove {}", path.display()))?;
+    systemctl(&["daemon-reload"])
+}
+
+fn systemctl(args: &[&str]) -> Result<()> {
+    let output = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .output()
+        .with_context(|| format!("failed to run systemctl --user {}", args.join(" ")))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "systemctl --user {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// The unit running a tunnel.
+fn unit_for(tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<String> {
+    let log_path = process::log_file(&tunnel.name)?;
+    let spec = process::launch_spec(tunnel, root, cfg)?;
+    let program = process::which(&spec.program)?;
+    Ok(unit(&tunnel.name, &program, &spec, &log_path, cfg.stop_timeout))
+}
+
+/// `systemctl show --value` prints 0 for a unit with no running process.
+fn parse_main_pid(output: &str) -> Option<u32> {
+    output.trim().parse().ok().filter(|&pid| pid != 0)
+}
+
+/// `systemctl show --property=Id,MainPID` prints a block per unit, separated
+/// by blank lines. Units without a running process are left out.
+fn parse_main_pids(output: &str) -> Vec<(String, u32)> {
+    output
+        .split("\n\n")
+        .filter_map(|block| {
+            let mut id = None;
+            let mut pid = None;
+            for line in block.lines() {
+                if let Some(value) = line.strip_prefix("Id=") {
+                    id = Some(value.to_string());
+                } else if let Some(value) = line.strip_prefix("MainPID=") {
+                    pid = parse_main_pid(value);
+                }
+            }
+            Some((id?, pid?))
+        })
+        .collect()
+}
+
+/// The unit file running `program` with the spec's arguments and environment.
+fn unit(name: &str, program: &str, spec: &LaunchSpec, log_path: &Path, stop_timeout: u64) -> String {
+    let exec: Vec<String> = std::iter::once(program)
+        .chain(spec.args.iter().map(String::as_str))
+        .map(exec_arg)
+        .collect();
+    let env: String = spec
+        .env
+        .iter()
+        .map(|(k, v)| format!("Environment=\"{}\"\n", escape(&format!("{}={}", k, v))))
+        .collect();
+    // A restarted supervisor would forget why it gave up
+    let prevent = match spec.backend {
+        Backend::Ssh => format!("RestartPreventExitStatus={}\n", supervisor::GIVE_UP_STATUS),
+        Backend::Autossh => String::new(),
+    };
+
+    format!(
+        "[Unit]
+Description=mole tunnel {name}
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=simple
+{env}ExecStart={exec}
+Restart=always
+RestartSec=5
+{prevent}TimeoutStopSec={stop_timeout}
+StandardOutput=null
+StandardError=append:{log}
+
+[Install]
+WantedBy=default.target
+",
+        name = name,
+        env = env,
+        exec = exec.join(" "),
+        stop_timeout = stop_timeout,
+        prevent = prevent,
+        log = log_path.display().to_string().replace('%', "%%"),
+    )
+}
+
+/// Escape text for a double-quoted unit file value: backslashes, quotes and
+/// `%` specifiers.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('%', "%%")
+}
+
+/// One `ExecStart=` word, quoted when needed. `$` is escaped too, since
+/// systemd expands variables on command lines.
+fn exec_arg(arg: &str) -> String {
+    let escaped = escape(arg).replace('$', "$$");
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@+%$".contains(c));
+    if plain {
+        escaped
+    } else {
+        format!("\"{}\"", escaped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_runs_the_launch_spec() {
+        let spec = LaunchSpec {
+            backend: Backend::Autossh,
+            program: "autossh".to_string(),
+            args: vec![
+                "-N".to_string(),
+                "-o".to_string(),
+                "ProxyCommand=nc -x localhost:1080 %h %p".to_string(),
+                "db".to_string(),
+            ],
+            env: vec![
+                ("AUTOSSH_PORT".to_string(), "0".to_string()),
+                ("AUTOSSH_GATETIME".to_string(), "0".to_string()),
+            ],
+        };
+        let unit = unit("db", "/usr/bin/autossh", &spec, Path::new("/home/me/.mole/logs/db.log"), 5);
+
+        assert!(unit.contains("Description=mole tunnel db\n"));
+        assert!(unit.contains(
+            "ExecStart=/usr/bin/autossh -N -o \"ProxyCommand=nc -x localhost:1080 %%h %%p\" db\n"
+        ));
+        assert!(unit.contains("Environment=\"AUTOSSH_PORT=0\"\nEnvironment=\"AUTOSSH_GATETIME=0\"\n"));
+        assert!(unit.contains("StandardError=append:/home/me/.mole/logs/db.log\n"));
+        assert!(unit.contains("Restart=always\n"));
+        assert!(unit.contains("TimeoutStopSec=5\n"));
+        assert!(unit.ends_with("[Install]\nWantedBy=default.target\n"));
+        assert!(!unit.contains("RestartPreventExitStatus"));
+    }
+
+    #[test]
+    fn supervisor_unit_respects_give_up() {
+        let spec = LaunchSpec {
+            backend: Backend::Ssh,
+            program: "/usr/bin/mole".to_string(),
+            args: vec!["supervise".to_string(), "db".to_string()],
+            env: Vec::new(),
+        };
+        let unit = unit("db", "/usr/bin/mole", &spec, Path::new("/tmp/db.log"), 5);
+        assert!(unit.contains("Restart=always\nRestartSec=5\nRestartPreventExitStatus=3\n"));
+    }
+
+    #[test]
+    fn quotes_exec_args() {
+        assert_eq!(exec_arg("-N"), "-N");
+        assert_eq!(exec_arg("ServerAliveInterval=30"), "ServerAliveInterval=30");
+        assert_eq!(exec_arg(""), "\"\"");
+        assert_eq!(exec_arg("a b"), "\"a b\"");
+        assert_eq!(exec_arg("$HOME"), "$$HOME");
+        assert_eq!(exec_arg("say \"hi\""), "\"say \\\"hi\\\"\"");
+    }
+
+    #[test]
+    fn parses_main_pid() {
+        assert_eq!(parse_main_pid("1234\n"), Some(1234));
+        assert_eq!(parse_main_pid("0\n"), None);
+        assert_eq!(parse_main_pid(""), None);
+    }
+
+    #[test]
+    fn parses_main_pids() {
+        let output = "MainPID=1234\nId=mole-db.service\n\nId=mole-web.service\nMainPID=0\n\nId=mole-api.service\nMainPID=77\n";
+        assert_eq!(
+            parse_main_pids(output),
+            [("mole-db.service".to_string(), 1234), ("mole-api.service".to_string(), 77)]
+        );
+    }
+
+    #[test]
+    fn escapes_unit_names() {
+        assert_eq!(unit_name("db-prod.internal"), "mole-db-prod.internal.service");
+        assert_eq!(unit_name("web/1"), "mole-web\\x2f1.service");
+    }
+
+    #[test]
+    fn enabled_means_installed_and_wanted() {
+        let dir = std::env::temp_dir().join("mole_test_systemd_enabled");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("default.target.wants")).unwrap();
+        assert!(!is_enabled_in(&dir, "db"));
+
+        fs::write(dir.join("mole-db.service"), "").unwrap();
+        assert!(!is_enabled_in(&dir, "db"));
+
+        std::os::unix::fs::symlink(
+            dir.join("mole-db.service"),
+            dir.join("default.target.wants").join("mole-db.service"),
+        )
+        .unwrap();
+        assert!(is_enabled_in(&dir, "db"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
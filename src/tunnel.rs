@@ -1,40 +1,79 @@
-/// A single port forward: local_port -> remote_host:remote_port
+use crate::config::Backend;
+
+/// One end of a forward: a TCP address or a Unix domain socket path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    /// `host` is the bind address or target host, without IPv6 brackets. It is
+    /// None when only a port was given, and "*" for the all-interfaces bind.
+    Tcp { host: Option<String>, port: u16 },
+    Unix(String),
+}
+
+impl Endpoint {
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Endpoint::Tcp { port, .. } => Some(*port),
+            Endpoint::Unix(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Tcp { host: None, port } => write!(f, "{}", port),
+            Endpoint::Tcp {
+                host: Some(host),
+                port,
+            } if host.contains(':') => write!(f, "[{}]:{}", host, port),
+            Endpoint::Tcp {
+                host: Some(host),
+                port,
+            } => write!(f, "{}:{}", host, port),
+            Endpoint::Unix(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+/// A single local forward: local `listen` -> `target` reached through the remote host
 #[derive(Debug, Clone)]
 pub struct PortForward {
-    pub local_port: u16,
-    pub remote_host: String,
-    pub remote_port: u16,
+    pub listen: Endpoint,
+    pub target: Endpoint,
 }
 
 impl std::fmt::Display for PortForward {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}:{}", self.local_port, self.remote_host, self.remote_port)
+        write!(f, "{}:{}", self.listen, self.target)
     }
 }
 
-/// A single remote (reverse) forward: remote bind_port -> local remote_host:remote_port
+/// A single remote (reverse) forward: remote `listen` -> local `target`.
+/// Without a target the remote side gets a SOCKS proxy (ssh's dynamic RemoteForward).
 #[derive(Debug, Clone)]
 pub struct RemotePortForward {
-    pub bind_port: u16,
-    pub remote_host: String,
-    pub remote_port: u16,
+    pub listen: Endpoint,
+    pub target: Option<Endpoint>,
 }
 
 impl std::fmt::Display for RemotePortForward {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "R:{}→{}:{}", self.bind_port, self.remote_host, self.remote_port)
+        match &self.target {
+            Some(target) => write!(f, "R:{}→{}", self.listen, target),
+            None => write!(f, "R:{}→SOCKS", self.listen),
+        }
     }
 }
 
-/// A dynamic (SOCKS proxy) forward: ssh -D listen_port
+/// A dynamic (SOCKS proxy) forward: ssh -D [bind_address:]port
 #[derive(Debug, Clone)]
 pub struct DynamicForward {
-    pub listen_port: u16,
+    pub listen: Endpoint,
 }
 
 impl std::fmt::Display for DynamicForward {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "D:{}", self.listen_port)
+        write!(f, "D:{}", self.listen)
     }
 }
 
@@ -42,10 +81,71 @@ impl std::fmt::Display for DynamicForward {
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct TunnelHost {
+    /// Primary name: the first concrete pattern of the Host line.
     pub name: String,
+    /// Every concrete pattern of the Host line, primary name first.
+    pub aliases: Vec<String>,
     pub hostname: Option<String>,
+    /// Effective User, Port, ProxyJump and IdentityFiles after merging every matching block.
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub proxy_jump: Option<String>,
+    pub identity_files: Vec<String>,
     pub forwards: Vec<PortForward>,
     pub remote_forwards: Vec<RemotePortForward>,
     pub dynamic_forwards: Vec<DynamicForward>,
     pub group: Option<String>,
+    /// Per-tunnel `# mole:backend=` override of the configured backend.
+    pub backend: Option<Backend>,
+    /// Tunnels that must be up before this one starts (`# mole:depends=a,b`).
+    pub depends: Vec<String>,
+    /// Extra ssh arguments from `# mole:ssh-option=` annotations, split into words.
+    pub ssh_options: Vec<String>,
+}
+
+impl TunnelHost {
+    /// A tunnel with only a name, for tests to fill in with struct-update syntax.
+    #[cfg(test)]
+    pub fn named(name: &str) -> Self {
+        TunnelHost {
+            name: name.to_string(),
+            aliases: vec![name.to_string()],
+            hostname: None,
+            user: None,
+            port: None,
+            proxy_jump: None,
+            identity_files: Vec::new(),
+            forwards: Vec::new(),
+            remote_forwards: Vec::new(),
+            dynamic_forwards: Vec::new(),
+            group: None,
+            backend: None,
+            depends: Vec::new(),
+            ssh_options: Vec::new(),
+        }
+    }
+
+    /// Whether `name` is the primary name or one of the aliases of this tunnel.
+    pub fn has_name(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|a| a == name)
+    }
+
+    /// Aliases other than the primary name.
+    pub fn other_aliases(&self) -> impl Iterator<Item = &String> {
+        self.aliases.iter().filter(move |a| **a != self.name)
+    }
+
+    /// The backend that runs this tunnel: its own override, else the configured default.
+    pub fn backend(&self, default: Backend) -> Backend {
+        self.backend.unwrap_or(default)
+    }
+
+    /// Local endpoints that ssh listens on (LocalForward and DynamicForward),
+    /// the ones that can conflict with other processes and be health-checked.
+    pub fn local_listeners(&self) -> impl Iterator<Item = &Endpoint> {
+        self.forwards
+            .iter()
+            .map(|f| &f.listen)
+            .chain(self.dynamic_forwards.iter().map(|f| &f.listen))
+    }
 }
@@ -2,12 +2,12 @@ use anyhow::{Context, Result};
 use colored::Colorize;
 use dialoguer::Input;
 use std::collections::BTreeSet;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
+use std::fs;
 use std::os::unix::io::AsRawFd;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::ssh_config;
+use crate::tunnel::Endpoint;
 
 // ─── SIGWINCH flag ──────────────────────────────────────────
 
@@ -112,7 +112,7 @@ fn read_key(fd: i32) -> std::io::Result<Key> {
                     Some(b) if b.is_ascii_digit() => {
                         // CSI sequences like \x1b[3~ — read until final byte
                         let mut last = b;
-                        while last < 0x40 || last > 0x7e {
+                        while !(0x40..=0x7e).contains(&last) {
                             match read_byte_timeout(fd, 50) {
                                 Some(next) => last = next,
                                 None => break,
@@ -133,7 +133,7 @@ fn read_key(fd: i32) -> std::io::Result<Key> {
             }
         }
         0x01..=0x1a => Key::Unknown, // other ctrl chars
-        b if b >= b' ' && b <= b'~' => Key::Char(b as char),
+        b if (b' '..=b'~').contains(&b) => Key::Char(b as char),
         _ => Key::Unknown,
     })
 }
@@ -236,10 +236,9 @@ impl FormSection {
             ref options,
             ..
         } = self.content
+            && idx < options.len()
         {
-            if idx < options.len() {
-                *selected = Some(idx);
-            }
+            *selected = Some(idx);
         }
         self
     }
@@ -546,13 +545,13 @@ impl FormState {
                     Err(_) => return Some("must be a number between 1 and 65535".into()),
                 }
             }
-            if let Ok(lp) = fields[0].buffer.trim().parse::<u16>() {
-                if self.used_ports.contains(&lp) {
-                    return Some(format!(
-                        "port {} is already used by another tunnel",
-                        lp
-                    ));
-                }
+            if let Ok(lp) = fields[0].buffer.trim().parse::<u16>()
+                && self.used_ports.contains(&lp)
+            {
+                return Some(format!(
+                    "port {} is already used by another tunnel",
+                    lp
+                ));
             }
         }
         None
@@ -668,8 +667,7 @@ fn render(state: &FormState, fd: i32) -> Result<()> {
                 ));
             }
 
-            for oi in start..end {
-                let opt = &options[oi];
+            for (oi, opt) in options.iter().enumerate().take(end).skip(start) {
                 let at_cursor = !state.on_confirm && state.item == oi;
                 let is_selected = *selected == Some(oi);
 
@@ -1055,25 +1053,8 @@ struct SshChoices {
     remote_hosts: Vec<String>,
 }
 
-impl SshChoices {
-    fn empty() -> Self {
-        Self {
-            hosts: vec![],
-            users: vec![],
-            identity_files: vec![],
-            proxy_jumps: vec![],
-            remote_hosts: vec![],
-        }
-    }
-}
-
-fn gather_choices() -> SshChoices {
-    let home = match dirs::home_dir() {
-        Some(h) => h,
-        None => return SshChoices::empty(),
-    };
-    let ssh_dir = home.join(".ssh");
-    let config_path = ssh_dir.join("config");
+fn gather_choices(root: &ssh_config::ConfigRoot) -> SshChoices {
+    let ssh_dir = &root.ssh_dir;
 
     let mut hosts = Vec::new();
     let mut users = BTreeSet::new();
@@ -1082,18 +1063,14 @@ fn gather_choices() -> SshChoices {
     let mut host_aliases = BTreeSet::new();
     let mut remote_hosts = BTreeSet::new();
 
-    if let Ok(content) = fs::read_to_string(&config_path) {
+    if let Ok(set) = ssh_config::load(root) {
         let mut cur_alias: Option<String> = None;
         let mut cur_hostname: Option<String> = None;
 
-        for line in content.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') {
-                continue;
-            }
-            let (key, value) = match parse_kv(line) {
-                Some(p) => p,
-                None => continue,
+        for line in &set.lines {
+            let (key, value) = match &line.kind {
+                ssh_config::LineKind::Directive { key, value } => (key.as_str(), value.as_str()),
+                _ => continue,
             };
             match key.to_lowercase().as_str() {
                 "host" => {
@@ -1111,10 +1088,8 @@ fn gather_choices() -> SshChoices {
                         cur_alias = Some(name.to_string());
                     }
                 }
-                "hostname" => {
-                    if cur_alias.is_some() {
-                        cur_hostname = Some(value.to_string());
-                    }
+                "hostname" if cur_alias.is_some() => {
+                    cur_hostname = Some(value.to_string());
                 }
                 "user" => {
                     users.insert(value.to_string());
@@ -1126,14 +1101,11 @@ fn gather_choices() -> SshChoices {
                     proxy_jumps.insert(value.to_string());
                 }
                 "localforward" => {
-                    let parts: Vec<&str> = value.split_whitespace().collect();
-                    if parts.len() == 2 {
-                        if let Some(c) = parts[1].rfind(':') {
-                            let rh = &parts[1][..c];
-                            if rh != "localhost" {
-                                remote_hosts.insert(rh.to_string());
-                            }
-                        }
+                    if let Some(fwd) = ssh_config::parse_local_forward(value)
+                        && let Endpoint::Tcp { host: Some(rh), .. } = fwd.target
+                        && rh != "localhost"
+                    {
+                        remote_hosts.insert(rh);
                     }
                 }
                 _ => {}
@@ -1150,18 +1122,15 @@ fn gather_choices() -> SshChoices {
         }
     }
 
-    if let Ok(entries) = fs::read_dir(&ssh_dir) {
+    if let Ok(entries) = fs::read_dir(ssh_dir) {
         for entry in entries.flatten() {
             let path = entry.path();
-            if path.is_file() {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    if name.ends_with(".pub") {
-                        let priv_name = &name[..name.len() - 4];
-                        if ssh_dir.join(priv_name).is_file() {
-                            identity_files.insert(format!("~/.ssh/{}", priv_name));
-                        }
-                    }
-                }
+            if path.is_file()
+                && let Some(name) = path.file_name().and_then(|n| n.to_str())
+                && let Some(priv_name) = name.strip_suffix(".pub")
+                && ssh_dir.join(priv_name).is_file()
+            {
+                identity_files.insert(format!("~/.ssh/{}", priv_name));
             }
         }
     }
@@ -1213,24 +1182,6 @@ fn tty_write(fd: i32, data: &str) {
     }
 }
 
-fn parse_kv(line: &str) -> Option<(&str, &str)> {
-    let line = line.trim();
-    if let Some(eq) = line.find('=') {
-        let k = line[..eq].trim();
-        let v = line[eq + 1..].trim();
-        if !k.is_empty() && !v.is_empty() {
-            return Some((k, v));
-        }
-    }
-    let mut parts = line.splitn(2, char::is_whitespace);
-    let k = parts.next()?;
-    let v = parts.next()?.trim();
-    if v.is_empty() {
-        return None;
-    }
-    Some((k, v))
-}
-
 // ─── Main wizard ─────────────────────────────────────────────
 
 #[derive(Clone, Copy, PartialEq)]
@@ -1240,19 +1191,14 @@ enum ForwardType {
     Dynamic,
 }
 
-pub fn cmd_add() -> Result<()> {
-    let tunnels = ssh_config::discover_tunnels().unwrap_or_default();
-    let choices = gather_choices();
+pub fn cmd_add(root: &ssh_config::ConfigRoot) -> Result<()> {
+    let tunnels = ssh_config::discover_tunnels(root).unwrap_or_default();
+    let choices = gather_choices(root);
 
     let existing_names: Vec<String> = tunnels.iter().map(|t| t.name.clone()).collect();
     let used_ports: Vec<u16> = tunnels
         .iter()
-        .flat_map(|t| {
-            t.forwards
-                .iter()
-                .map(|f| f.local_port)
-                .chain(t.dynamic_forwards.iter().map(|f| f.listen_port))
-        })
+        .flat_map(|t| t.local_listeners().filter_map(|e| e.port()))
         .collect();
 
     // ── Ask forward type ──
@@ -1323,19 +1269,18 @@ pub fn cmd_add() -> Result<()> {
         }
         user_sec = user_sec.choice(u, u);
     }
-    if !has_current {
-        if let TabContent::Selection {
+    if !has_current
+        && let TabContent::Selection {
             ref mut options, ..
         } = user_sec.content
-        {
-            options.insert(
-                0,
-                FormOption {
-                    label: default_user.clone(),
-                    kind: OptionKind::Choice(default_user),
-                },
-            );
-        }
+    {
+        options.insert(
+            0,
+            FormOption {
+                label: default_user.clone(),
+                kind: OptionKind::Choice(default_user),
+            },
+        );
     }
     user_sec = user_sec.manual();
     sections.push(user_sec);
@@ -1445,9 +1390,7 @@ pub fn cmd_add() -> Result<()> {
     let proxy_jump = state.sections[5].value();
 
     // ── Build config block ──
-    let mut block = format!(
-        "\n\n# Tunnel: {name}\nHost {name}\n"
-    );
+    let mut block = format!("# Tunnel: {name}\nHost {name}\n");
     if let Some(ref g) = group {
         block.push_str(&format!("  # mole:group={g}\n"));
     }
@@ -1509,29 +1452,19 @@ pub fn cmd_add() -> Result<()> {
     block.push_str("  RequestTTY no\n  ExitOnForwardFailure yes\n");
 
     // ── Preview + Write ──
-    println!("\n  Will add to ~/.ssh/config:\n");
+    println!("\n  Will add to {}:\n", root.path.display());
     for line in block.lines() {
         println!("  {line}");
     }
     println!();
 
-    let config_path = dirs::home_dir()
-        .context("cannot determine home directory")?
-        .join(".ssh")
-        .join("config");
-
-    let mut file = OpenOptions::new()
-        .append(true)
-        .open(&config_path)
-        .with_context(|| format!("failed to open {}", config_path.display()))?;
-
-    file.write_all(block.as_bytes())
-        .with_context(|| format!("failed to write to {}", config_path.display()))?;
+    let config_path = ssh_config::append_host_block(root, &block)?;
 
     println!(
-        "  {} Tunnel '{}' added to ~/.ssh/config",
+        "  {} Tunnel '{}' added to {}",
         "✓".green(),
-        name
+        name,
+        config_path.display()
     );
 
     Ok(())
@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::persist::{self, AutoStart};
+use crate::process;
+use crate::ssh_config::ConfigRoot;
+use crate::tunnel::TunnelHost;
+
+/// `.desktop` entries in `$XDG_CONFIG_HOME/autostart`, run by the desktop
+/// session at graphical login.
+pub struct XdgAutostart;
+
+impl AutoStart for XdgAutostart {
+    fn name(&self) -> &'static str {
+        "xdg-autostart"
+    }
+
+    /// Only desktop sessions read autostart entries.
+    fn is_available(&self) -> bool {
+        !cfg!(target_os = "macos")
+            && ["XDG_CURRENT_DESKTOP", "DESKTOP_SESSION"]
+                .iter()
+                .any(|var| std::env::var_os(var).is_some_and(|v| !v.is_empty()))
+    }
+
+    fn is_enabled(&self, name: &str) -> bool {
+        entry_path(name).is_ok_and(|p| p.exists())
+    }
+
+    fn enable(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<()> {
+        let dir = autostart_dir()?;
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(file_name(&tunnel.name));
+        fs::write(&path, self.render(tunnel, root, cfg)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn disable(&self, name: &str) -> Result<()> {
+        let path = entry_path(name)?;
+        if !path.exists() {
+            anyhow::bail!("tunnel '{}' is not enabled for auto-start", name);
+        }
+        fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))
+    }
+
+    fn render(&self, tunnel: &TunnelHost, root: &ConfigRoot, cfg: &Config) -> Result<String> {
+        let log_path = process::log_file(&tunnel.name)?;
+        let spec = process::launch_spec(tunnel, root, cfg)?;
+        let program = process::which(&spec.program)?;
+        Ok(entry(&tunnel.name, &persist::shell_command(&program, &spec, &log_path)))
+    }
+
+    fn installed(&self, name: &str) -> Option<String> {
+        fs::read_to_string(entry_path(name).ok()?).ok()
+    }
+}
+
+fn autostart_dir() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .context("cannot determine config directory")?
+        .join("autostart"))
+}
+
+fn file_name(name: &str) -> String {
+    format!("mole-{}.desktop", name)
+}
+
+fn entry_path(name: &str) -> Result<PathBuf> {
+    Ok(autostart_dir()?.join(file_name(name)))
+}
+
+/// The desktop entry running `command` with `sh`; entries can't set
+/// environment or redirect output themselves.
+fn entry(name: &str, command: &str) -> String {
+    format!(
+        "[Desktop Entry]
+Type=Application
+Name=mole tunnel {name}
+Exec=/bin/sh -c {command}
+Terminal=false
+NoDisplay=true
+X-GNOME-Autostart-enabled=true
+",
+        name = name,
+        command = exec_arg(command),
+    )
+}
+
+/// Quote one `Exec=` argument: a double-quoted word with `"`, `` ` ``, `$`
+/// and `\` escaped, then escaped again as a string value, with `%` doubled
+/// so it isn't read as a field code.
+fn exec_arg(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if "\"`$\\".contains(c) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted.replace('\\', "\\\\").replace('%', "%%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_runs_the_command_with_sh() {
+        let entry = entry("db", "exec /usr/bin/autossh -N db >/dev/null 2>>/tmp/db.log");
+        assert!(entry.starts_with("[Desktop Entry]\nType=Application\nName=mole tunnel db\n"));
+        assert!(entry.contains(
+            "\nExec=/bin/sh -c \"exec /usr/bin/autossh -N db >/dev/null 2>>/tmp/db.log\"\n"
+        ));
+    }
+
+    #[test]
+    fn escapes_exec_arguments() {
+        assert_eq!(exec_arg("nc %h %p"), "\"nc %%h %%p\"");
+        assert_eq!(exec_arg("say \"$HOME\""), "\"say \\\\\"\\\\$HOME\\\\\"\"");
+        assert_eq!(exec_arg("'it'\\''s'"), "\"'it'\\\\\\\\''s'\"");
+    }
+}